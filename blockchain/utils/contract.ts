@@ -1,5 +1,17 @@
 import { Forwarder } from "../typechain-types";
 
+// Gas `Forwarder.execute` spends besides the inner call (see `rust/src/relayer/gas.rs`).
+const EXECUTE_OVERHEAD = 60000n;
+
+// The intrinsic gas of a call transaction with `data`.
+function intrinsicGas(data: string): bigint {
+  let gas = 21000n;
+  for (let i = 2; i < data.length; i += 2) {
+    gas += data.slice(i, i + 2) === "00" ? 4n : 16n;
+  }
+  return gas;
+}
+
 export async function sendMetaTransaction(
   ethers: any,
   txnData: any,
@@ -17,30 +29,34 @@ export async function sendMetaTransaction(
     await metaTransactionSigner.getAddress()
   );
 
-  const gasLimit = await ethers.provider.estimateGas({
-    ...txnData,
-    from: await metaTransactionSigner.getAddress(),
-  });
+  // Estimate the gas of the inner call as the forwarder makes it: from the forwarder, with the
+  // 20-byte sender appended to the calldata. A CALL does not pay the transaction's intrinsic gas.
+  const forwardedData = ethers.concat([
+    txnData.data,
+    await metaTransactionSigner.getAddress(),
+  ]);
+  const innerGas =
+    (await ethers.provider.estimateGas({
+      from: await forwarderContractWithFundedWallet.getAddress(),
+      to: recipientContractAddress,
+      data: forwardedData,
+    })) - intrinsicGas(forwardedData);
 
   // Construct the EIP-2771 request
   const metaTxn = {
     from: await metaTransactionSigner.getAddress(),
     to: recipientContractAddress,
     value: "0",
-    gas: 30000,
+    gas: innerGas.toString(),
     nonce: nonce.toString(),
     data: txnData.data,
   };
 
-  // Create domain for EIP-712 signing, which needs to match the forwarder contract
-  const domain = {
-    name: "GSNv2 Forwarder",
-    version: "0.0.1",
-    chainId: await ethers.provider
-      .getNetwork()
-      .then((network: any) => network.chainId),
-    verifyingContract: await forwarderContractWithFundedWallet.getAddress(),
-  };
+  // Read the EIP-712 domain from the forwarder contract itself (EIP-5267), so that it always
+  // matches the deployment
+  const [, name, version, chainId, verifyingContract] =
+    await forwarderContractWithFundedWallet.eip712Domain();
+  const domain = { name, version, chainId, verifyingContract };
 
   const types = {
     ForwardRequest: [
@@ -60,10 +76,22 @@ export async function sendMetaTransaction(
     metaTxn
   );
 
-  // Execute the txn
+  // Execute the txn. The CALL only forwards 63/64 of the remaining gas and `execute` asserts
+  // `gasleft() > req.gas / 63` afterwards, so leave `gas * 64 / 63` for it on top of the
+  // forwarder's own overhead.
+  const executeData = forwarderContractWithFundedWallet.interface.encodeFunctionData(
+    "execute",
+    [metaTxn, signature]
+  );
   const tx = await forwarderContractWithFundedWallet.execute(
     metaTxn,
-    signature
+    signature,
+    {
+      gasLimit:
+        (innerGas * 64n + 62n) / 63n +
+        intrinsicGas(executeData) +
+        EXECUTE_OVERHEAD,
+    }
   );
 
   // Now that the meta-txn has been executed, we need to assert that the metaTransactionSigner
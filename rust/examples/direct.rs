@@ -1,37 +1,21 @@
-use ethers::{
-    middleware::SignerMiddleware,
-    prelude::*,
-    providers::{Http, Provider},
-    signers::{LocalWallet, Signer},
+use counter_client::{
+    config::{self, ProfileConfig},
+    relayer::abi::CounterByAddress,
 };
+use ethers::middleware::SignerMiddleware;
 use eyre::Result;
 use std::sync::Arc;
 
-// Generate the type-safe contract bindings using the JSON ABI
-abigen!(
-    CounterByAddress,
-    "../blockchain/artifacts/contracts/CounterByAddress.sol/CounterByAddress.json"
-);
-
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Load private key from environment variable
-    let private_key =
-        "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string();
-    let wallet = private_key.parse::<LocalWallet>()?;
-
-    // Connect to the network (using local hardhat node by default)
-    let provider = Provider::<Http>::try_from("http://localhost:8545")?;
-    let chain_id = provider.get_chainid().await?;
-    let wallet = wallet.with_chain_id(chain_id.as_u64());
+    // Load the network, contract addresses and wallet from the default profile
+    let profile = config::load(None, None, ProfileConfig::default()).await?;
 
     // Create a client
-    let client = SignerMiddleware::new(provider, wallet);
+    let client = SignerMiddleware::new(profile.provider.clone(), profile.gas_wallet()?);
     let client = Arc::new(client);
 
-    // Contract address - replace with your deployed contract address
-    let contract_address = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512".parse::<Address>()?;
-    let contract = CounterByAddress::new(contract_address, client.clone());
+    let contract = CounterByAddress::new(profile.counter()?, client.clone());
 
     // Send the increment transaction
     println!("Sending increment transaction...");
@@ -0,0 +1,58 @@
+use alloy::{
+    network::EthereumWallet,
+    providers::{ProviderBuilder, ProviderLayer},
+    signers::local::PrivateKeySigner,
+};
+use counter_client::{
+    config::{self, ProfileConfig},
+    identity::IdentityStore,
+    relayer::{alloy_structs::CounterByAddress, convert, EIP2771GasRelayerLayer},
+};
+use eyre::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Load the network, contract addresses and gas wallet from the default profile
+    let profile = config::load(None, None, ProfileConfig::default()).await?;
+    let counter_address = convert::to_alloy_address(profile.counter()?);
+    let forwarder_address = convert::to_alloy_address(profile.forwarder()?);
+
+    // Reuse the `meta-example` identity, creating it on the first run. This wallet has no funds
+    // and will be the transaction signer.
+    let meta_wallet = IdentityStore::open_default()?
+        .get_or_create("meta-example")?
+        .wallet()?;
+    let meta_signer = PrivateKeySigner::from_signing_key(meta_wallet.signer().clone());
+
+    // The gas wallet signs and pays for the outer `Forwarder.execute` transaction
+    let gas_signer = PrivateKeySigner::from_signing_key(profile.gas_wallet()?.signer().clone());
+    let gas_provider = ProviderBuilder::new()
+        .wallet(EthereumWallet::from(gas_signer))
+        .on_http(profile.rpc_url.parse()?);
+
+    let meta_provider =
+        EIP2771GasRelayerLayer::new(meta_signer.clone(), forwarder_address).layer(gas_provider);
+    let counter = CounterByAddress::new(counter_address, meta_provider);
+
+    let meta_wallet_counter = counter.getCounter(meta_signer.address()).call().await?._0;
+    println!(
+        "Counter value for {}: {}",
+        meta_signer.address(),
+        meta_wallet_counter
+    );
+
+    println!("Sending transaction to counter");
+    let tx = counter.increment().send().await?;
+    println!("Transaction sent! Waiting for confirmation...");
+    let receipt = tx.get_receipt().await?;
+    println!("Transaction confirmed: {:?}", receipt);
+
+    let meta_wallet_counter = counter.getCounter(meta_signer.address()).call().await?._0;
+    println!(
+        "Counter value for {}: {}",
+        meta_signer.address(),
+        meta_wallet_counter
+    );
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use counter_client::{
+    config::{self, ProfileConfig},
+    identity::IdentityStore,
+    relayer::{abi, EIP2771GasRelayerMiddleware},
+};
+use ethers::{
+    middleware::{NonceManagerMiddleware, SignerMiddleware},
+    signers::{LocalWallet, Signer as EthersSigner},
+};
+use eyre::Result;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+const INCREMENTS: u64 = 50;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Load the network, contract addresses and gas wallet from the default profile
+    let profile = config::load(None, None, ProfileConfig::default()).await?;
+    let provider = profile.provider.clone();
+
+    // Reuse the `meta-example` identity, creating it on the first run. This wallet has no funds
+    // and will be the transaction signer.
+    let meta_wallet: LocalWallet = IdentityStore::open_default()?
+        .get_or_create("meta-example")?
+        .wallet()?;
+    let meta_address = meta_wallet.address();
+
+    let counter_address = profile.counter()?;
+    let counter_read = abi::CounterByAddress::new(counter_address, Arc::new(provider.clone()));
+    let before = counter_read.get_counter(meta_address).call().await?;
+    println!("Counter value for {}: {}", meta_address, before);
+
+    let forwarder_address = profile.forwarder()?;
+
+    // The gas wallet sends every meta-transaction, so its own nonce has to be managed locally too.
+    let forwarder_with_gas_signer = {
+        let gas_wallet = profile.gas_wallet()?;
+        let gas_address = gas_wallet.address();
+        let gas_client = SignerMiddleware::new(provider.clone(), gas_wallet);
+        let gas_client = NonceManagerMiddleware::new(gas_client, gas_address);
+
+        abi::forwarder::Forwarder::new(forwarder_address, Arc::new(gas_client))
+    };
+
+    let meta_client = {
+        let meta_client = SignerMiddleware::new(provider.clone(), meta_wallet.clone());
+        let meta_client = NonceManagerMiddleware::new(meta_client, meta_address);
+
+        Arc::new(EIP2771GasRelayerMiddleware::new(
+            meta_client,
+            meta_wallet,
+            forwarder_with_gas_signer,
+        ))
+    };
+
+    let counter_write = abi::CounterByAddress::new(counter_address, meta_client.clone());
+
+    // Each send reserves the next forwarder nonce, so all of them can be in flight at once. A
+    // dropped or reverted meta-transaction makes the middleware read the nonce again.
+    println!("Sending {INCREMENTS} increments in parallel");
+    let mut sends = JoinSet::new();
+    for i in 0..INCREMENTS {
+        let counter_write = counter_write.clone();
+        sends.spawn(async move {
+            let fn_call = counter_write.increment();
+            let receipt = fn_call.send().await?.await?;
+            Ok::<_, eyre::Report>((i, receipt))
+        });
+    }
+
+    let mut failed = 0;
+    while let Some(result) = sends.join_next().await {
+        match result? {
+            Ok((i, Some(receipt))) if receipt.status == Some(1.into()) => {
+                println!(
+                    "Increment {i} confirmed in block {:?}",
+                    receipt.block_number
+                );
+            }
+            Ok((i, receipt)) => {
+                println!("Increment {i} dropped or reverted: {:?}", receipt);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("Increment failed: {:?}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let after = counter_read.get_counter(meta_address).call().await?;
+    println!(
+        "Counter value for {}: {} ({} landed, {} failed)",
+        meta_address,
+        after,
+        after - before,
+        failed
+    );
+
+    Ok(())
+}
@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+# Refreshes the vendored contract artifacts in `rust/abi` from a Hardhat build.
+#
+# Usage: ./scripts/sync-abi.sh   (from the `rust` directory)
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+(cd ../blockchain && npx hardhat compile)
+
+for contract in Forwarder CounterByAddress; do
+    cp "../blockchain/artifacts/contracts/${contract}.sol/${contract}.json" "abi/${contract}.json"
+done
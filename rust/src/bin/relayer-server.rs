@@ -0,0 +1,171 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser;
+use counter_client::{
+    config::{self, ProfileConfig},
+    relayer::{
+        abi, Allowlist, BalanceMonitor, Budget, GasWalletPool, PoolEvent, RateLimiter, RelayQueue,
+        Relayer,
+    },
+    server::RelayServer,
+};
+use ethers::middleware::SignerMiddleware;
+use eyre::Result;
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::broadcast,
+};
+
+/// JSON-RPC relayer that pays for `ForwardRequest`s signed by its clients with the profile's gas
+/// wallet.
+///
+/// Only the calls the profile's `allowlist` allows are relayed, and none without one, unless the
+/// profile sets `allow_any = true`. Send the process `SIGHUP` to reload both from the config file.
+/// If it has a `budget`, the gas spent on
+/// each meta signer is recorded in the budget's ledger, and signers over budget are refused. Its
+/// `rate_limits` limit how many requests each meta signer and client IP may send.
+///
+/// With a `relay_queue` file, accepted requests survive restarts: on startup, the server follows
+/// the `execute` transactions that were pending and resubmits the requests that were not sent.
+///
+/// With `gas_wallets`, requests are paid for from those wallets and `gas_wallet` in turn, as set
+/// in the `gas_pool` table. With a `min_balance_wei`, wallets below it are taken out of rotation,
+/// reported on stderr and topped up from `treasury_wallet` if the pool has a `top_up_wei`.
+#[derive(Debug, Parser)]
+#[command(name = "relayer-server", version)]
+struct Cli {
+    /// Config file [default: counter-client.toml].
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Profile in the config file [default: the file's `default_profile`].
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Address to serve JSON-RPC on.
+    #[arg(long, default_value = "127.0.0.1:8546")]
+    listen: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let profile = config::load(
+        cli.config.as_deref(),
+        cli.profile.as_deref(),
+        ProfileConfig::default(),
+    )
+    .await?;
+
+    let gas_client = |wallet| Arc::new(SignerMiddleware::new(profile.provider.clone(), wallet));
+    let forwarder = profile.forwarder()?;
+    let mut gas_wallets = profile.gas_wallets()?;
+    if profile.gas_wallet.is_some() || gas_wallets.is_empty() {
+        // Without any gas wallet, this fails naming the missing `gas_wallet`.
+        gas_wallets.insert(0, profile.gas_wallet()?);
+    }
+    let forwarders: Vec<_> = gas_wallets
+        .into_iter()
+        .map(|wallet| abi::Forwarder::new(forwarder, gas_client(wallet)))
+        .collect();
+
+    let mut relayer = Relayer::new(forwarders[0].clone());
+    if forwarders.len() > 1 || profile.gas_pool.is_some() {
+        let config = profile.gas_pool.clone().unwrap_or_default();
+        let pool = GasWalletPool::new(forwarders, config.selection)?;
+        if let Some(min_balance) = config.min_balance_wei {
+            let mut monitor = BalanceMonitor::new(pool.clone(), min_balance)
+                .with_interval(Duration::from_secs(config.check_interval_secs));
+            if let (Some(treasury), Some(top_up)) = (profile.treasury_wallet()?, config.top_up_wei)
+            {
+                monitor = monitor.with_treasury(gas_client(treasury), top_up);
+            }
+            tokio::spawn(report_pool_events(pool.subscribe()));
+            monitor.spawn();
+        }
+        println!("Paying from {} gas wallets", pool.wallets().len());
+        relayer = relayer.with_gas_wallets(pool);
+    }
+    if let Some(fees) = &profile.fees {
+        relayer = relayer.with_fee_strategy(fees.clone());
+    }
+    if let Some(escalation) = &profile.escalation {
+        relayer = relayer.with_escalation(escalation.clone());
+    }
+    if let Some(transaction_type) = profile.transaction_type {
+        relayer = relayer.with_transaction_type(transaction_type);
+    }
+    let allowlist = Allowlist::new(profile.allowlist.clone().unwrap_or_default());
+    allowlist.set_allow_any(profile.allow_any);
+    if profile.allow_any {
+        println!("Relaying any call: the profile sets allow_any");
+    } else if allowlist.rules().is_empty() {
+        eprintln!("The allowlist is empty: every request is rejected until rules are loaded");
+    }
+    relayer = relayer.with_allowlist(allowlist.clone());
+    tokio::spawn(reload_allowlist(
+        cli.config.clone(),
+        profile.name.clone(),
+        allowlist,
+    ));
+    if let Some(budget) = &profile.budget {
+        relayer = relayer.with_budget(Budget::open(budget)?);
+    }
+
+    let listener = TcpListener::bind(cli.listen).await?;
+    println!("Relaying for profile {} on {}", profile.name, cli.listen);
+    let mut server = RelayServer::new(relayer);
+    if let Some(rate_limits) = profile.rate_limits.clone() {
+        server = server.with_rate_limiter(RateLimiter::new(rate_limits));
+    }
+    if let Some(path) = &profile.relay_queue {
+        server = server.with_queue(RelayQueue::open(path)?);
+        let resumed = server.resume().await?;
+        println!("Resuming {resumed} requests from {}", path.display());
+    }
+    server.serve(listener).await?;
+    Ok(())
+}
+
+/// Replaces the allowlist's rules and `allow_any` with those in the config file on every `SIGHUP`.
+/// A config file that fails to parse leaves the allowlist as it is.
+async fn reload_allowlist(config: Option<PathBuf>, profile: String, allowlist: Allowlist) {
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        eprintln!("Cannot listen for SIGHUP; the allowlist will not be reloaded");
+        return;
+    };
+
+    while hangups.recv().await.is_some() {
+        match config::read_profile(config.as_deref(), Some(&profile)) {
+            Ok((_, profile)) => {
+                let rules = profile.allowlist.unwrap_or_default();
+                let allow_any = profile.allow_any.unwrap_or(false);
+                println!(
+                    "Reloaded the allowlist: {} rules{}",
+                    rules.len(),
+                    if allow_any { ", allowing any call" } else { "" }
+                );
+                allowlist.replace(rules);
+                allowlist.set_allow_any(allow_any);
+            }
+            Err(e) => eprintln!("Keeping the allowlist: {e}"),
+        }
+    }
+}
+
+/// Prints the balance monitor's events, with the alerts on stderr.
+async fn report_pool_events(mut events: broadcast::Receiver<PoolEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event @ (PoolEvent::Restored { .. } | PoolEvent::ToppedUp { .. })) => {
+                println!("{event}")
+            }
+            Ok(event) => eprintln!("ALERT: {event}"),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                eprintln!("ALERT: missed {missed} gas wallet events")
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
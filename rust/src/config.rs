@@ -0,0 +1,641 @@
+//! Typed configuration for endpoints, contract addresses, chain id and signers.
+//!
+//! Configuration is read from a TOML file containing named profiles:
+//!
+//! ```toml
+//! default_profile = "local"
+//!
+//! [profiles.local]
+//! rpc_url = "http://localhost:8545"
+//! chain_id = 31337
+//! forwarder = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+//! counter = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+//! gas_wallet = { keystore = { path = "keys/gas.json", password_env = "GAS_KEYSTORE_PASSWORD" } }
+//! meta_wallet = { mnemonic = { phrase_env = "META_MNEMONIC", index = 3 } }
+//!
+//! [profiles.local.fees]
+//! reward_percentile = 60.0
+//! max_fee_per_gas = 50_000_000_000
+//!
+//! [profiles.local.escalation]
+//! interval_secs = 20
+//! ```
+//!
+//! Wallets are given as a private key, a Web3 Secret Storage keystore, a BIP-39 mnemonic and index,
+//! or the name of an identity in the local identity directory (see [`crate::identity`]). The
+//! optional `fees` and `escalation` tables configure how the gas wallet prices and rebroadcasts
+//! its `execute` transactions (see [`FeeStrategy`] and [`EscalationPolicy`]), and
+//! `transaction_type` sends them as `"legacy"` or `"eip2930"` transactions on chains without
+//! EIP-1559 (see [`OuterTransactionType`]). An `allowlist` array of tables lists the calls a
+//! relayer server pays for (see [`AllowRule`]); without one it pays for none, unless `allow_any`
+//! is set to relay any call. A `budget` table caps the gas it sponsors per meta
+//! signer (see [`BudgetConfig`]). A `rate_limits` table limits how often each meta signer and
+//! client may use a relayer server (see [`RateLimitConfig`]). `relay_queue` is the file in which a
+//! relayer server keeps the requests it accepted across restarts (see
+//! [`RelayQueue`](crate::relayer::RelayQueue)). A relayer server pays from `gas_wallets` as well
+//! as `gas_wallet`, and a `gas_pool` table sets how it picks among them and the minimum balance
+//! below which they are topped up from `treasury_wallet` (see [`PoolConfig`]).
+//!
+//! Every field of the selected profile can be overridden with a `COUNTER_CLIENT_*` environment
+//! variable (see [`ProfileConfig::from_env`]), and the result is validated by [`load`]: addresses
+//! must be well-formed, keys must parse, and the chain id must match the one reported by the RPC
+//! endpoint. Keystores and identities are only loaded when they are first used (see
+//! [`ProfileWallet`]), so commands that sign nothing never prompt for a keystore password.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    signers::{coins_bip39::English, LocalWallet, MnemonicBuilder, Signer},
+    types::Address,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    identity::IdentityStore,
+    relayer::{
+        AllowRule, BudgetConfig, EscalationPolicy, FeeStrategy, OuterTransactionType, PoolConfig,
+        RateLimitConfig,
+    },
+};
+
+/// The config file used when none is given explicitly.
+pub const DEFAULT_CONFIG_PATH: &str = "counter-client.toml";
+
+/// The profile used when none is given explicitly and the file has no `default_profile`.
+pub const DEFAULT_PROFILE: &str = "local";
+
+pub const CONFIG_ENV: &str = "COUNTER_CLIENT_CONFIG";
+pub const PROFILE_ENV: &str = "COUNTER_CLIENT_PROFILE";
+pub const RPC_URL_ENV: &str = "COUNTER_CLIENT_RPC_URL";
+pub const CHAIN_ID_ENV: &str = "COUNTER_CLIENT_CHAIN_ID";
+pub const FORWARDER_ENV: &str = "COUNTER_CLIENT_FORWARDER";
+pub const COUNTER_ENV: &str = "COUNTER_CLIENT_COUNTER";
+pub const GAS_PRIVATE_KEY_ENV: &str = "COUNTER_CLIENT_GAS_PRIVATE_KEY";
+pub const META_PRIVATE_KEY_ENV: &str = "COUNTER_CLIENT_META_PRIVATE_KEY";
+pub const META_IDENTITY_ENV: &str = "COUNTER_CLIENT_META_IDENTITY";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Failed to parse {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+
+    #[error("Unknown profile {0}")]
+    UnknownProfile(String),
+
+    #[error("Profile {profile} is missing {field}")]
+    MissingField {
+        profile: String,
+        field: &'static str,
+    },
+
+    #[error("Invalid {field}: {value} is not a valid address")]
+    InvalidAddress { field: &'static str, value: String },
+
+    #[error("Invalid {field}: {reason}")]
+    InvalidPrivateKey { field: &'static str, reason: String },
+
+    #[error("Failed to decrypt keystore {path}: {reason}")]
+    Keystore { path: PathBuf, reason: String },
+
+    #[error("Invalid {field} mnemonic: {reason}")]
+    InvalidMnemonic { field: &'static str, reason: String },
+
+    #[error("Failed to write {0}: {1}")]
+    Serialize(PathBuf, toml::ser::Error),
+
+    #[error("Unknown identity {0}")]
+    UnknownIdentity(String),
+
+    #[error("Identity {0} already exists")]
+    IdentityExists(String),
+
+    #[error("Invalid identity name {0}: use letters, digits, '-' and '_'")]
+    InvalidIdentityName(String),
+
+    #[error("Invalid {var}: {reason}")]
+    InvalidEnv { var: String, reason: String },
+
+    #[error("{0}")]
+    Rpc(String),
+
+    #[error("Chain ID mismatch: profile expects {configured}, RPC endpoint reports {reported}")]
+    ChainIdMismatch { configured: u64, reported: u64 },
+}
+
+/// The contents of a config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+impl ConfigFile {
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Returns the name of the profile to use when none is given explicitly.
+    pub fn default_profile(&self) -> &str {
+        self.default_profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+}
+
+/// An unvalidated profile, as written in the config file or the environment.
+///
+/// All fields are optional so that profiles can be layered: see [`ProfileConfig::merge`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub rpc_url: Option<String>,
+    pub chain_id: Option<u64>,
+    pub forwarder: Option<String>,
+    pub counter: Option<String>,
+    pub gas_wallet: Option<SignerConfig>,
+    pub gas_wallets: Option<Vec<SignerConfig>>,
+    pub treasury_wallet: Option<SignerConfig>,
+    pub meta_wallet: Option<SignerConfig>,
+    pub fees: Option<FeeStrategy>,
+    pub escalation: Option<EscalationPolicy>,
+    pub transaction_type: Option<OuterTransactionType>,
+    pub allowlist: Option<Vec<AllowRule>>,
+    pub allow_any: Option<bool>,
+    pub budget: Option<BudgetConfig>,
+    pub rate_limits: Option<RateLimitConfig>,
+    pub relay_queue: Option<PathBuf>,
+    pub gas_pool: Option<PoolConfig>,
+}
+
+/// Where a signer's key comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerConfig {
+    /// A hex-encoded private key.
+    PrivateKey(String),
+
+    /// The name of an environment variable holding a hex-encoded private key.
+    PrivateKeyEnv(String),
+
+    /// A Web3 Secret Storage JSON keystore. The password is read from `password_env` if it is
+    /// set, and prompted for otherwise.
+    Keystore {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password_env: Option<String>,
+    },
+
+    /// The key at `m/44'/60'/0'/0/{index}` of a BIP-39 mnemonic, given either inline or through
+    /// an environment variable.
+    Mnemonic {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        phrase: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        phrase_env: Option<String>,
+        #[serde(default)]
+        index: u32,
+    },
+
+    /// A named identity from the default identity directory.
+    Identity(String),
+}
+
+impl SignerConfig {
+    /// Loads the wallet, decrypting or deriving it if needed. `field` names the wallet in errors.
+    pub fn resolve(&self, field: &'static str) -> Result<LocalWallet, ConfigError> {
+        match self {
+            SignerConfig::PrivateKey(private_key) => parse_private_key(field, private_key),
+            SignerConfig::PrivateKeyEnv(var) => parse_private_key(field, &env_var(var)?),
+            SignerConfig::Keystore { path, password_env } => {
+                let password = match password_env.as_deref().map(std::env::var) {
+                    Some(Ok(password)) => password,
+                    _ => rpassword::prompt_password(format!(
+                        "Password for {field} keystore {}: ",
+                        path.display()
+                    ))
+                    .map_err(|e| ConfigError::Keystore {
+                        path: path.clone(),
+                        reason: e.to_string(),
+                    })?,
+                };
+
+                LocalWallet::decrypt_keystore(path, password).map_err(|e| ConfigError::Keystore {
+                    path: path.clone(),
+                    reason: e.to_string(),
+                })
+            }
+            SignerConfig::Mnemonic {
+                phrase,
+                phrase_env,
+                index,
+            } => {
+                let phrase = match (phrase, phrase_env) {
+                    (Some(phrase), _) => phrase.clone(),
+                    (None, Some(var)) => env_var(var)?,
+                    (None, None) => {
+                        return Err(ConfigError::InvalidMnemonic {
+                            field,
+                            reason: "one of phrase or phrase_env is required".to_string(),
+                        })
+                    }
+                };
+                let invalid = |e: ethers::signers::WalletError| ConfigError::InvalidMnemonic {
+                    field,
+                    reason: e.to_string(),
+                };
+
+                MnemonicBuilder::<English>::default()
+                    .phrase(phrase.as_str())
+                    .index(*index)
+                    .map_err(invalid)?
+                    .build()
+                    .map_err(invalid)
+            }
+            SignerConfig::Identity(name) => IdentityStore::open_default()?.get(name)?.wallet(),
+        }
+    }
+}
+
+fn env_var(var: &str) -> Result<String, ConfigError> {
+    std::env::var(var).map_err(|e| ConfigError::InvalidEnv {
+        var: var.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn parse_private_key(field: &'static str, private_key: &str) -> Result<LocalWallet, ConfigError> {
+    private_key
+        .parse::<LocalWallet>()
+        .map_err(|e| ConfigError::InvalidPrivateKey {
+            field,
+            reason: e.to_string(),
+        })
+}
+
+impl ProfileConfig {
+    /// Reads overrides from the `COUNTER_CLIENT_*` environment variables.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let var = |name: &str| std::env::var(name).ok();
+
+        let chain_id = match var(CHAIN_ID_ENV) {
+            Some(chain_id) => {
+                Some(
+                    chain_id
+                        .parse::<u64>()
+                        .map_err(|e| ConfigError::InvalidEnv {
+                            var: CHAIN_ID_ENV.to_string(),
+                            reason: e.to_string(),
+                        })?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            rpc_url: var(RPC_URL_ENV),
+            chain_id,
+            forwarder: var(FORWARDER_ENV),
+            counter: var(COUNTER_ENV),
+            gas_wallet: var(GAS_PRIVATE_KEY_ENV).map(SignerConfig::PrivateKey),
+            gas_wallets: None,
+            treasury_wallet: None,
+            meta_wallet: var(META_PRIVATE_KEY_ENV)
+                .map(SignerConfig::PrivateKey)
+                .or_else(|| var(META_IDENTITY_ENV).map(SignerConfig::Identity)),
+            fees: None,
+            escalation: None,
+            transaction_type: None,
+            allowlist: None,
+            allow_any: None,
+            budget: None,
+            rate_limits: None,
+            relay_queue: None,
+            gas_pool: None,
+        })
+    }
+
+    /// Overrides the fields of `self` with those set in `other`.
+    pub fn merge(&mut self, other: ProfileConfig) {
+        let ProfileConfig {
+            rpc_url,
+            chain_id,
+            forwarder,
+            counter,
+            gas_wallet,
+            gas_wallets,
+            treasury_wallet,
+            meta_wallet,
+            fees,
+            escalation,
+            transaction_type,
+            allowlist,
+            allow_any,
+            budget,
+            rate_limits,
+            relay_queue,
+            gas_pool,
+        } = other;
+
+        self.rpc_url = rpc_url.or(self.rpc_url.take());
+        self.chain_id = chain_id.or(self.chain_id);
+        self.forwarder = forwarder.or(self.forwarder.take());
+        self.counter = counter.or(self.counter.take());
+        self.gas_wallet = gas_wallet.or(self.gas_wallet.take());
+        self.gas_wallets = gas_wallets.or(self.gas_wallets.take());
+        self.treasury_wallet = treasury_wallet.or(self.treasury_wallet.take());
+        self.meta_wallet = meta_wallet.or(self.meta_wallet.take());
+        self.fees = fees.or(self.fees.take());
+        self.escalation = escalation.or(self.escalation.take());
+        self.transaction_type = transaction_type.or(self.transaction_type);
+        self.allowlist = allowlist.or(self.allowlist.take());
+        self.allow_any = allow_any.or(self.allow_any);
+        self.budget = budget.or(self.budget.take());
+        self.rate_limits = rate_limits.or(self.rate_limits.take());
+        self.relay_queue = relay_queue.or(self.relay_queue.take());
+        self.gas_pool = gas_pool.or(self.gas_pool.take());
+    }
+
+    /// Validates the profile without contacting the RPC endpoint.
+    pub fn resolve(self, name: &str) -> Result<Profile, ConfigError> {
+        let rpc_url = self.rpc_url.ok_or(ConfigError::MissingField {
+            profile: name.to_string(),
+            field: "rpc_url",
+        })?;
+        let provider = Provider::<Http>::try_from(rpc_url.as_str())
+            .map_err(|e| ConfigError::Rpc(format!("Invalid rpc_url {rpc_url}: {e}")))?;
+
+        Ok(Profile {
+            name: name.to_string(),
+            rpc_url,
+            chain_id: self.chain_id,
+            forwarder: parse_address("forwarder", self.forwarder)?,
+            counter: parse_address("counter", self.counter)?,
+            gas_wallet: self
+                .gas_wallet
+                .map(|signer| ProfileWallet::new(signer, "gas_wallet"))
+                .transpose()?,
+            gas_wallets: self
+                .gas_wallets
+                .unwrap_or_default()
+                .into_iter()
+                .map(|signer| ProfileWallet::new(signer, "gas_wallets"))
+                .collect::<Result<_, _>>()?,
+            treasury_wallet: self
+                .treasury_wallet
+                .map(|signer| ProfileWallet::new(signer, "treasury_wallet"))
+                .transpose()?,
+            meta_wallet: self
+                .meta_wallet
+                .map(|signer| ProfileWallet::new(signer, "meta_wallet"))
+                .transpose()?,
+            fees: self.fees,
+            escalation: self.escalation,
+            transaction_type: self.transaction_type,
+            allowlist: self.allowlist,
+            allow_any: self.allow_any.unwrap_or(false),
+            budget: self.budget,
+            rate_limits: self.rate_limits,
+            relay_queue: self.relay_queue,
+            gas_pool: self.gas_pool,
+            provider,
+        })
+    }
+}
+
+fn parse_address(
+    field: &'static str,
+    value: Option<String>,
+) -> Result<Option<Address>, ConfigError> {
+    value
+        .map(|value| {
+            value
+                .parse::<Address>()
+                .map_err(|_| ConfigError::InvalidAddress { field, value })
+        })
+        .transpose()
+}
+
+/// A wallet of a profile. Private keys and mnemonics are parsed when the profile is resolved;
+/// keystores and identities are loaded the first time they are used, since decrypting a keystore
+/// may prompt for its password and an identity has to exist.
+///
+/// Clones share the loaded wallet.
+#[derive(Debug, Clone)]
+pub struct ProfileWallet {
+    signer: SignerConfig,
+    field: &'static str,
+    wallet: Arc<OnceLock<LocalWallet>>,
+}
+
+impl ProfileWallet {
+    /// Parses `signer` unless it is a keystore or an identity. `field` names the wallet in errors.
+    pub fn new(signer: SignerConfig, field: &'static str) -> Result<Self, ConfigError> {
+        let wallet = OnceLock::new();
+        if !matches!(
+            signer,
+            SignerConfig::Keystore { .. } | SignerConfig::Identity(_)
+        ) {
+            let _ = wallet.set(signer.resolve(field)?);
+        }
+        Ok(Self {
+            signer,
+            field,
+            wallet: Arc::new(wallet),
+        })
+    }
+
+    pub fn signer(&self) -> &SignerConfig {
+        &self.signer
+    }
+
+    /// Loads the wallet, or returns the one loaded before.
+    pub fn wallet(&self) -> Result<LocalWallet, ConfigError> {
+        if let Some(wallet) = self.wallet.get() {
+            return Ok(wallet.clone());
+        }
+        let wallet = self.signer.resolve(self.field)?;
+        Ok(self.wallet.get_or_init(|| wallet).clone())
+    }
+}
+
+/// A validated profile.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub rpc_url: String,
+    /// The chain id. After [`load`] this is always set to the one the RPC endpoint reports.
+    pub chain_id: Option<u64>,
+    pub forwarder: Option<Address>,
+    pub counter: Option<Address>,
+    pub gas_wallet: Option<ProfileWallet>,
+    /// More gas wallets for a relayer server, which pays from all of them and `gas_wallet`.
+    pub gas_wallets: Vec<ProfileWallet>,
+    /// Funds the gas wallets that fall below the pool's minimum balance.
+    pub treasury_wallet: Option<ProfileWallet>,
+    pub meta_wallet: Option<ProfileWallet>,
+    /// How the gas wallet prices `execute` transactions. `None` leaves it to the gas wallet's
+    /// client.
+    pub fees: Option<FeeStrategy>,
+    pub escalation: Option<EscalationPolicy>,
+    /// The type of the gas wallet's `execute` transactions. `None` sends EIP-1559 transactions.
+    pub transaction_type: Option<OuterTransactionType>,
+    /// The calls a relayer server pays for. `None` relays none, unless `allow_any` is set.
+    pub allowlist: Option<Vec<AllowRule>>,
+    /// Lets a relayer server relay any call, whatever the `allowlist` says.
+    pub allow_any: bool,
+    /// How much gas a relayer sponsors per meta signer. `None` sponsors any amount.
+    pub budget: Option<BudgetConfig>,
+    /// How often a relayer server accepts requests per meta signer and client. `None` accepts
+    /// any number.
+    pub rate_limits: Option<RateLimitConfig>,
+    /// Where a relayer server keeps the requests it accepted. `None` keeps them in memory.
+    pub relay_queue: Option<PathBuf>,
+    /// How a relayer server uses its gas wallets. `None` picks them in turn and does not check
+    /// their balances.
+    pub gas_pool: Option<PoolConfig>,
+    pub provider: Provider<Http>,
+}
+
+impl Profile {
+    /// Checks the configured chain id against the RPC endpoint, filling it in if unset. The
+    /// wallets are bound to it when they are loaded.
+    pub async fn verify_chain_id(&mut self) -> Result<u64, ConfigError> {
+        let reported = self
+            .provider
+            .get_chainid()
+            .await
+            .map_err(|e| ConfigError::Rpc(format!("Failed to get chain ID: {e}")))?
+            .as_u64();
+
+        if let Some(configured) = self.chain_id {
+            if configured != reported {
+                return Err(ConfigError::ChainIdMismatch {
+                    configured,
+                    reported,
+                });
+            }
+        }
+
+        self.chain_id = Some(reported);
+        Ok(reported)
+    }
+
+    pub fn chain_id(&self) -> Result<u64, ConfigError> {
+        self.chain_id.ok_or_else(|| self.missing("chain_id"))
+    }
+
+    pub fn forwarder(&self) -> Result<Address, ConfigError> {
+        self.forwarder.ok_or_else(|| self.missing("forwarder"))
+    }
+
+    pub fn counter(&self) -> Result<Address, ConfigError> {
+        self.counter.ok_or_else(|| self.missing("counter"))
+    }
+
+    pub fn gas_wallet(&self) -> Result<LocalWallet, ConfigError> {
+        let wallet = self
+            .gas_wallet
+            .as_ref()
+            .ok_or_else(|| self.missing("gas_wallet"))?;
+        self.load(wallet)
+    }
+
+    /// The `gas_wallets` besides `gas_wallet`.
+    pub fn gas_wallets(&self) -> Result<Vec<LocalWallet>, ConfigError> {
+        self.gas_wallets
+            .iter()
+            .map(|wallet| self.load(wallet))
+            .collect()
+    }
+
+    pub fn treasury_wallet(&self) -> Result<Option<LocalWallet>, ConfigError> {
+        self.treasury_wallet
+            .as_ref()
+            .map(|wallet| self.load(wallet))
+            .transpose()
+    }
+
+    pub fn meta_wallet(&self) -> Result<LocalWallet, ConfigError> {
+        let wallet = self
+            .meta_wallet
+            .as_ref()
+            .ok_or_else(|| self.missing("meta_wallet"))?;
+        self.load(wallet)
+    }
+
+    /// Loads `wallet`, bound to the profile's chain id if it is known.
+    fn load(&self, wallet: &ProfileWallet) -> Result<LocalWallet, ConfigError> {
+        let wallet = wallet.wallet()?;
+        Ok(match self.chain_id {
+            Some(chain_id) => wallet.with_chain_id(chain_id),
+            None => wallet,
+        })
+    }
+
+    fn missing(&self, field: &'static str) -> ConfigError {
+        ConfigError::MissingField {
+            profile: self.name.clone(),
+            field,
+        }
+    }
+}
+
+/// Loads and validates a profile.
+///
+/// The config file is `path`, else `$COUNTER_CLIENT_CONFIG`, else [`DEFAULT_CONFIG_PATH`] if it
+/// exists. The profile is `profile`, else `$COUNTER_CLIENT_PROFILE`, else the file's
+/// `default_profile`. Its fields are then overridden by the environment and finally by
+/// `overrides` (e.g. command-line flags).
+pub async fn load(
+    path: Option<&Path>,
+    profile: Option<&str>,
+    overrides: ProfileConfig,
+) -> Result<Profile, ConfigError> {
+    let (name, mut config) = read_profile(path, profile)?;
+    config.merge(ProfileConfig::from_env()?);
+    config.merge(overrides);
+
+    let mut profile = config.resolve(&name)?;
+    profile.verify_chain_id().await?;
+    Ok(profile)
+}
+
+/// Reads the profile [`load`] would select, as written in the config file: without environment
+/// overrides, validation or contacting the RPC endpoint. Returns its name and config.
+pub fn read_profile(
+    path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<(String, ProfileConfig), ConfigError> {
+    let path = path
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from));
+    let file = match path {
+        Some(path) => ConfigFile::from_path(&path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+            ConfigFile::from_path(Path::new(DEFAULT_CONFIG_PATH))?
+        }
+        None => ConfigFile::default(),
+    };
+
+    let name = profile
+        .map(str::to_string)
+        .or_else(|| std::env::var(PROFILE_ENV).ok())
+        .unwrap_or_else(|| file.default_profile().to_string());
+    let config = match file.profiles.get(&name) {
+        Some(config) => config.clone(),
+        None if file.profiles.is_empty() => ProfileConfig::default(),
+        None => return Err(ConfigError::UnknownProfile(name)),
+    };
+    Ok((name, config))
+}
@@ -0,0 +1,227 @@
+//! Named meta-transaction identities kept in a local directory.
+//!
+//! Each identity `<name>` is a `<name>.toml` file holding its address and a [`SignerConfig`]:
+//! either a keystore (`<name>.keystore.json`, next to it) or a reference to a mnemonic and index.
+//! Private keys and mnemonics are never written in the clear, so the same meta user, and its
+//! counter, can be reused across runs of the CLI and the examples.
+
+use std::path::{Path, PathBuf};
+
+use ethers::{
+    core::rand::thread_rng,
+    signers::{LocalWallet, Signer},
+    types::Address,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, SignerConfig};
+
+/// Overrides the identity directory.
+pub const IDENTITY_DIR_ENV: &str = "COUNTER_CLIENT_IDENTITY_DIR";
+
+/// The identity directory used when `$COUNTER_CLIENT_IDENTITY_DIR` is unset, relative to the
+/// home directory.
+pub const DEFAULT_IDENTITY_DIR: &str = ".counter-client/identities";
+
+/// The environment variable new keystore identities read their password from, if set.
+pub const KEYSTORE_PASSWORD_ENV: &str = "COUNTER_CLIENT_KEYSTORE_PASSWORD";
+
+/// A named meta-transaction identity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    #[serde(skip)]
+    pub name: String,
+    pub address: Address,
+    pub signer: SignerConfig,
+}
+
+impl Identity {
+    /// Loads the identity's wallet, prompting for the keystore password if needed.
+    pub fn wallet(&self) -> Result<LocalWallet, ConfigError> {
+        self.signer.resolve("identity")
+    }
+}
+
+/// A directory of [`Identity`] files.
+#[derive(Debug, Clone)]
+pub struct IdentityStore {
+    dir: PathBuf,
+}
+
+impl IdentityStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Opens `$COUNTER_CLIENT_IDENTITY_DIR`, else [`DEFAULT_IDENTITY_DIR`] in the home directory.
+    pub fn open_default() -> Result<Self, ConfigError> {
+        if let Some(dir) = std::env::var_os(IDENTITY_DIR_ENV) {
+            return Ok(Self::new(dir));
+        }
+
+        let home = std::env::home_dir().ok_or_else(|| ConfigError::InvalidEnv {
+            var: IDENTITY_DIR_ENV.to_string(),
+            reason: "not set and no home directory found".to_string(),
+        })?;
+        Ok(Self::new(home.join(DEFAULT_IDENTITY_DIR)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns every identity in the directory, sorted by name.
+    pub fn list(&self) -> Result<Vec<Identity>, ConfigError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(ConfigError::Io(self.dir.clone(), e)),
+        };
+
+        let mut identities = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|e| ConfigError::Io(self.dir.clone(), e))?
+                .path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    identities.push(self.get(name)?);
+                }
+            }
+        }
+        identities.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(identities)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Identity, ConfigError> {
+        let path = self.path(name)?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ConfigError::UnknownIdentity(name.to_string()))
+            }
+            Err(e) => return Err(ConfigError::Io(path, e)),
+        };
+
+        let mut identity: Identity =
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(path, e))?;
+        identity.name = name.to_string();
+        // Keystores are stored next to the identity file.
+        if let SignerConfig::Keystore { path, .. } = &mut identity.signer {
+            *path = self.dir.join(&*path);
+        }
+        Ok(identity)
+    }
+
+    /// Creates an identity with a new random key, encrypted with `password`.
+    pub fn create_keystore(&self, name: &str, password: &str) -> Result<Identity, ConfigError> {
+        self.check_new(name)?;
+
+        let file_name = format!("{name}.keystore.json");
+        let (wallet, _) =
+            LocalWallet::new_keystore(&self.dir, &mut thread_rng(), password, Some(&file_name))
+                .map_err(|e| ConfigError::Keystore {
+                    path: self.dir.join(&file_name),
+                    reason: e.to_string(),
+                })?;
+
+        self.write(Identity {
+            name: name.to_string(),
+            address: wallet.address(),
+            signer: SignerConfig::Keystore {
+                path: PathBuf::from(file_name),
+                password_env: Some(KEYSTORE_PASSWORD_ENV.to_string()),
+            },
+        })
+    }
+
+    /// Creates an identity for the key at `index` of the mnemonic in the environment variable
+    /// `phrase_env`. Only the variable's name is stored.
+    pub fn create_mnemonic(
+        &self,
+        name: &str,
+        phrase_env: &str,
+        index: u32,
+    ) -> Result<Identity, ConfigError> {
+        self.check_new(name)?;
+
+        let signer = SignerConfig::Mnemonic {
+            phrase: None,
+            phrase_env: Some(phrase_env.to_string()),
+            index,
+        };
+        let address = signer.resolve("identity")?.address();
+
+        self.write(Identity {
+            name: name.to_string(),
+            address,
+            signer,
+        })
+    }
+
+    /// Returns the identity `name`, creating it with a new keystore if it does not exist yet.
+    /// The password is read from `$COUNTER_CLIENT_KEYSTORE_PASSWORD`, or prompted for.
+    pub fn get_or_create(&self, name: &str) -> Result<Identity, ConfigError> {
+        match self.get(name) {
+            Err(ConfigError::UnknownIdentity(_)) => {
+                let password = new_password(name)?;
+                let identity = self.create_keystore(name, &password)?;
+                // `create_keystore` stores the keystore path relative to the directory.
+                self.get(&identity.name)
+            }
+            result => result,
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, ConfigError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(ConfigError::InvalidIdentityName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{name}.toml")))
+    }
+
+    fn check_new(&self, name: &str) -> Result<(), ConfigError> {
+        if self.path(name)?.exists() {
+            return Err(ConfigError::IdentityExists(name.to_string()));
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| ConfigError::Io(self.dir.clone(), e))
+    }
+
+    fn write(&self, identity: Identity) -> Result<Identity, ConfigError> {
+        let path = self.path(&identity.name)?;
+        let contents =
+            toml::to_string(&identity).map_err(|e| ConfigError::Serialize(path.clone(), e))?;
+        std::fs::write(&path, contents).map_err(|e| ConfigError::Io(path, e))?;
+        Ok(identity)
+    }
+}
+
+/// Reads the password for a new keystore from `$COUNTER_CLIENT_KEYSTORE_PASSWORD`, or prompts
+/// for it twice.
+pub fn new_password(name: &str) -> Result<String, ConfigError> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_ENV) {
+        return Ok(password);
+    }
+
+    let prompt_error = |e: std::io::Error| ConfigError::InvalidEnv {
+        var: KEYSTORE_PASSWORD_ENV.to_string(),
+        reason: format!("not set and failed to prompt: {e}"),
+    };
+    let password = rpassword::prompt_password(format!("New password for identity {name}: "))
+        .map_err(prompt_error)?;
+    let confirmation = rpassword::prompt_password("Repeat password: ").map_err(prompt_error)?;
+    if password != confirmation {
+        return Err(ConfigError::InvalidEnv {
+            var: KEYSTORE_PASSWORD_ENV.to_string(),
+            reason: "passwords do not match".to_string(),
+        });
+    }
+    Ok(password)
+}
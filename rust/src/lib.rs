@@ -0,0 +1,12 @@
+//! Client library for the `CounterByAddress` meta-transaction example.
+//!
+//! The [`relayer`] module contains the EIP-2771 gas relayer middleware along with the contract
+//! bindings it is built on, so that services can depend on this crate instead of copying the
+//! examples. The [`config`] module loads endpoints, addresses and signers from profiles, and
+//! [`identity`] keeps named meta-transaction identities on disk. The [`server`] module relays
+//! requests signed by other clients over JSON-RPC.
+
+pub mod config;
+pub mod identity;
+pub mod relayer;
+pub mod server;
@@ -1,3 +1,468 @@
-fn main() {
-    println!("Hello, world!");
+use std::{path::PathBuf, sync::Arc};
+
+use clap::{Args, Parser, Subcommand};
+use counter_client::{
+    config::{self, Profile, ProfileConfig, SignerConfig},
+    identity::{self, IdentityStore},
+    relayer::{
+        abi, budget::unix_time, convert, EIP2771GasRelayerMiddleware, ForwardRequest,
+        ForwardRequestVerifier, ForwarderDomains, HttpRelayTransport, MetaTransactionOptions,
+        SpendReport,
+    },
+};
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, Bytes},
+};
+use eyre::{bail, Result};
+
+/// Command-line client for the `CounterByAddress` meta-transaction example.
+#[derive(Debug, Parser)]
+#[command(name = "counter-client", version)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Selects a profile from the config file and overrides its fields. Each of these falls back to
+/// the matching `COUNTER_CLIENT_*` environment variable and then to the profile.
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    /// Config file [default: counter-client.toml].
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Profile in the config file [default: the file's `default_profile`].
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// JSON-RPC endpoint of the node.
+    #[arg(long, global = true)]
+    rpc_url: Option<String>,
+
+    /// Expected chain id; checked against the RPC endpoint.
+    #[arg(long, global = true)]
+    chain_id: Option<u64>,
+
+    /// Address of the `Forwarder` contract.
+    #[arg(long, global = true)]
+    forwarder: Option<String>,
+
+    /// Address of the `CounterByAddress` contract.
+    #[arg(long, global = true)]
+    counter: Option<String>,
+
+    /// Private key of the funded wallet that pays for transactions.
+    #[arg(long, global = true)]
+    gas_private_key: Option<String>,
+
+    /// Private key of the wallet that signs meta-transactions.
+    #[arg(long, global = true, conflicts_with = "meta_identity")]
+    meta_private_key: Option<String>,
+
+    /// Name of the identity that signs meta-transactions (see `identity list`).
+    #[arg(long, global = true)]
+    meta_identity: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Send transactions directly from the gas wallet.
+    #[command(subcommand)]
+    Direct(DirectCommand),
+
+    /// Send meta-transactions through the `Forwarder`.
+    #[command(subcommand)]
+    Meta(MetaCommand),
+
+    /// Read from the `CounterByAddress` contract.
+    #[command(subcommand)]
+    Counter(CounterCommand),
+
+    /// Read from the `Forwarder` contract.
+    #[command(subcommand)]
+    Forwarder(ForwarderCommand),
+
+    /// Manage the named meta-transaction identities in the identity directory.
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+
+    /// Inspect the gas a relayer sponsored, from the profile's budget ledger.
+    #[command(subcommand)]
+    Budget(BudgetCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum DirectCommand {
+    /// Increment the gas wallet's counter.
+    Increment,
+}
+
+#[derive(Debug, Subcommand)]
+enum MetaCommand {
+    /// Increment the meta wallet's counter, paying gas with the gas wallet.
+    Increment {
+        /// Send the transaction even if simulating it shows that it will revert.
+        #[arg(long)]
+        skip_preflight: bool,
+
+        /// Deliver the signed request to the `relayer-server` at this URL instead of sending it
+        /// from the gas wallet.
+        #[arg(long)]
+        relayer_url: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CounterCommand {
+    /// Print the counter value for an address.
+    Get { address: Address },
+}
+
+#[derive(Debug, Subcommand)]
+enum ForwarderCommand {
+    /// Print the forwarder nonce for an address.
+    Nonce { address: Address },
+
+    /// Print the forwarder's EIP-712 domain, read with `eip712Domain()`.
+    Domain,
+
+    /// Check a signed `ForwardRequest` with `Forwarder.verify`.
+    Verify {
+        /// JSON file containing the `ForwardRequest`.
+        request: PathBuf,
+
+        /// Hex-encoded signature over the request.
+        signature: Bytes,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum BudgetCommand {
+    /// Print the wei spent per meta signer and per target.
+    Report {
+        /// Only count the last this many seconds [default: the budget's window].
+        #[arg(long)]
+        window_secs: Option<u64>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum IdentityCommand {
+    /// Create an identity. Without `--mnemonic-env` a new key is generated and stored in an
+    /// encrypted keystore (password from $COUNTER_CLIENT_KEYSTORE_PASSWORD or a prompt).
+    New {
+        name: String,
+
+        /// Derive the key from the BIP-39 mnemonic in this environment variable instead.
+        #[arg(long)]
+        mnemonic_env: Option<String>,
+
+        /// Index of the derived key in the mnemonic.
+        #[arg(long, default_value_t = 0, requires = "mnemonic_env")]
+        index: u32,
+    },
+
+    /// List the identities and their addresses.
+    List,
+
+    /// Print an identity's address and where its key is stored.
+    Show { name: String },
+}
+
+impl ConfigArgs {
+    async fn load(self) -> Result<Profile> {
+        let overrides = ProfileConfig {
+            rpc_url: self.rpc_url,
+            chain_id: self.chain_id,
+            forwarder: self.forwarder,
+            counter: self.counter,
+            gas_wallet: self.gas_private_key.map(SignerConfig::PrivateKey),
+            meta_wallet: self
+                .meta_private_key
+                .map(SignerConfig::PrivateKey)
+                .or(self.meta_identity.map(SignerConfig::Identity)),
+            ..Default::default()
+        };
+
+        Ok(config::load(self.config.as_deref(), self.profile.as_deref(), overrides).await?)
+    }
+}
+
+fn signer_client(
+    profile: &Profile,
+    wallet: LocalWallet,
+) -> SignerMiddleware<Provider<Http>, LocalWallet> {
+    SignerMiddleware::new(profile.provider.clone(), wallet)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Identities are managed offline, without loading a profile.
+    if let Command::Identity(command) = cli.command {
+        return identity_command(command);
+    }
+    // So is the spend ledger, which may be read where the relayer's keys are not available.
+    if let Command::Budget(command) = cli.command {
+        return budget_command(&cli.config, command);
+    }
+
+    let profile = cli.config.load().await?;
+
+    match cli.command {
+        Command::Direct(DirectCommand::Increment) => direct_increment(&profile).await,
+        Command::Meta(MetaCommand::Increment {
+            skip_preflight,
+            relayer_url,
+        }) => meta_increment(&profile, skip_preflight, relayer_url).await,
+        Command::Counter(CounterCommand::Get { address }) => counter_get(&profile, address).await,
+        Command::Forwarder(ForwarderCommand::Nonce { address }) => {
+            forwarder_nonce(&profile, address).await
+        }
+        Command::Forwarder(ForwarderCommand::Domain) => forwarder_domain(&profile).await,
+        Command::Forwarder(ForwarderCommand::Verify { request, signature }) => {
+            forwarder_verify(&profile, request, signature).await
+        }
+        Command::Identity(_) | Command::Budget(_) => unreachable!("handled above"),
+    }
+}
+
+async fn direct_increment(profile: &Profile) -> Result<()> {
+    let client = Arc::new(signer_client(profile, profile.gas_wallet()?));
+    let counter = abi::CounterByAddress::new(profile.counter()?, client.clone());
+
+    let receipt = counter.increment().send().await?.await?;
+    println!(
+        "Transaction confirmed: {:?}",
+        receipt.map(|r| r.transaction_hash)
+    );
+
+    let value = counter.get_counter(client.address()).call().await?;
+    println!("Counter value for {:?}: {}", client.address(), value);
+    Ok(())
+}
+
+async fn meta_increment(
+    profile: &Profile,
+    skip_preflight: bool,
+    relayer_url: Option<String>,
+) -> Result<()> {
+    let meta_wallet = profile.meta_wallet()?;
+    let meta_address = meta_wallet.address();
+
+    let gas_client = signer_client(profile, profile.gas_wallet()?);
+    let forwarder_with_gas_signer = abi::Forwarder::new(profile.forwarder()?, Arc::new(gas_client));
+
+    let mut meta_client = EIP2771GasRelayerMiddleware::new(
+        signer_client(profile, meta_wallet.clone()),
+        meta_wallet,
+        forwarder_with_gas_signer,
+    );
+    if let Some(fees) = &profile.fees {
+        meta_client = meta_client.with_fee_strategy(fees.clone());
+    }
+    if let Some(escalation) = &profile.escalation {
+        meta_client = meta_client.with_escalation(escalation.clone());
+    }
+    if let Some(transaction_type) = profile.transaction_type {
+        meta_client = meta_client.with_transaction_type(transaction_type);
+    }
+    if let Some(url) = relayer_url {
+        let relayer = Provider::<Http>::try_from(url.as_str())?;
+        meta_client = meta_client.with_transport(HttpRelayTransport::new(relayer));
+    }
+    let meta_client = Arc::new(meta_client);
+
+    // Follow the `execute` transaction through any fee escalation.
+    let counter = abi::CounterByAddress::new(profile.counter()?, meta_client.clone());
+    let mut options = MetaTransactionOptions::new();
+    if skip_preflight {
+        options = options.skip_preflight();
+    }
+    let receipt = meta_client
+        .send_meta_transaction(counter.increment().tx, options)
+        .await?
+        .await?;
+    match receipt {
+        Some(receipt) => {
+            println!(
+                "Transaction confirmed: {:?}",
+                receipt.receipt.transaction_hash
+            );
+            println!(
+                "Forwarder nonce {} consumed: {}",
+                receipt.nonce(),
+                receipt.nonce_advanced
+            );
+        }
+        None => println!("Transaction replaced by another one from the gas wallet"),
+    }
+
+    let value = counter.get_counter(meta_address).call().await?;
+    println!("Counter value for {:?}: {}", meta_address, value);
+    Ok(())
+}
+
+async fn counter_get(profile: &Profile, address: Address) -> Result<()> {
+    let counter =
+        abi::CounterByAddress::new(profile.counter()?, Arc::new(profile.provider.clone()));
+    let value = counter.get_counter(address).call().await?;
+    println!("{value}");
+    Ok(())
+}
+
+async fn forwarder_nonce(profile: &Profile, address: Address) -> Result<()> {
+    let forwarder = abi::Forwarder::new(profile.forwarder()?, Arc::new(profile.provider.clone()));
+    let nonce = forwarder.get_nonce(address).call().await?;
+    println!("{nonce}");
+    Ok(())
+}
+
+async fn forwarder_verify(profile: &Profile, request: PathBuf, signature: Bytes) -> Result<()> {
+    let request: ForwardRequest = serde_json::from_str(&std::fs::read_to_string(request)?)?;
+
+    let forwarder = abi::Forwarder::new(profile.forwarder()?, Arc::new(profile.provider.clone()));
+    let valid = forwarder
+        .verify(request.clone().into(), signature.clone())
+        .call()
+        .await?;
+    println!("{valid}");
+
+    if !valid {
+        // The forwarder only says no; replay the check offline to find out why.
+        let domain = ForwarderDomains::new()
+            .get(
+                &profile.provider,
+                convert::to_alloy_address(forwarder.address()),
+                profile.chain_id()?,
+            )
+            .await?;
+        let nonce = forwarder
+            .get_nonce(convert::to_ethers_address(request.from))
+            .call()
+            .await?;
+
+        match ForwardRequestVerifier::new(domain).verify(
+            &request,
+            &signature,
+            convert::to_alloy_u256(nonce),
+        ) {
+            Err(e) => bail!("signature does not verify against the forwarder: {e}"),
+            Ok(()) => bail!("signature does not verify against the forwarder"),
+        }
+    }
+    Ok(())
+}
+
+async fn forwarder_domain(profile: &Profile) -> Result<()> {
+    let domain = ForwarderDomains::new()
+        .get(
+            &profile.provider,
+            convert::to_alloy_address(profile.forwarder()?),
+            profile.chain_id()?,
+        )
+        .await?;
+
+    println!(
+        "name:              {}",
+        domain.name.as_deref().unwrap_or_default()
+    );
+    println!(
+        "version:           {}",
+        domain.version.as_deref().unwrap_or_default()
+    );
+    println!("chainId:           {}", domain.chain_id.unwrap_or_default());
+    println!(
+        "verifyingContract: {}",
+        domain.verifying_contract.unwrap_or_default()
+    );
+    println!("separator:         {}", domain.separator());
+    Ok(())
+}
+
+fn identity_command(command: IdentityCommand) -> Result<()> {
+    let store = IdentityStore::open_default()?;
+
+    match command {
+        IdentityCommand::New {
+            name,
+            mnemonic_env,
+            index,
+        } => {
+            let identity = match mnemonic_env {
+                Some(var) => store.create_mnemonic(&name, &var, index)?,
+                None => store.create_keystore(&name, &identity::new_password(&name)?)?,
+            };
+            println!("Created identity {}: {:?}", identity.name, identity.address);
+        }
+        IdentityCommand::List => {
+            for identity in store.list()? {
+                println!("{}\t{:?}", identity.name, identity.address);
+            }
+        }
+        IdentityCommand::Show { name } => {
+            let identity = store.get(&name)?;
+            println!("name:    {}", identity.name);
+            println!("address: {:?}", identity.address);
+            match identity.signer {
+                SignerConfig::Keystore { path, .. } => println!("keystore: {}", path.display()),
+                SignerConfig::Mnemonic {
+                    phrase_env, index, ..
+                } => println!(
+                    "mnemonic: ${} index {}",
+                    phrase_env.unwrap_or_default(),
+                    index
+                ),
+                SignerConfig::PrivateKeyEnv(var) => println!("private key: ${var}"),
+                SignerConfig::PrivateKey(_) => println!("private key: inline"),
+                SignerConfig::Identity(name) => println!("identity: {name}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn budget_command(config: &ConfigArgs, command: BudgetCommand) -> Result<()> {
+    let (name, profile) =
+        config::read_profile(config.config.as_deref(), config.profile.as_deref())?;
+    let Some(budget) = profile.budget else {
+        bail!("Profile {name} has no budget");
+    };
+
+    match command {
+        BudgetCommand::Report { window_secs } => {
+            let window_secs = window_secs.unwrap_or(budget.window_secs);
+            let report =
+                SpendReport::read(&budget.ledger, unix_time().saturating_sub(window_secs))?;
+
+            println!(
+                "Spend in the last {window_secs}s (budget {} wei per user)",
+                budget.max_wei
+            );
+            println!("users:");
+            for total in report.users {
+                println!(
+                    "  {}\t{} wei\t{} txs",
+                    total.address, total.wei, total.transactions
+                );
+            }
+            println!("targets:");
+            for total in report.targets {
+                println!(
+                    "  {}\t{} wei\t{} txs",
+                    total.address, total.wei, total.transactions
+                );
+            }
+        }
+    }
+
+    Ok(())
 }
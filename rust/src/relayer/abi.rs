@@ -0,0 +1,17 @@
+//! Type-safe bindings for the `Forwarder` and `CounterByAddress` contracts.
+//!
+//! The Hardhat artifacts are vendored in `rust/abi` so that the crate builds without compiling
+//! the contracts first. Run `scripts/sync-abi.sh` after changing the Solidity sources; the
+//! `vendored_abi` test flags any drift from a fresh build.
+
+use ethers::contract::abigen;
+
+/// The vendored `Forwarder` Hardhat artifact.
+pub const FORWARDER_ARTIFACT: &str = include_str!("../../abi/Forwarder.json");
+
+/// The vendored `CounterByAddress` Hardhat artifact.
+pub const COUNTER_BY_ADDRESS_ARTIFACT: &str = include_str!("../../abi/CounterByAddress.json");
+
+// Generate the type-safe contract bindings using the JSON ABI
+abigen!(CounterByAddress, "abi/CounterByAddress.json");
+abigen!(Forwarder, "abi/Forwarder.json");
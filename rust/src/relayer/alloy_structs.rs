@@ -0,0 +1,33 @@
+//! alloy `sol!` definitions used for EIP-712 signing and by the alloy provider layer.
+//!
+//! We use alloy to generate typed data signatures because there is a bug in ethers-rs that causes
+//! the encoding for the `bytes data` field to be incorrect.
+
+use alloy::sol;
+use serde::Serialize;
+
+sol! {
+    #[derive(Debug, Serialize)]
+    struct ForwardRequest {
+        address from;
+        address to;
+        uint256 value;
+        uint256 gas;
+        uint256 nonce;
+        bytes data;
+    }
+}
+
+sol!(
+    #[sol(rpc)]
+    #[derive(Debug)]
+    Forwarder,
+    "abi/Forwarder.json"
+);
+
+sol!(
+    #[sol(rpc)]
+    #[derive(Debug)]
+    CounterByAddress,
+    "abi/CounterByAddress.json"
+);
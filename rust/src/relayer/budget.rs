@@ -0,0 +1,576 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use alloy::primitives::{Address, U256};
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::MetaTransactionReceipt;
+
+/// The window budgets are counted over when the config does not say, in seconds.
+pub const DEFAULT_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Error, Debug)]
+pub enum BudgetError {
+    /// `spent` includes what is reserved for the signer's meta-transactions in flight.
+    #[error("{from} has used {spent} of its {max} wei budget for the last {window_secs}s")]
+    Exhausted {
+        from: Address,
+        spent: U256,
+        max: U256,
+        window_secs: u64,
+    },
+
+    #[error("{from} has {remaining} wei of budget left for the last {window_secs}s, but the meta-transaction may cost up to {cost}")]
+    Insufficient {
+        from: Address,
+        remaining: U256,
+        cost: U256,
+        window_secs: u64,
+    },
+
+    #[error("Failed to access spend ledger {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Invalid spend ledger {path} at line {line}: {reason}")]
+    Corrupt {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+}
+
+/// What the gas wallet paid for one meta-transaction: `gasUsed * effectiveGasPrice` of its
+/// `execute` transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendRecord {
+    /// The meta signer.
+    pub from: Address,
+    /// The contract the request called.
+    pub target: Address,
+    pub tx_hash: H256,
+    pub wei: U256,
+    /// When the spend was recorded, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl SpendRecord {
+    pub fn new(receipt: &MetaTransactionReceipt, wei: U256, timestamp: u64) -> Self {
+        Self {
+            from: receipt.from(),
+            target: receipt.request.to,
+            tx_hash: receipt.receipt.transaction_hash,
+            wei,
+            timestamp,
+        }
+    }
+}
+
+/// The spend of every meta-transaction a relayer paid for.
+///
+/// A ledger opened from a file appends each record to it as a line of JSON, so spend survives
+/// restarts. In memory, the records are kept per meta signer with a running total. With a
+/// [`window`](Self::with_window), records that fall out of it are dropped from memory, so that
+/// [`spent`](Self::spent) does not grow with the history. Clones share the records, and the
+/// [reservations](SpendReservation) of the meta-transactions in flight.
+#[derive(Debug, Clone, Default)]
+pub struct SpendLedger {
+    inner: Arc<Mutex<Ledger>>,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    path: Option<PathBuf>,
+    /// How long records are kept in memory, in seconds. `None` keeps them all.
+    window_secs: Option<u64>,
+    signers: HashMap<Address, SignerSpend>,
+    /// What is reserved per meta signer for its meta-transactions in flight.
+    reserved: HashMap<Address, U256>,
+}
+
+/// One meta signer's records, oldest first, and their total.
+#[derive(Debug, Default)]
+struct SignerSpend {
+    records: VecDeque<SpendRecord>,
+    total: U256,
+}
+
+impl SignerSpend {
+    fn push(&mut self, record: SpendRecord) {
+        self.total = self.total.saturating_add(record.wei);
+        self.records.push_back(record);
+    }
+
+    /// Drops the records from before `since`.
+    fn expire(&mut self, since: u64) {
+        while let Some(record) = self.records.front() {
+            if record.timestamp >= since {
+                break;
+            }
+            self.total = self.total.saturating_sub(record.wei);
+            self.records.pop_front();
+        }
+    }
+
+    /// The total since `since`.
+    fn since(&self, since: u64) -> U256 {
+        self.records
+            .iter()
+            .take_while(|record| record.timestamp < since)
+            .fold(self.total, |total, record| total.saturating_sub(record.wei))
+    }
+}
+
+impl Ledger {
+    fn push(&mut self, record: SpendRecord) {
+        let from = record.from;
+        let timestamp = record.timestamp;
+        let spend = self.signers.entry(from).or_default();
+        spend.push(record);
+        if let Some(window_secs) = self.window_secs {
+            spend.expire(timestamp.saturating_sub(window_secs));
+        }
+    }
+
+    /// What `from` was sponsored for since `since`, after dropping the records that were out of
+    /// the window at `now`.
+    fn spent(&mut self, from: Address, since: u64, now: u64) -> U256 {
+        let window_secs = self.window_secs;
+        let Some(spend) = self.signers.get_mut(&from) else {
+            return U256::ZERO;
+        };
+        if let Some(window_secs) = window_secs {
+            spend.expire(now.saturating_sub(window_secs));
+        }
+        let total = spend.since(since);
+        if spend.records.is_empty() {
+            self.signers.remove(&from);
+        }
+        total
+    }
+
+    fn reserved(&self, from: Address) -> U256 {
+        self.reserved.get(&from).copied().unwrap_or_default()
+    }
+
+    fn release(&mut self, from: Address, wei: U256) {
+        if let Some(reserved) = self.reserved.get_mut(&from) {
+            *reserved = reserved.saturating_sub(wei);
+            if reserved.is_zero() {
+                self.reserved.remove(&from);
+            }
+        }
+    }
+}
+
+impl SpendLedger {
+    /// A ledger that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens the ledger at `path`, reading the records already in it. The file is created on the
+    /// first record.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, BudgetError> {
+        Self::load(path.into(), None)
+    }
+
+    /// Opens the ledger at `path` like [`open`](Self::open), but only reads the records of the
+    /// last `window` into memory.
+    pub fn open_with_window(
+        path: impl Into<PathBuf>,
+        window: Duration,
+    ) -> Result<Self, BudgetError> {
+        Self::load(path.into(), Some(window.as_secs()))
+    }
+
+    fn load(path: PathBuf, window_secs: Option<u64>) -> Result<Self, BudgetError> {
+        let since = window_secs.map_or(0, |secs| unix_time().saturating_sub(secs));
+        let mut ledger = Ledger {
+            path: None,
+            window_secs,
+            signers: HashMap::new(),
+            reserved: HashMap::new(),
+        };
+        match File::open(&path) {
+            Ok(file) => {
+                for record in read_records(&path, file) {
+                    let record = record?;
+                    if record.timestamp >= since {
+                        ledger.push(record);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(BudgetError::Io(path, e)),
+        }
+        ledger.path = Some(path);
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(ledger)),
+        })
+    }
+
+    /// Keeps only the records of the last `window` in memory, for this ledger and its clones. A
+    /// ledger shared by budgets with different windows keeps the longest.
+    pub fn with_window(self, window: Duration) -> Self {
+        {
+            let mut ledger = self.inner.lock().unwrap();
+            let window_secs = window.as_secs();
+            ledger.window_secs = Some(
+                ledger
+                    .window_secs
+                    .map_or(window_secs, |secs| secs.max(window_secs)),
+            );
+        }
+        self
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.inner.lock().unwrap().path.clone()
+    }
+
+    /// Adds `record`, writing it to the file first if the ledger has one.
+    pub fn record(&self, record: SpendRecord) -> Result<(), BudgetError> {
+        self.record_releasing(record, U256::ZERO)
+    }
+
+    /// Records `record` and, in the same step, releases `reserved` of its signer's reservations.
+    fn record_releasing(&self, record: SpendRecord, reserved: U256) -> Result<(), BudgetError> {
+        let mut ledger = self.inner.lock().unwrap();
+        if let Some(path) = &ledger.path {
+            let line = serde_json::to_string(&record).expect("spend records serialize");
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{line}"))
+                .map_err(|e| BudgetError::Io(path.clone(), e))?;
+        }
+        ledger.release(record.from, reserved);
+        ledger.push(record);
+        Ok(())
+    }
+
+    /// The records kept in memory, oldest first.
+    pub fn records(&self) -> Vec<SpendRecord> {
+        let ledger = self.inner.lock().unwrap();
+        let mut records: Vec<_> = ledger
+            .signers
+            .values()
+            .flat_map(|spend| spend.records.iter().cloned())
+            .collect();
+        records.sort_by_key(|record| (record.timestamp, record.tx_hash));
+        records
+    }
+
+    /// What `from` was sponsored for since `since` (seconds since the Unix epoch).
+    ///
+    /// With a window, the records that are out of it are dropped, whatever `since` is.
+    pub fn spent(&self, from: Address, since: u64) -> U256 {
+        self.inner.lock().unwrap().spent(from, since, unix_time())
+    }
+
+    /// What is reserved for `from`'s meta-transactions in flight.
+    pub fn reserved(&self, from: Address) -> U256 {
+        self.inner.lock().unwrap().reserved(from)
+    }
+
+    /// Totals the spend since `since` per meta signer and per target, from the records kept in
+    /// memory. [`SpendReport::read`] reads them from the file instead.
+    pub fn report(&self, since: u64) -> SpendReport {
+        let ledger = self.inner.lock().unwrap();
+        let mut report = ReportBuilder::default();
+        for spend in ledger.signers.values() {
+            for record in spend
+                .records
+                .iter()
+                .filter(|record| record.timestamp >= since)
+            {
+                report.add(record);
+            }
+        }
+        report.finish()
+    }
+}
+
+/// Reads the records of the ledger file at `path` one line at a time.
+fn read_records<'a>(
+    path: &'a Path,
+    file: File,
+) -> impl Iterator<Item = Result<SpendRecord, BudgetError>> + 'a {
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter_map(move |(i, line)| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(BudgetError::Io(path.to_path_buf(), e))),
+            };
+            if line.trim().is_empty() {
+                return None;
+            }
+            Some(
+                serde_json::from_str(&line).map_err(|e| BudgetError::Corrupt {
+                    path: path.to_path_buf(),
+                    line: i + 1,
+                    reason: e.to_string(),
+                }),
+            )
+        })
+}
+
+#[derive(Debug, Default)]
+struct ReportBuilder {
+    users: HashMap<Address, SpendTotal>,
+    targets: HashMap<Address, SpendTotal>,
+}
+
+impl ReportBuilder {
+    fn add(&mut self, record: &SpendRecord) {
+        for (totals, address) in [
+            (&mut self.users, record.from),
+            (&mut self.targets, record.target),
+        ] {
+            let total = totals.entry(address).or_insert(SpendTotal {
+                address,
+                wei: U256::ZERO,
+                transactions: 0,
+            });
+            total.wei = total.wei.saturating_add(record.wei);
+            total.transactions += 1;
+        }
+    }
+
+    fn finish(self) -> SpendReport {
+        SpendReport {
+            users: sorted(self.users),
+            targets: sorted(self.targets),
+        }
+    }
+}
+
+fn sorted(totals: HashMap<Address, SpendTotal>) -> Vec<SpendTotal> {
+    let mut totals: Vec<_> = totals.into_values().collect();
+    totals.sort_by(|a, b| b.wei.cmp(&a.wei).then(a.address.cmp(&b.address)));
+    totals
+}
+
+/// Spend per meta signer and per target, largest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendReport {
+    pub users: Vec<SpendTotal>,
+    pub targets: Vec<SpendTotal>,
+}
+
+impl SpendReport {
+    /// Totals the spend since `since` in the ledger file at `path`, reading it one record at a
+    /// time. A missing file has no spend.
+    pub fn read(path: &Path, since: u64) -> Result<Self, BudgetError> {
+        let mut report = ReportBuilder::default();
+        match File::open(path) {
+            Ok(file) => {
+                for record in read_records(path, file) {
+                    let record = record?;
+                    if record.timestamp >= since {
+                        report.add(&record);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(BudgetError::Io(path.to_path_buf(), e)),
+        }
+        Ok(report.finish())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendTotal {
+    pub address: Address,
+    pub wei: U256,
+    pub transactions: usize,
+}
+
+/// A budget, as written in the config file:
+///
+/// ```toml
+/// [profiles.local.budget]
+/// max_wei = "10000000000000000"
+/// window_secs = 86400
+/// ledger = "spend.jsonl"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    pub max_wei: U256,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    pub ledger: PathBuf,
+}
+
+fn default_window_secs() -> u64 {
+    DEFAULT_WINDOW_SECS
+}
+
+/// How much gas a relayer sponsors per meta signer: at most `max_wei` over any `window`.
+///
+/// Before a meta-transaction is broadcast, the most it may cost is [reserved](Self::reserve),
+/// and the reservation is settled against what it actually cost once its receipt is awaited.
+/// Requests in flight thus count against the budget, and cannot take a signer over it.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub max_wei: U256,
+    pub window: Duration,
+    ledger: SpendLedger,
+}
+
+impl Budget {
+    /// Counts spend in `ledger`, which then keeps only the records of the last `window` in
+    /// memory (see [`SpendLedger::with_window`]).
+    pub fn new(max_wei: U256, window: Duration, ledger: SpendLedger) -> Self {
+        Self {
+            max_wei,
+            window,
+            ledger: ledger.with_window(window),
+        }
+    }
+
+    /// Opens the configured ledger, reading only the records of the window.
+    pub fn open(config: &BudgetConfig) -> Result<Self, BudgetError> {
+        let window = Duration::from_secs(config.window_secs);
+        Ok(Self::new(
+            config.max_wei,
+            window,
+            SpendLedger::open_with_window(&config.ledger, window)?,
+        ))
+    }
+
+    pub fn ledger(&self) -> &SpendLedger {
+        &self.ledger
+    }
+
+    /// Rejects `from` if it has used up its budget for the window ending now.
+    pub fn check(&self, from: Address) -> Result<(), BudgetError> {
+        self.check_at(from, unix_time())
+    }
+
+    /// Rejects `from` if it has used up its budget for the window ending at `now`. What is
+    /// reserved for its meta-transactions in flight counts as used.
+    pub fn check_at(&self, from: Address, now: u64) -> Result<(), BudgetError> {
+        let mut ledger = self.ledger.inner.lock().unwrap();
+        self.admit(&mut ledger, from, U256::ZERO, now)
+    }
+
+    /// Reserves `cost`, the most a meta-transaction of `from` may cost, until it is settled or
+    /// dropped. Rejects `from` if that would take it over its budget for the window ending now.
+    pub fn reserve(&self, from: Address, cost: U256) -> Result<SpendReservation, BudgetError> {
+        self.reserve_at(from, cost, unix_time())
+    }
+
+    /// Like [`reserve`](Self::reserve), for the window ending at `now`.
+    pub fn reserve_at(
+        &self,
+        from: Address,
+        cost: U256,
+        now: u64,
+    ) -> Result<SpendReservation, BudgetError> {
+        let mut ledger = self.ledger.inner.lock().unwrap();
+        self.admit(&mut ledger, from, cost, now)?;
+        let reserved = ledger.reserved.entry(from).or_default();
+        *reserved = reserved.saturating_add(cost);
+        Ok(SpendReservation {
+            ledger: self.ledger.clone(),
+            from,
+            wei: cost,
+        })
+    }
+
+    fn admit(
+        &self,
+        ledger: &mut Ledger,
+        from: Address,
+        cost: U256,
+        now: u64,
+    ) -> Result<(), BudgetError> {
+        let window_secs = self.window.as_secs();
+        let spent = ledger
+            .spent(from, now.saturating_sub(window_secs), now)
+            .saturating_add(ledger.reserved(from));
+        if spent >= self.max_wei {
+            return Err(BudgetError::Exhausted {
+                from,
+                spent,
+                max: self.max_wei,
+                window_secs,
+            });
+        }
+        if spent.saturating_add(cost) > self.max_wei {
+            return Err(BudgetError::Insufficient {
+                from,
+                remaining: self.max_wei - spent,
+                cost,
+                window_secs,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The most a meta-transaction in flight may cost, set aside in its signer's budget (see
+/// [`Budget::reserve`]). Dropping the reservation releases it, and [`settle`](Self::settle)
+/// replaces it with what the meta-transaction actually cost.
+#[derive(Debug)]
+pub struct SpendReservation {
+    ledger: SpendLedger,
+    from: Address,
+    wei: U256,
+}
+
+impl SpendReservation {
+    /// The meta signer.
+    pub fn from(&self) -> Address {
+        self.from
+    }
+
+    pub fn wei(&self) -> U256 {
+        self.wei
+    }
+
+    pub fn ledger(&self) -> &SpendLedger {
+        &self.ledger
+    }
+
+    /// Records `record` in the ledger and releases the reservation in the same step.
+    pub fn settle(mut self, record: SpendRecord) -> Result<(), BudgetError> {
+        self.ledger.record_releasing(record, self.wei)?;
+        self.wei = U256::ZERO;
+        Ok(())
+    }
+}
+
+impl Drop for SpendReservation {
+    fn drop(&mut self) {
+        if !self.wei.is_zero() {
+            self.ledger
+                .inner
+                .lock()
+                .unwrap()
+                .release(self.from, self.wei);
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
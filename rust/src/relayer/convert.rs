@@ -0,0 +1,31 @@
+//! Conversions between ethers and alloy primitive types.
+//!
+//! Both libraries use the same underlying representations (20-byte addresses and little-endian
+//! `u64` limbs for `U256`), so these conversions are lossless.
+
+use alloy::primitives::{Address, Bytes, U256};
+use ethers::types::{Address as EthersAddress, Bytes as EthersBytes, U256 as EthersU256};
+
+pub fn to_alloy_address(address: EthersAddress) -> Address {
+    Address::new(address.0)
+}
+
+pub fn to_ethers_address(address: Address) -> EthersAddress {
+    EthersAddress::from(address.0 .0)
+}
+
+pub fn to_alloy_u256(value: EthersU256) -> U256 {
+    U256::from_limbs(value.0)
+}
+
+pub fn to_ethers_u256(value: U256) -> EthersU256 {
+    EthersU256(value.into_limbs())
+}
+
+pub fn to_alloy_bytes(bytes: EthersBytes) -> Bytes {
+    Bytes(bytes.0)
+}
+
+pub fn to_ethers_bytes(bytes: Bytes) -> EthersBytes {
+    EthersBytes(bytes.0)
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use alloy::{
+    primitives::{Address, FixedBytes, U256},
+    providers::Provider,
+    sol_types::{Eip712Domain, SolCall},
+};
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, Eip1559TransactionRequest},
+};
+use thiserror::Error;
+
+use super::{
+    alloy_structs::Forwarder, convert, forwarder_domain, FORWARDER_NAME, FORWARDER_VERSION,
+};
+
+/// The EIP-5267 `fields` value of a domain with a name, version, chain id and verifying contract,
+/// and no salt or extensions.
+const FORWARDER_DOMAIN_FIELDS: u8 = 0x0f;
+
+#[derive(Error, Debug)]
+pub enum DomainError {
+    #[error("Failed to read eip712Domain() from forwarder {forwarder}: {reason}")]
+    Unavailable { forwarder: Address, reason: String },
+
+    #[error("Forwarder {forwarder} has an unsupported EIP-712 domain: {reason}")]
+    Unsupported { forwarder: Address, reason: String },
+
+    #[error("Forwarder {forwarder} has EIP-712 domain {field} {actual}, expected {expected}")]
+    Mismatch {
+        forwarder: Address,
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Reads the EIP-712 domain of `Forwarder` deployments with EIP-5267 `eip712Domain()` and caches
+/// it per forwarder address.
+///
+/// Every domain is checked against [`forwarder_domain`] before it is used, so that a forwarder
+/// redeployed with a different name or version fails with a [`DomainError::Mismatch`] naming the
+/// field, instead of every signature being rejected with `SignatureDoesNotMatch`.
+///
+/// Clones share the same cache.
+#[derive(Debug, Clone, Default)]
+pub struct ForwarderDomains {
+    cache: Arc<RwLock<HashMap<Address, Eip712Domain>>>,
+}
+
+impl ForwarderDomains {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached domain of `forwarder`, if it has been read already.
+    pub fn cached(&self, forwarder: Address) -> Option<Eip712Domain> {
+        self.cache.read().unwrap().get(&forwarder).cloned()
+    }
+
+    /// Returns the domain of `forwarder` on `chain_id`, reading it through an ethers client on
+    /// first use.
+    pub async fn get<M: Middleware>(
+        &self,
+        client: &M,
+        forwarder: Address,
+        chain_id: u64,
+    ) -> Result<Eip712Domain, DomainError> {
+        if let Some(domain) = self.cached(forwarder) {
+            return check(forwarder, chain_id, domain);
+        }
+
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(convert::to_ethers_address(forwarder))
+            .data(Forwarder::eip712DomainCall {}.abi_encode())
+            .into();
+        let output = client
+            .call(&tx, None)
+            .await
+            .map_err(|e| DomainError::Unavailable {
+                forwarder,
+                reason: e.to_string(),
+            })?;
+        let domain =
+            Forwarder::eip712DomainCall::abi_decode_returns(&output, true).map_err(|e| {
+                DomainError::Unavailable {
+                    forwarder,
+                    reason: e.to_string(),
+                }
+            })?;
+
+        self.insert(forwarder, chain_id, domain)
+    }
+
+    /// Like [`get`](Self::get), for an alloy provider.
+    pub async fn get_alloy<P: Provider>(
+        &self,
+        provider: &P,
+        forwarder: Address,
+        chain_id: u64,
+    ) -> Result<Eip712Domain, DomainError> {
+        if let Some(domain) = self.cached(forwarder) {
+            return check(forwarder, chain_id, domain);
+        }
+
+        let domain = Forwarder::new(forwarder, provider)
+            .eip712Domain()
+            .call()
+            .await
+            .map_err(|e| DomainError::Unavailable {
+                forwarder,
+                reason: e.to_string(),
+            })?;
+
+        self.insert(forwarder, chain_id, domain)
+    }
+
+    fn insert(
+        &self,
+        forwarder: Address,
+        chain_id: u64,
+        domain: Forwarder::eip712DomainReturn,
+    ) -> Result<Eip712Domain, DomainError> {
+        let fields = domain.fields.0[0];
+        if fields != FORWARDER_DOMAIN_FIELDS || !domain.extensions.is_empty() {
+            return Err(DomainError::Unsupported {
+                forwarder,
+                reason: format!(
+                    "fields {fields:#04x} with {} extensions, expected {FORWARDER_DOMAIN_FIELDS:#04x}",
+                    domain.extensions.len()
+                ),
+            });
+        }
+
+        let domain = Eip712Domain::new(
+            Some(domain.name.into()),
+            Some(domain.version.into()),
+            Some(domain.chainId),
+            Some(domain.verifyingContract),
+            None::<FixedBytes<32>>,
+        );
+        let domain = check(forwarder, chain_id, domain)?;
+
+        self.cache
+            .write()
+            .unwrap()
+            .insert(forwarder, domain.clone());
+        Ok(domain)
+    }
+}
+
+/// Checks `domain` against the defaults for `forwarder` on `chain_id`.
+fn check(
+    forwarder: Address,
+    chain_id: u64,
+    domain: Eip712Domain,
+) -> Result<Eip712Domain, DomainError> {
+    let expected = forwarder_domain(chain_id, forwarder);
+    let mismatch = |field, expected: String, actual: String| DomainError::Mismatch {
+        forwarder,
+        field,
+        expected,
+        actual,
+    };
+
+    if domain.name != expected.name {
+        return Err(mismatch(
+            "name",
+            FORWARDER_NAME.to_string(),
+            domain.name.unwrap_or_default().into_owned(),
+        ));
+    }
+    if domain.version != expected.version {
+        return Err(mismatch(
+            "version",
+            FORWARDER_VERSION.to_string(),
+            domain.version.unwrap_or_default().into_owned(),
+        ));
+    }
+    if domain.chain_id != expected.chain_id {
+        return Err(mismatch(
+            "chainId",
+            chain_id.to_string(),
+            domain.chain_id.unwrap_or(U256::ZERO).to_string(),
+        ));
+    }
+    if domain.verifying_contract != expected.verifying_contract {
+        return Err(mismatch(
+            "verifyingContract",
+            forwarder.to_string(),
+            domain.verifying_contract.unwrap_or_default().to_string(),
+        ));
+    }
+
+    Ok(domain)
+}
@@ -0,0 +1,515 @@
+use std::{
+    future::{Future, IntoFuture},
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, BlockId, BlockNumber, TransactionReceipt, H256,
+        U256,
+    },
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Error, Debug)]
+pub enum FeeError {
+    #[error("Failed to get fee history: {0}")]
+    FeeHistory(String),
+
+    #[error(
+        "Base fee {base_fee} plus priority fee {priority_fee} is above the max fee cap of {cap}"
+    )]
+    AboveCap {
+        base_fee: U256,
+        priority_fee: U256,
+        cap: U256,
+    },
+
+    #[error("Failed to follow transaction {hash:?}: {reason}")]
+    Rpc { hash: H256, reason: String },
+
+    #[error("Transaction {hash:?} was dropped: the node knows none of its broadcasts")]
+    Dropped { hash: H256 },
+
+    #[error("Transaction {hash:?} was not mined within {secs}s")]
+    TimedOut { hash: H256, secs: u64 },
+}
+
+/// How the gas wallet prices its `execute` transactions, from `eth_feeHistory`.
+///
+/// The priority fee is the median, over the last [`blocks`](Self::blocks), of the
+/// [`reward_percentile`](Self::reward_percentile)-th priority fee paid in each block. The max fee
+/// leaves room for the base fee to grow [`base_fee_multiplier`](Self::base_fee_multiplier) times
+/// before the transaction is priced out. Fees are in wei.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeStrategy {
+    pub blocks: u64,
+    pub reward_percentile: f64,
+    pub base_fee_multiplier: u64,
+    pub min_priority_fee_per_gas: u64,
+    /// Never pay more than this per gas. Sending fails with [`FeeError::AboveCap`] if the next
+    /// block's base fee plus the priority fee is already above it.
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+}
+
+impl Default for FeeStrategy {
+    fn default() -> Self {
+        Self {
+            blocks: 10,
+            reward_percentile: 50.0,
+            base_fee_multiplier: 2,
+            min_priority_fee_per_gas: 0,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+        }
+    }
+}
+
+/// EIP-1559 fees per gas, in wei, and the base fee they were priced from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fees {
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    /// The base fee of the next block, or zero on a chain without one.
+    pub base_fee_per_gas: U256,
+}
+
+impl Fees {
+    /// Sets the fees on `tx`. A legacy or EIP-2930 transaction pays its whole gas price, so it
+    /// offers the base fee plus the priority fee rather than the max fee.
+    pub fn apply(&self, tx: &mut TypedTransaction) {
+        match tx {
+            TypedTransaction::Legacy(tx) => tx.gas_price = Some(self.gas_price()),
+            TypedTransaction::Eip2930(tx) => tx.tx.gas_price = Some(self.gas_price()),
+            TypedTransaction::Eip1559(tx) => {
+                tx.max_fee_per_gas = Some(self.max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(self.max_priority_fee_per_gas);
+            }
+        }
+    }
+
+    /// The gas price of a transaction without EIP-1559 fees.
+    pub fn gas_price(&self) -> U256 {
+        (self.base_fee_per_gas + self.max_priority_fee_per_gas).min(self.max_fee_per_gas)
+    }
+}
+
+impl FeeStrategy {
+    /// Prices the next block from the fee history, or from `eth_gasPrice` on a chain without a
+    /// base fee.
+    pub async fn estimate<M: Middleware>(&self, client: &M) -> Result<Fees, FeeError> {
+        let history = match client
+            .fee_history(self.blocks, BlockNumber::Latest, &[self.reward_percentile])
+            .await
+        {
+            Ok(history) => history,
+            // Nodes for chains without London may not implement `eth_feeHistory` at all.
+            Err(e) => {
+                return self
+                    .legacy_fees(client)
+                    .await
+                    .map_err(|_| FeeError::FeeHistory(e.to_string()))
+            }
+        };
+
+        // The last entry is the base fee of the block after the newest one.
+        let base_fee = *history
+            .base_fee_per_gas
+            .last()
+            .ok_or_else(|| FeeError::FeeHistory("no base fee returned".to_string()))?;
+        if base_fee.is_zero() {
+            return self.legacy_fees(client).await;
+        }
+
+        let mut rewards: Vec<U256> = history
+            .reward
+            .iter()
+            .filter_map(|block| block.first().copied())
+            .collect();
+        rewards.sort();
+        let priority_fee = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+
+        self.fees(base_fee, priority_fee)
+    }
+
+    /// Prices a chain without a base fee from `eth_gasPrice`, which is paid in full: both fees
+    /// are the gas price, raised to the floor and capped.
+    async fn legacy_fees<M: Middleware>(&self, client: &M) -> Result<Fees, FeeError> {
+        let gas_price = client
+            .get_gas_price()
+            .await
+            .map_err(|e| FeeError::FeeHistory(e.to_string()))?;
+        let gas_price = gas_price.max(self.min_priority_fee_per_gas.into());
+
+        match self.max_fee_per_gas.map(U256::from) {
+            Some(cap) if gas_price > cap => Err(FeeError::AboveCap {
+                base_fee: U256::zero(),
+                priority_fee: gas_price,
+                cap,
+            }),
+            _ => Ok(Fees {
+                max_fee_per_gas: gas_price,
+                max_priority_fee_per_gas: gas_price,
+                base_fee_per_gas: U256::zero(),
+            }),
+        }
+    }
+
+    /// Applies the multiplier, floor and caps to a base fee and priority fee.
+    pub fn fees(&self, base_fee: U256, priority_fee: U256) -> Result<Fees, FeeError> {
+        let priority_fee =
+            self.cap_priority_fee(priority_fee.max(self.min_priority_fee_per_gas.into()));
+        let max_fee = base_fee * self.base_fee_multiplier + priority_fee;
+
+        let max_fee = match self.max_fee_per_gas.map(U256::from) {
+            Some(cap) if base_fee + priority_fee > cap => {
+                return Err(FeeError::AboveCap {
+                    base_fee,
+                    priority_fee,
+                    cap,
+                })
+            }
+            Some(cap) => max_fee.min(cap),
+            None => max_fee,
+        };
+
+        Ok(Fees {
+            max_fee_per_gas: max_fee,
+            max_priority_fee_per_gas: priority_fee,
+            base_fee_per_gas: base_fee,
+        })
+    }
+
+    fn cap_priority_fee(&self, priority_fee: U256) -> U256 {
+        match self.max_priority_fee_per_gas {
+            Some(cap) => priority_fee.min(cap.into()),
+            None => priority_fee,
+        }
+    }
+
+    fn cap_max_fee(&self, max_fee: U256) -> U256 {
+        match self.max_fee_per_gas {
+            Some(cap) => max_fee.min(cap.into()),
+            None => max_fee,
+        }
+    }
+
+    /// Raises the fees of `tx` by `percent`, within the caps. Returns `false` if the caps leave
+    /// no room to raise them.
+    pub fn bump(&self, tx: &mut TypedTransaction, percent: u64) -> bool {
+        let bump = |fee: U256| fee * (100 + percent) / 100 + 1;
+
+        match tx {
+            TypedTransaction::Legacy(tx) => {
+                bump_field(&mut tx.gas_price, |fee| self.cap_max_fee(bump(fee)))
+            }
+            TypedTransaction::Eip2930(tx) => {
+                bump_field(&mut tx.tx.gas_price, |fee| self.cap_max_fee(bump(fee)))
+            }
+            TypedTransaction::Eip1559(tx) => {
+                let before = (tx.max_fee_per_gas, tx.max_priority_fee_per_gas);
+                bump_field(&mut tx.max_priority_fee_per_gas, |fee| {
+                    self.cap_priority_fee(bump(fee))
+                });
+                bump_field(&mut tx.max_fee_per_gas, |fee| self.cap_max_fee(bump(fee)));
+                // The max fee has to cover the priority fee.
+                if let (Some(max_fee), Some(priority_fee)) =
+                    (tx.max_fee_per_gas, tx.max_priority_fee_per_gas)
+                {
+                    tx.max_priority_fee_per_gas = Some(priority_fee.min(max_fee));
+                }
+                (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) != before
+            }
+        }
+    }
+
+    /// The most `tx` may pay for gas: its gas limit at the fee it ends up offering after every
+    /// bump `escalation` allows, within the caps.
+    pub fn max_cost(&self, tx: &TypedTransaction, escalation: Option<&EscalationPolicy>) -> U256 {
+        let mut tx = tx.clone();
+        if let Some(policy) = escalation {
+            for _ in 0..policy.max_bumps {
+                if !self.bump(&mut tx, policy.bump_percent) {
+                    break;
+                }
+            }
+        }
+        let price = match &tx {
+            TypedTransaction::Eip1559(tx) => tx.max_fee_per_gas,
+            tx => tx.gas_price(),
+        };
+        tx.gas()
+            .copied()
+            .unwrap_or_default()
+            .saturating_mul(price.unwrap_or_default())
+    }
+}
+
+fn bump_field(field: &mut Option<U256>, bump: impl Fn(U256) -> U256) -> bool {
+    match *field {
+        Some(fee) => {
+            let bumped = bump(fee);
+            *field = Some(bumped);
+            bumped > fee
+        }
+        None => false,
+    }
+}
+
+/// When and how far to raise the fees of a gas wallet transaction that is not getting mined.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EscalationPolicy {
+    /// Seconds to wait for a receipt before rebroadcasting with higher fees.
+    pub interval_secs: u64,
+    /// How much to raise the fees by each time, in percent. Nodes reject replacements that raise
+    /// them by less than 10%.
+    pub bump_percent: u64,
+    /// Rebroadcasts after which the last one is simply waited for.
+    pub max_bumps: u32,
+    /// Seconds after the first broadcast after which waiting fails with
+    /// [`FeeError::TimedOut`] if none of the broadcasts is mined.
+    pub timeout_secs: u64,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            bump_percent: 12,
+            max_bumps: 5,
+            timeout_secs: 600,
+        }
+    }
+}
+
+/// A broadcast gas wallet transaction that is rebroadcast with higher fees, at the same nonce,
+/// while it is not mined.
+///
+/// Awaiting it resolves to the receipt of whichever broadcast is mined, or `None` if the nonce
+/// was used by some other transaction. It fails with [`FeeError::Dropped`] once the node knows
+/// none of the broadcasts, and with [`FeeError::TimedOut`] after the policy's
+/// [`timeout_secs`](EscalationPolicy::timeout_secs). Without an [`EscalationPolicy`] it only
+/// waits.
+#[derive(Debug)]
+pub struct EscalatingTransaction<'a, M> {
+    client: GasClient<'a, M>,
+    tx: TypedTransaction,
+    hashes: Vec<H256>,
+    strategy: FeeStrategy,
+    policy: Option<EscalationPolicy>,
+    block: Option<BlockId>,
+    broadcasts: Option<mpsc::UnboundedSender<H256>>,
+}
+
+impl<'a, M: Middleware> EscalatingTransaction<'a, M> {
+    /// Follows `tx`, already filled in (including its nonce) and broadcast as `hash`.
+    pub fn new(
+        client: &'a M,
+        tx: TypedTransaction,
+        hash: H256,
+        strategy: FeeStrategy,
+        policy: Option<EscalationPolicy>,
+        block: Option<BlockId>,
+    ) -> Self {
+        Self {
+            client: GasClient::Borrowed(client),
+            tx,
+            hashes: vec![hash],
+            strategy,
+            policy,
+            block,
+            broadcasts: None,
+        }
+    }
+
+    /// Sends the hash of every replacement to `broadcasts` as it is broadcast, e.g. to keep them
+    /// where they survive a restart.
+    pub fn with_broadcasts(mut self, broadcasts: mpsc::UnboundedSender<H256>) -> Self {
+        self.broadcasts = Some(broadcasts);
+        self
+    }
+
+    /// The hash of the latest broadcast.
+    pub fn tx_hash(&self) -> H256 {
+        *self.hashes.last().unwrap()
+    }
+
+    /// The hashes of every broadcast so far, oldest first.
+    pub fn hashes(&self) -> &[H256] {
+        &self.hashes
+    }
+
+    /// The transaction as last broadcast.
+    pub fn tx(&self) -> &TypedTransaction {
+        &self.tx
+    }
+
+    /// The client the transaction is followed with.
+    pub fn client(&self) -> &M {
+        &self.client
+    }
+
+    /// Follows the same broadcasts with `client`, which must be the client they were sent with,
+    /// so that they can be followed from a task of their own.
+    pub fn into_shared(self, client: Arc<M>) -> EscalatingTransaction<'static, M>
+    where
+        M: 'static,
+    {
+        EscalatingTransaction {
+            client: GasClient::Shared(client),
+            tx: self.tx,
+            hashes: self.hashes,
+            strategy: self.strategy,
+            policy: self.policy,
+            block: self.block,
+            broadcasts: self.broadcasts,
+        }
+    }
+
+    /// Waits for one of the broadcasts to be mined, escalating while none is.
+    ///
+    /// A replacement the node rejects (e.g. because an earlier broadcast was just mined) is
+    /// skipped.
+    pub async fn wait(&mut self) -> Result<Option<TransactionReceipt>, FeeError> {
+        let poll_interval = self.client.provider().get_interval();
+        let started = Instant::now();
+        let mut bumps = 0;
+        let mut last_broadcast = started;
+
+        loop {
+            if let Some(receipt) = self.receipt().await? {
+                return Ok(Some(receipt));
+            }
+            if self.nonce_used().await? {
+                // The nonce may have been used by one of ours since the receipts were checked.
+                return self.receipt().await;
+            }
+            if !self.known().await? {
+                // Evicted from the mempool, so nothing will ever use the nonce.
+                return Err(FeeError::Dropped {
+                    hash: self.tx_hash(),
+                });
+            }
+
+            if let Some(policy) = &self.policy {
+                if started.elapsed() >= Duration::from_secs(policy.timeout_secs) {
+                    return Err(FeeError::TimedOut {
+                        hash: self.tx_hash(),
+                        secs: policy.timeout_secs,
+                    });
+                }
+                if bumps < policy.max_bumps
+                    && last_broadcast.elapsed() >= Duration::from_secs(policy.interval_secs)
+                {
+                    bumps += 1;
+                    last_broadcast = Instant::now();
+
+                    let mut replacement = self.tx.clone();
+                    if !self.strategy.bump(&mut replacement, policy.bump_percent) {
+                        bumps = policy.max_bumps;
+                    } else if let Ok(pending) = self
+                        .client
+                        .send_transaction(replacement.clone(), self.block)
+                        .await
+                    {
+                        if let Some(broadcasts) = &self.broadcasts {
+                            // Nobody may be listening.
+                            let _ = broadcasts.send(pending.tx_hash());
+                        }
+                        self.hashes.push(pending.tx_hash());
+                        self.tx = replacement;
+                    }
+                }
+            }
+
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    async fn receipt(&self) -> Result<Option<TransactionReceipt>, FeeError> {
+        for hash in self.hashes.iter().rev() {
+            let receipt = self
+                .client
+                .get_transaction_receipt(*hash)
+                .await
+                .map_err(|e| FeeError::Rpc {
+                    hash: *hash,
+                    reason: e.to_string(),
+                })?;
+            if receipt.is_some() {
+                return Ok(receipt);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether the node still knows any of the broadcasts.
+    async fn known(&self) -> Result<bool, FeeError> {
+        for hash in self.hashes.iter().rev() {
+            let tx = self
+                .client
+                .get_transaction(*hash)
+                .await
+                .map_err(|e| FeeError::Rpc {
+                    hash: *hash,
+                    reason: e.to_string(),
+                })?;
+            if tx.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether a mined transaction has used the nonce.
+    async fn nonce_used(&self) -> Result<bool, FeeError> {
+        let (Some(from), Some(nonce)) = (self.tx.from(), self.tx.nonce()) else {
+            return Ok(false);
+        };
+        let mined = self
+            .client
+            .get_transaction_count(*from, Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(|e| FeeError::Rpc {
+                hash: self.tx_hash(),
+                reason: e.to_string(),
+            })?;
+        Ok(mined > *nonce)
+    }
+}
+
+impl<'a, M: Middleware + 'a> IntoFuture for EscalatingTransaction<'a, M> {
+    type Output = Result<Option<TransactionReceipt>, FeeError>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(mut self) -> Self::IntoFuture {
+        Box::pin(async move { self.wait().await })
+    }
+}
+
+/// The gas wallet's client, borrowed or shared with a task that follows the transaction.
+#[derive(Debug)]
+enum GasClient<'a, M> {
+    Borrowed(&'a M),
+    Shared(Arc<M>),
+}
+
+impl<M> Deref for GasClient<'_, M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        match self {
+            GasClient::Borrowed(client) => client,
+            GasClient::Shared(client) => client,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use alloy::{
+    primitives::{Address, Bytes, B256, U256},
+    sol_types::{eip712_domain, Eip712Domain, SolStruct},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{abi, alloy_structs, convert};
+
+/// The EIP-712 domain name the `Forwarder` is deployed with.
+pub const FORWARDER_NAME: &str = "GSNv2 Forwarder";
+
+/// The EIP-712 domain version the `Forwarder` is deployed with.
+pub const FORWARDER_VERSION: &str = "0.0.1";
+
+/// Returns the default EIP-712 domain of the `Forwarder` deployed at `verifying_contract`.
+///
+/// This assumes the deployment uses [`FORWARDER_NAME`] and [`FORWARDER_VERSION`]; use
+/// [`ForwarderDomains`](super::ForwarderDomains) to read the actual domain from the chain.
+pub fn forwarder_domain(chain_id: u64, verifying_contract: Address) -> Eip712Domain {
+    eip712_domain! {
+        name: FORWARDER_NAME,
+        version: FORWARDER_VERSION,
+        chain_id: chain_id,
+        verifying_contract: verifying_contract,
+    }
+}
+
+/// A `Forwarder.ForwardRequest`.
+///
+/// This is the canonical representation used throughout the crate. It converts losslessly to and
+/// from both the ethers `abigen!` struct (used to call `Forwarder.execute`) and the alloy `sol!`
+/// structs (used for EIP-712 signing and by the alloy provider layer).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ForwardRequest {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U256,
+    pub nonce: U256,
+    pub data: Bytes,
+}
+
+impl ForwardRequest {
+    /// Returns the EIP-712 hash that the `from` account signs for this request.
+    pub fn eip712_signing_hash(&self, domain: &Eip712Domain) -> B256 {
+        alloy_structs::ForwardRequest::from(self.clone()).eip712_signing_hash(domain)
+    }
+}
+
+impl From<abi::forwarder::ForwardRequest> for ForwardRequest {
+    fn from(req: abi::forwarder::ForwardRequest) -> Self {
+        Self {
+            from: convert::to_alloy_address(req.from),
+            to: convert::to_alloy_address(req.to),
+            value: convert::to_alloy_u256(req.value),
+            gas: convert::to_alloy_u256(req.gas),
+            nonce: convert::to_alloy_u256(req.nonce),
+            data: convert::to_alloy_bytes(req.data),
+        }
+    }
+}
+
+impl From<ForwardRequest> for abi::forwarder::ForwardRequest {
+    fn from(req: ForwardRequest) -> Self {
+        Self {
+            from: convert::to_ethers_address(req.from),
+            to: convert::to_ethers_address(req.to),
+            value: convert::to_ethers_u256(req.value),
+            gas: convert::to_ethers_u256(req.gas),
+            nonce: convert::to_ethers_u256(req.nonce),
+            data: convert::to_ethers_bytes(req.data),
+        }
+    }
+}
+
+impl From<alloy_structs::ForwardRequest> for ForwardRequest {
+    fn from(req: alloy_structs::ForwardRequest) -> Self {
+        Self {
+            from: req.from,
+            to: req.to,
+            value: req.value,
+            gas: req.gas,
+            nonce: req.nonce,
+            data: req.data,
+        }
+    }
+}
+
+impl From<ForwardRequest> for alloy_structs::ForwardRequest {
+    fn from(req: ForwardRequest) -> Self {
+        Self {
+            from: req.from,
+            to: req.to,
+            value: req.value,
+            gas: req.gas,
+            nonce: req.nonce,
+            data: req.data,
+        }
+    }
+}
+
+impl From<alloy_structs::Forwarder::ForwardRequest> for ForwardRequest {
+    fn from(req: alloy_structs::Forwarder::ForwardRequest) -> Self {
+        Self {
+            from: req.from,
+            to: req.to,
+            value: req.value,
+            gas: req.gas,
+            nonce: req.nonce,
+            data: req.data,
+        }
+    }
+}
+
+impl From<ForwardRequest> for alloy_structs::Forwarder::ForwardRequest {
+    fn from(req: ForwardRequest) -> Self {
+        Self {
+            from: req.from,
+            to: req.to,
+            value: req.value,
+            gas: req.gas,
+            nonce: req.nonce,
+            data: req.data,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, Bytes, U256},
+    providers::Provider,
+    rpc::types::TransactionRequest,
+    transports::TransportResult,
+};
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, BlockId, Eip1559TransactionRequest},
+};
+use thiserror::Error;
+
+use super::convert;
+
+/// Gas `Forwarder.execute` spends besides the inner call: decoding the request, hashing it,
+/// `ecrecover`, the cold nonce read and write, the cold `CALL` to the target with an optional
+/// value transfer, and the return data.
+pub const EXECUTE_OVERHEAD: u64 = 60_000;
+
+const TX_BASE_GAS: u64 = 21_000;
+const ZERO_BYTE_GAS: u64 = 4;
+const NON_ZERO_BYTE_GAS: u64 = 16;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GasError {
+    #[error(
+        "ForwardRequest.gas of {requested} is too low: the inner call needs at least {required}"
+    )]
+    GasTooLow { requested: U256, required: U256 },
+}
+
+/// Returns the intrinsic gas of a call transaction with `data`.
+pub fn intrinsic_gas(data: &[u8]) -> u64 {
+    data.iter().fold(TX_BASE_GAS, |gas, byte| {
+        gas + if *byte == 0 {
+            ZERO_BYTE_GAS
+        } else {
+            NON_ZERO_BYTE_GAS
+        }
+    })
+}
+
+/// Returns the calldata `Forwarder.execute` calls the target with: `data` with the 20-byte
+/// `from` appended, as read by `ERC2771Context._msgSender()`.
+pub fn forwarded_calldata(data: &[u8], from: Address) -> Bytes {
+    [data, from.as_slice()].concat().into()
+}
+
+/// Returns the gas the inner call needs, from the estimate of a transaction that makes the same
+/// call as the forwarder would (see [`estimate_inner_gas`]).
+///
+/// The estimate covers a whole transaction, so its intrinsic gas is taken off: a `CALL` does not
+/// pay it.
+pub fn inner_gas(estimate: u64, forwarded_calldata: &[u8]) -> U256 {
+    U256::from(estimate.saturating_sub(intrinsic_gas(forwarded_calldata)))
+}
+
+/// Returns the `ForwardRequest.gas` to sign: `requested` if the caller set one, `required`
+/// otherwise.
+///
+/// A request with less than `required` can never succeed, since the target runs out of gas.
+pub fn request_gas(required: U256, requested: Option<U256>) -> Result<U256, GasError> {
+    match requested {
+        Some(requested) if requested < required => Err(GasError::GasTooLow {
+            requested,
+            required,
+        }),
+        Some(requested) => Ok(requested),
+        None => Ok(required),
+    }
+}
+
+/// Returns the gas limit of the gas wallet's `execute` transaction for a request with `req_gas`,
+/// given the `execute` calldata.
+///
+/// `CALL` forwards at most 63/64 of the remaining gas, and `execute` asserts
+/// `gasleft() > req.gas / 63` afterwards to catch a target that was given less than `req.gas`.
+/// So `req.gas * 64 / 63` has to be left at the `CALL`, on top of the intrinsic gas and
+/// [`EXECUTE_OVERHEAD`].
+pub fn outer_gas_limit(req_gas: U256, execute_calldata: &[u8]) -> U256 {
+    let at_call = (req_gas * U256::from(64)).div_ceil(U256::from(63));
+    at_call + U256::from(intrinsic_gas(execute_calldata) + EXECUTE_OVERHEAD)
+}
+
+/// Estimates the gas of the inner call of a `ForwardRequest` through an ethers client, by
+/// simulating it as made by the forwarder: from `forwarder`, with `from` appended to `data`.
+///
+/// The simulated call carries `value` from the forwarder, so it needs the forwarder to hold at
+/// least `value` at `block`.
+pub async fn estimate_inner_gas<M: Middleware>(
+    client: &M,
+    forwarder: Address,
+    from: Address,
+    to: Address,
+    value: U256,
+    data: &[u8],
+    block: Option<BlockId>,
+) -> Result<U256, M::Error> {
+    let calldata = forwarded_calldata(data, from);
+    let tx: TypedTransaction = Eip1559TransactionRequest::new()
+        .from(convert::to_ethers_address(forwarder))
+        .to(convert::to_ethers_address(to))
+        .value(convert::to_ethers_u256(value))
+        .data(convert::to_ethers_bytes(calldata.clone()))
+        .into();
+
+    let estimate = client.estimate_gas(&tx, block).await?;
+    Ok(inner_gas(estimate.as_u64(), &calldata))
+}
+
+/// Like [`estimate_inner_gas`], but through an alloy provider.
+pub async fn estimate_inner_gas_alloy<P: Provider>(
+    provider: &P,
+    forwarder: Address,
+    from: Address,
+    to: Address,
+    value: U256,
+    data: &[u8],
+) -> TransportResult<U256> {
+    let calldata = forwarded_calldata(data, from);
+    let tx = TransactionRequest::default()
+        .with_from(forwarder)
+        .with_to(to)
+        .with_value(value)
+        .with_input(calldata.clone());
+
+    let estimate = provider.estimate_gas(tx).await?;
+    Ok(inner_gas(estimate, &calldata))
+}
@@ -0,0 +1,308 @@
+use alloy::{
+    network::{Ethereum, TransactionBuilder},
+    primitives::{Address, U256},
+    providers::{PendingTransactionBuilder, Provider, ProviderLayer, RootProvider, SendableTx},
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+    transports::{TransportErrorKind, TransportResult},
+};
+use async_trait::async_trait;
+use thiserror::Error;
+
+use super::{
+    alloy_structs::Forwarder, gas, nonce, DomainError, ForwardRequest, ForwardRequestVerifier,
+    ForwarderDomains, ForwarderNonces, GasError, MetaSigner, NonceReservation, RevertDecoder,
+    RevertReason, VerifyError,
+};
+
+/// An alloy [`ProviderLayer`] that turns every transaction sent through it into a
+/// `Forwarder.execute` meta-transaction.
+///
+/// This is the alloy counterpart of [`EIP2771GasRelayerMiddleware`](super::EIP2771GasRelayerMiddleware).
+/// It must wrap a provider that already signs and fills transactions for the gas wallet (e.g. one
+/// built with `ProviderBuilder::new().wallet(gas_wallet)`), because the rewritten transaction is
+/// handed to that provider to fill the gas wallet's nonce and fees:
+///
+/// ```ignore
+/// let gas_provider = ProviderBuilder::new().wallet(gas_wallet).on_http(url);
+/// let provider = EIP2771GasRelayerLayer::new(meta_signer, forwarder).layer(gas_provider);
+/// let counter = CounterByAddress::new(counter, provider);
+/// counter.increment().send().await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct EIP2771GasRelayerLayer<S> {
+    /// This is the signer that will sign the meta-transaction. This is NOT the signer that
+    /// will send the transaction to the Forwarder contract.
+    transaction_signer: S,
+    forwarder: Address,
+    domains: ForwarderDomains,
+    nonces: ForwarderNonces,
+    reverts: RevertDecoder,
+}
+
+impl<S> EIP2771GasRelayerLayer<S> {
+    pub fn new(transaction_signer: S, forwarder: Address) -> Self {
+        Self {
+            transaction_signer,
+            forwarder,
+            domains: ForwarderDomains::new(),
+            nonces: ForwarderNonces::new(),
+            reverts: RevertDecoder::new(),
+        }
+    }
+
+    /// Shares a cache of forwarder domains, e.g. between several layers.
+    pub fn with_domains(mut self, domains: ForwarderDomains) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Shares the forwarder nonces, e.g. between several layers for the same meta signer.
+    ///
+    /// Providers produced by the same layer already share them.
+    pub fn with_nonces(mut self, nonces: ForwarderNonces) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    /// Decodes reverts with `reverts`, e.g. one that knows the custom errors of more targets.
+    pub fn with_revert_decoder(mut self, reverts: RevertDecoder) -> Self {
+        self.reverts = reverts;
+        self
+    }
+}
+
+impl<P, S> ProviderLayer<P> for EIP2771GasRelayerLayer<S>
+where
+    P: Provider,
+    S: MetaSigner + Clone,
+{
+    type Provider = EIP2771GasRelayerProvider<P, S>;
+
+    fn layer(&self, inner: P) -> Self::Provider {
+        EIP2771GasRelayerProvider {
+            inner,
+            transaction_signer: self.transaction_signer.clone(),
+            forwarder: self.forwarder,
+            domains: self.domains.clone(),
+            nonces: self.nonces.clone(),
+            reverts: self.reverts.clone(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EIP2771GasRelayerLayerError {
+    #[error("{0}")]
+    SignerError(String),
+
+    #[error("Failed to get nonce: {0}")]
+    FailedToGetNonce(String),
+
+    #[error("Failed to estimate gas: {0}")]
+    FailedToEstimateGas(String),
+
+    #[error("{0}")]
+    Reverted(RevertReason),
+
+    #[error("Failed to get chain ID: {0}")]
+    MissingChainID(String),
+
+    #[error("Missing to address")]
+    MissingToAddress,
+
+    #[error("{0}")]
+    DomainError(DomainError),
+
+    #[error("Meta-transaction would be rejected by the forwarder: {0}")]
+    VerificationFailed(VerifyError),
+
+    #[error("{0}")]
+    GasError(GasError),
+}
+
+impl From<EIP2771GasRelayerLayerError> for alloy::transports::TransportError {
+    fn from(e: EIP2771GasRelayerLayerError) -> Self {
+        TransportErrorKind::custom(e)
+    }
+}
+
+/// The provider produced by [`EIP2771GasRelayerLayer`].
+#[derive(Debug, Clone)]
+pub struct EIP2771GasRelayerProvider<P, S> {
+    inner: P,
+    transaction_signer: S,
+    forwarder: Address,
+    domains: ForwarderDomains,
+    nonces: ForwarderNonces,
+    reverts: RevertDecoder,
+}
+
+impl<P, S> EIP2771GasRelayerProvider<P, S>
+where
+    P: Provider,
+    S: MetaSigner,
+{
+    /// The forwarder nonces handed out to the meta signer.
+    pub fn nonces(&self) -> &ForwarderNonces {
+        &self.nonces
+    }
+
+    /// Signs `tx` as a `ForwardRequest` from the meta signer and returns the transaction that
+    /// submits it through `Forwarder.execute`.
+    ///
+    /// The returned reservation holds the forwarder nonce the request was signed with. Commit it
+    /// once the transaction has been broadcast; until then, other relays for the same signer wait.
+    pub async fn relay_transaction(
+        &self,
+        tx: TransactionRequest,
+    ) -> Result<(TransactionRequest, NonceReservation), EIP2771GasRelayerLayerError> {
+        let from = self.transaction_signer.address();
+        let to = tx
+            .to
+            .and_then(|to| to.to().copied())
+            .ok_or(EIP2771GasRelayerLayerError::MissingToAddress)?;
+
+        // Reserve the next nonce of the transaction signer
+        let reservation = self
+            .nonces
+            .reserve_alloy(&self.inner, self.forwarder, from)
+            .await
+            .map_err(|e| EIP2771GasRelayerLayerError::FailedToGetNonce(e.to_string()))?;
+        let nonce = reservation.nonce();
+
+        let value = tx.value.unwrap_or_default();
+        let data = tx.input.input().cloned().unwrap_or_default();
+
+        // Estimate the gas needed for the inner call, as the forwarder makes it. A gas limit set
+        // on the transaction is taken as the requested `ForwardRequest.gas`.
+        let required =
+            gas::estimate_inner_gas_alloy(&self.inner, self.forwarder, from, to, value, &data)
+                .await
+                .map_err(|e| {
+                    match e
+                        .as_error_resp()
+                        .and_then(|payload| payload.as_revert_data())
+                    {
+                        // The target was called directly, so its revert data is intact.
+                        Some(data) => {
+                            EIP2771GasRelayerLayerError::Reverted(self.reverts.decode_target(&data))
+                        }
+                        None => EIP2771GasRelayerLayerError::FailedToEstimateGas(e.to_string()),
+                    }
+                })?;
+        let gas = gas::request_gas(required, tx.gas.map(U256::from))
+            .map_err(EIP2771GasRelayerLayerError::GasError)?;
+
+        let chain_id = match tx.chain_id {
+            Some(chain_id) => chain_id,
+            None => self
+                .inner
+                .get_chain_id()
+                .await
+                .map_err(|e| EIP2771GasRelayerLayerError::MissingChainID(e.to_string()))?,
+        };
+
+        let request = ForwardRequest {
+            from,
+            to,
+            value,
+            gas,
+            nonce,
+            data,
+        };
+
+        let domain = self
+            .domains
+            .get_alloy(&self.inner, self.forwarder, chain_id)
+            .await
+            .map_err(EIP2771GasRelayerLayerError::DomainError)?;
+
+        // Use the meta wallet to sign the request
+        let signature = self
+            .transaction_signer
+            .sign_forward_request(&request, &domain)
+            .await
+            .map_err(|e| EIP2771GasRelayerLayerError::SignerError(e.to_string()))?;
+
+        // Replay `Forwarder.verify` against the forwarder's own nonce, so that a local nonce that
+        // is out of step is diagnosed, and read again, before any gas is spent on `execute`.
+        let expected = nonce::get_nonce_alloy(&self.inner, self.forwarder, from)
+            .await
+            .map_err(EIP2771GasRelayerLayerError::FailedToGetNonce)?;
+        if let Err(e) =
+            ForwardRequestVerifier::new(domain).verify(&request, &signature.as_bytes(), expected)
+        {
+            if matches!(e, VerifyError::NonceMismatch { .. }) {
+                reservation.resync();
+            }
+            return Err(EIP2771GasRelayerLayerError::VerificationFailed(e));
+        }
+
+        let execute = Forwarder::executeCall {
+            req: request.into(),
+            signature: signature.as_bytes().into(),
+        }
+        .abi_encode();
+        let outer_gas = gas::outer_gas_limit(gas, &execute);
+
+        // The forwarder passes `req.value` on to the target, so the outer transaction has to
+        // carry it. Everything else about the outer transaction is filled in for the gas wallet
+        // by the inner provider.
+        let tx = TransactionRequest::default()
+            .with_to(self.forwarder)
+            .with_value(value)
+            .with_chain_id(chain_id)
+            .with_gas_limit(outer_gas.saturating_to())
+            .with_input(execute);
+        Ok((tx, reservation))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<P, S> Provider for EIP2771GasRelayerProvider<P, S>
+where
+    P: Provider,
+    S: MetaSigner,
+{
+    fn root(&self) -> &RootProvider<Ethereum> {
+        self.inner.root()
+    }
+
+    async fn send_transaction_internal(
+        &self,
+        tx: SendableTx<Ethereum>,
+    ) -> TransportResult<PendingTransactionBuilder<Ethereum>> {
+        match tx {
+            SendableTx::Builder(tx) => {
+                let (tx, reservation) = self.relay_transaction(tx).await?;
+                let sent = self
+                    .inner
+                    .send_transaction_internal(SendableTx::Builder(tx))
+                    .await;
+                match sent {
+                    Ok(pending) => {
+                        reservation.commit();
+                        Ok(pending)
+                    }
+                    Err(e) => {
+                        reservation.resync();
+                        match e
+                            .as_error_resp()
+                            .and_then(|payload| payload.as_revert_data())
+                        {
+                            Some(data) => Err(EIP2771GasRelayerLayerError::Reverted(
+                                self.reverts.decode_execute(&data),
+                            )
+                            .into()),
+                            None => Err(e),
+                        }
+                    }
+                }
+            }
+            // Already signed by someone else, so there is nothing to relay.
+            SendableTx::Envelope(_) => self.inner.send_transaction_internal(tx).await,
+        }
+    }
+}
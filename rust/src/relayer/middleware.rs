@@ -0,0 +1,514 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    contract::ContractError,
+    providers::{Middleware, MiddlewareError, PendingTransaction},
+    types::{
+        transaction::eip2718::TypedTransaction, BlockId, BlockNumber, Bytes,
+        Eip1559TransactionRequest, Eip2930TransactionRequest, TransactionRequest,
+    },
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    abi, convert, gas, nonce, revert::revert_data, Allowlist, Budget, BudgetError, DomainError,
+    EscalationPolicy, FeeError, FeeStrategy, ForwardRequest, ForwardRequestVerifier,
+    ForwarderDomains, ForwarderNonces, GasError, GasWalletPool, MetaSigner, MetaTransactionOptions,
+    NonceReservation, PolicyError, PoolError, PreflightError, QueueError, RateLimitError,
+    RelayError, RelayTransport, RelayedTransaction, Relayer, RevertDecoder, RevertReason,
+    TransportError, VerifyError,
+};
+
+#[derive(Debug)]
+pub struct EIP2771GasRelayerMiddleware<M> {
+    inner: M,
+    /// This is the signer that will sign the meta-transaction. This is NOT the signer that
+    /// will send the transaction to the Forwarder contract.
+    transaction_signer: Arc<dyn MetaSigner>,
+    /// Submits the signed requests from the gas wallet, unless a transport is set.
+    relayer: Relayer<M>,
+    transport: Option<Arc<dyn RelayTransport>>,
+    nonces: ForwarderNonces,
+}
+
+/// The type of the gas wallet's `execute` transaction, independent of the type of the
+/// transaction being relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OuterTransactionType {
+    /// For chains without EIP-1559 (London).
+    Legacy,
+    Eip2930,
+    Eip1559,
+}
+
+impl OuterTransactionType {
+    /// Converts `tx` to this type, keeping its sender, recipient, gas limit, value, data, nonce,
+    /// chain id and access list. Fees are dropped, since they are priced differently.
+    pub fn convert(self, tx: TypedTransaction) -> TypedTransaction {
+        let access_list = tx.access_list().cloned().unwrap_or_default();
+        let mut request = TransactionRequest::new();
+        request.from = tx.from().copied();
+        request.to = tx.to().cloned();
+        request.gas = tx.gas().copied();
+        request.value = tx.value().copied();
+        request.data = tx.data().cloned();
+        request.nonce = tx.nonce().copied();
+        request.chain_id = tx.chain_id();
+
+        match self {
+            OuterTransactionType::Legacy => request.into(),
+            OuterTransactionType::Eip2930 => {
+                Eip2930TransactionRequest::new(request, access_list).into()
+            }
+            OuterTransactionType::Eip1559 => {
+                let mut eip1559 = Eip1559TransactionRequest::new().access_list(access_list);
+                eip1559.from = request.from;
+                eip1559.to = request.to;
+                eip1559.gas = request.gas;
+                eip1559.value = request.value;
+                eip1559.data = request.data;
+                eip1559.nonce = request.nonce;
+                eip1559.chain_id = request.chain_id;
+                eip1559.into()
+            }
+        }
+    }
+}
+
+impl<M> EIP2771GasRelayerMiddleware<M> {
+    pub fn new<S: MetaSigner + 'static>(
+        inner: M,
+        transaction_signer: S,
+        forwarder_with_gas_signer: abi::Forwarder<M>,
+    ) -> Self {
+        let nonces = ForwarderNonces::new();
+        Self {
+            inner,
+            transaction_signer: Arc::new(transaction_signer),
+            relayer: Relayer::new(forwarder_with_gas_signer).with_nonces(nonces.clone()),
+            transport: None,
+            nonces,
+        }
+    }
+
+    /// Shares a cache of forwarder domains, e.g. between several middlewares.
+    pub fn with_domains(mut self, domains: ForwarderDomains) -> Self {
+        self.relayer = self.relayer.with_domains(domains);
+        self
+    }
+
+    /// Shares the forwarder nonces, e.g. between several middlewares for the same meta signer.
+    pub fn with_nonces(mut self, nonces: ForwarderNonces) -> Self {
+        self.relayer = self.relayer.with_nonces(nonces.clone());
+        self.nonces = nonces;
+        self
+    }
+
+    /// The forwarder nonces handed out to the meta signer.
+    pub fn nonces(&self) -> &ForwarderNonces {
+        &self.nonces
+    }
+
+    /// Only relays transactions that `allowlist` allows (see [`Relayer::with_allowlist`]).
+    pub fn with_allowlist(mut self, allowlist: Allowlist) -> Self {
+        self.relayer = self.relayer.with_allowlist(allowlist);
+        self
+    }
+
+    /// Caps the gas sponsored for the meta signer (see [`Relayer::with_budget`]).
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.relayer = self.relayer.with_budget(budget);
+        self
+    }
+
+    /// Pays for the requests from the wallets of `pool` (see [`Relayer::with_gas_wallets`]).
+    pub fn with_gas_wallets(mut self, pool: GasWalletPool<M>) -> Self {
+        self.relayer = self.relayer.with_gas_wallets(pool);
+        self
+    }
+
+    /// The relayer that submits the signed requests from the gas wallet.
+    pub fn relayer(&self) -> &Relayer<M> {
+        &self.relayer
+    }
+
+    /// Delivers the signed requests with `transport`, e.g. to a remote relayer, instead of
+    /// submitting them from the gas wallet. The gas wallet is then only used to read the
+    /// forwarder's address, and the fee, escalation, transaction type and preflight settings are
+    /// the transport's.
+    pub fn with_transport<T: RelayTransport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Prices the gas wallet's `execute` transactions with `strategy`, instead of leaving the
+    /// fees to the gas wallet's client.
+    pub fn with_fee_strategy(mut self, strategy: FeeStrategy) -> Self {
+        self.relayer = self.relayer.with_fee_strategy(strategy);
+        self
+    }
+
+    /// Rebroadcasts `execute` transactions that are not getting mined with higher fees. Only
+    /// the handles returned by [`send_meta_transaction`](Self::send_meta_transaction) escalate.
+    pub fn with_escalation(mut self, policy: EscalationPolicy) -> Self {
+        self.relayer = self.relayer.with_escalation(policy);
+        self
+    }
+
+    /// Sends the gas wallet's `execute` transactions as `transaction_type`, e.g. legacy on a
+    /// chain without EIP-1559. By default they are EIP-1559 transactions.
+    pub fn with_transaction_type(mut self, transaction_type: OuterTransactionType) -> Self {
+        self.relayer = self.relayer.with_transaction_type(transaction_type);
+        self
+    }
+
+    /// Decodes reverts with `reverts`, e.g. one that knows the custom errors of more targets.
+    pub fn with_revert_decoder(mut self, reverts: RevertDecoder) -> Self {
+        self.relayer = self.relayer.with_revert_decoder(reverts);
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EIP2771GasRelayerMiddlewareError<M: Middleware> {
+    #[error("{0}")]
+    SignerError(String),
+
+    #[error("{0}")]
+    MiddlewareError(M::Error),
+
+    #[error("{0}")]
+    Reverted(RevertReason),
+
+    #[error("{0}")]
+    PreflightFailed(PreflightError),
+
+    #[error("{0}")]
+    PolicyRejected(PolicyError),
+
+    #[error("{0}")]
+    OverBudget(BudgetError),
+
+    #[error("{0}")]
+    RateLimited(RateLimitError),
+
+    #[error("{0}")]
+    Queue(QueueError),
+
+    #[error("{0}")]
+    GasWallets(PoolError),
+
+    #[error("{0}")]
+    ContractError(ContractError<M>),
+
+    #[error("Failed to get nonce")]
+    FailedToGetNonce(String),
+
+    #[error("{0}")]
+    FailedToEstimateGas(M::Error),
+
+    #[error("Missing chain ID")]
+    MissingChainID(String),
+
+    #[error("{0}")]
+    DomainError(DomainError),
+
+    #[error("Meta-transaction would be rejected by the forwarder: {0}")]
+    VerificationFailed(VerifyError),
+
+    #[error("{0}")]
+    GasError(GasError),
+
+    #[error("{0}")]
+    FeeError(FeeError),
+
+    #[error("{0}")]
+    Transport(TransportError),
+
+    #[error("Missing to address")]
+    MissingToAddress,
+
+    #[error("Missing data")]
+    MissingData,
+
+    #[error("Conversion error")]
+    ConversionError(String),
+}
+
+impl<M> MiddlewareError for EIP2771GasRelayerMiddlewareError<M>
+where
+    M: Middleware,
+{
+    type Inner = M::Error;
+
+    fn from_err(src: M::Error) -> Self {
+        EIP2771GasRelayerMiddlewareError::MiddlewareError(src)
+    }
+
+    fn as_inner(&self) -> Option<&Self::Inner> {
+        match self {
+            EIP2771GasRelayerMiddlewareError::MiddlewareError(e) => Some(e),
+            EIP2771GasRelayerMiddlewareError::FailedToEstimateGas(e) => Some(e),
+            EIP2771GasRelayerMiddlewareError::ContractError(e) => e.as_middleware_error(),
+            _ => None,
+        }
+    }
+}
+
+impl<M: Middleware> From<RelayError<M>> for EIP2771GasRelayerMiddlewareError<M> {
+    fn from(e: RelayError<M>) -> Self {
+        match e {
+            RelayError::PolicyRejected(e) => Self::PolicyRejected(e),
+            RelayError::OverBudget(e) => Self::OverBudget(e),
+            RelayError::RateLimited(e) => Self::RateLimited(e),
+            RelayError::Queue(e) => Self::Queue(e),
+            RelayError::GasWallets(e) => Self::GasWallets(e),
+            RelayError::MissingChainID(e) => Self::MissingChainID(e.to_string()),
+            RelayError::FailedToGetNonce(e) => Self::FailedToGetNonce(e),
+            RelayError::DomainError(e) => Self::DomainError(e),
+            RelayError::VerificationFailed(e) => Self::VerificationFailed(e),
+            RelayError::FailedToEstimateGas(e) => Self::FailedToEstimateGas(e),
+            RelayError::GasError(e) => Self::GasError(e),
+            RelayError::FeeError(e) => Self::FeeError(e),
+            RelayError::PreflightFailed(e) => Self::PreflightFailed(e),
+            RelayError::Reverted(e) => Self::Reverted(e),
+            RelayError::ContractError(e) => Self::ContractError(e),
+        }
+    }
+}
+
+impl<M: Middleware> EIP2771GasRelayerMiddleware<M> {
+    /// Relays `tx` like [`send_transaction`](Middleware::send_transaction), but returns a handle
+    /// that follows the gas wallet's `execute` transaction through fee escalation and resolves to
+    /// a [`MetaTransactionReceipt`](super::MetaTransactionReceipt).
+    ///
+    /// The request is signed here and delivered by the [`RelayTransport`] if one is set (see
+    /// [`with_transport`](Self::with_transport)), and from the gas wallet otherwise.
+    ///
+    /// `send_transaction` has to return a `PendingTransaction` for a single hash, so it cannot
+    /// follow a replacement; use this when an [`EscalationPolicy`] is set.
+    ///
+    /// Before broadcasting, `execute` is simulated from the gas wallet at the pending block, and
+    /// a meta-transaction that would fail is refused with
+    /// [`PreflightFailed`](EIP2771GasRelayerMiddlewareError::PreflightFailed). Set
+    /// [`skip_preflight`](MetaTransactionOptions::skip_preflight) to broadcast it anyway.
+    pub async fn send_meta_transaction<Tx: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: Tx,
+        options: MetaTransactionOptions,
+    ) -> Result<RelayedTransaction<'_>, EIP2771GasRelayerMiddlewareError<M>> {
+        let (reservation, request, signature) = self.sign(tx, options.block).await?;
+
+        // From here on, the request is the relayer's. Only a broadcast uses up the nonce.
+        let relayed = match &self.transport {
+            Some(transport) => transport
+                .send(request, signature, options)
+                .await
+                .map_err(EIP2771GasRelayerMiddlewareError::Transport),
+            None => self
+                .relayer
+                .relay(request, signature, options)
+                .await
+                .map(RelayedTransaction::from)
+                .map_err(Into::into),
+        };
+        match relayed {
+            Ok(relayed) => {
+                reservation.commit();
+                Ok(relayed)
+            }
+            Err(e) => {
+                reservation.resync();
+                Err(e)
+            }
+        }
+    }
+
+    /// Turns `tx` into a `ForwardRequest` at the meta signer's next nonce and signs it.
+    async fn sign<Tx: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: Tx,
+        block: Option<BlockId>,
+    ) -> Result<(NonceReservation, ForwardRequest, Bytes), EIP2771GasRelayerMiddlewareError<M>>
+    {
+        let forwarder = convert::to_alloy_address(self.relayer.forwarder().address());
+        let transaction_signer_address =
+            convert::to_ethers_address(self.transaction_signer.address());
+
+        // Reserve the next nonce of the transaction signer. Concurrent sends for the same signer
+        // wait here until this one has been broadcast.
+        let reservation = self
+            .nonces
+            .reserve(&self.inner, forwarder, self.transaction_signer.address())
+            .await
+            .map_err(|e| EIP2771GasRelayerMiddlewareError::FailedToGetNonce(e.to_string()))?;
+        let nonce = convert::to_ethers_u256(reservation.nonce());
+
+        // Only the call itself is relayed, so any transaction type will do.
+        let typed_tx: TypedTransaction = tx.into();
+
+        let to = convert::to_alloy_address(
+            *typed_tx
+                .to()
+                .ok_or(EIP2771GasRelayerMiddlewareError::MissingToAddress)?
+                .as_address()
+                .ok_or(EIP2771GasRelayerMiddlewareError::ConversionError(
+                    "To is not an address".to_string(),
+                ))?,
+        );
+        let value = convert::to_alloy_u256(typed_tx.value().copied().unwrap_or_default());
+        let data = convert::to_alloy_bytes(
+            typed_tx
+                .data()
+                .cloned()
+                .ok_or(EIP2771GasRelayerMiddlewareError::MissingData)?,
+        );
+
+        // Estimate the gas needed for the inner call, as the forwarder makes it. A gas limit set
+        // on the transaction is taken as the requested `ForwardRequest.gas`.
+        let required = gas::estimate_inner_gas(
+            &self.inner,
+            forwarder,
+            self.transaction_signer.address(),
+            to,
+            value,
+            &data,
+            block,
+        )
+        .await
+        .map_err(|e| match revert_data(&e) {
+            // The target was called directly, so its revert data is intact.
+            Some(data) => EIP2771GasRelayerMiddlewareError::Reverted(
+                self.relayer.reverts().decode_target(&data),
+            ),
+            None => EIP2771GasRelayerMiddlewareError::FailedToEstimateGas(e),
+        })?;
+        let gas = gas::request_gas(
+            required,
+            typed_tx.gas().copied().map(convert::to_alloy_u256),
+        )
+        .map_err(EIP2771GasRelayerMiddlewareError::GasError)?;
+
+        let request = ForwardRequest {
+            from: convert::to_alloy_address(transaction_signer_address),
+            to,
+            value,
+            gas,
+            nonce: convert::to_alloy_u256(nonce),
+            data,
+        };
+
+        // Get the signature over the typed data
+        // Here, we use alloy to generate the typed data signature because there is a bug in ethers-rs that causes
+        // the encoding for the data field (Bytes) to be incorrect.
+        let signature = {
+            let chain_id = {
+                match typed_tx.chain_id() {
+                    Some(chain_id) => chain_id.as_u64(),
+                    None => {
+                        let chain_id = self.inner.get_chainid().await.map_err(|e| {
+                            EIP2771GasRelayerMiddlewareError::MissingChainID(e.to_string())
+                        })?;
+                        chain_id.as_u64()
+                    }
+                }
+            };
+
+            let alloy_domain = self
+                .relayer
+                .domains()
+                .get(&self.inner, forwarder, chain_id)
+                .await
+                .map_err(EIP2771GasRelayerMiddlewareError::DomainError)?;
+
+            // Use the meta wallet to sign the request
+            let alloy_sig = self
+                .transaction_signer
+                .sign_forward_request(&request, &alloy_domain)
+                .await
+                .map_err(|e| EIP2771GasRelayerMiddlewareError::SignerError(e.to_string()))?;
+
+            // Replay `Forwarder.verify` against the forwarder's own nonce, so that a bad signature
+            // or a local nonce that is out of step is diagnosed, and the nonce read again, before
+            // any gas is spent on `execute`.
+            let expected = nonce::get_nonce(
+                &self.inner,
+                forwarder,
+                request.from,
+                BlockNumber::Pending.into(),
+            )
+            .await
+            .map_err(EIP2771GasRelayerMiddlewareError::FailedToGetNonce)?;
+            if let Err(e) = ForwardRequestVerifier::new(alloy_domain).verify(
+                &request,
+                &alloy_sig.as_bytes(),
+                expected,
+            ) {
+                if matches!(e, VerifyError::NonceMismatch { .. }) {
+                    reservation.resync();
+                }
+                return Err(EIP2771GasRelayerMiddlewareError::VerificationFailed(e));
+            }
+
+            Bytes::from(alloy_sig.as_bytes())
+        };
+        Ok((reservation, request, signature))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M> Middleware for EIP2771GasRelayerMiddleware<M>
+where
+    M: Middleware + 'static,
+{
+    type Error = EIP2771GasRelayerMiddlewareError<M>;
+    type Provider = M::Provider;
+    type Inner = M;
+
+    fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    async fn send_transaction<Tx: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: Tx,
+        block: Option<BlockId>,
+    ) -> Result<PendingTransaction<'_, Self::Provider>, Self::Error> {
+        let (reservation, request, signature) = self.sign(tx, block).await?;
+        let options = MetaTransactionOptions {
+            block,
+            ..Default::default()
+        };
+
+        // The `PendingTransaction` only knows the hash, so the gas wallet's transaction is
+        // followed from a task of its own, which records its spend and holds the pool's wallet
+        // until it is mined. A transport's relayer does that on its side.
+        let sent = match &self.transport {
+            Some(transport) => transport
+                .send(request, signature, options)
+                .await
+                .map(|relayed| relayed.tx_hash())
+                .map_err(EIP2771GasRelayerMiddlewareError::Transport),
+            None => self
+                .relayer
+                .relay_in_background(request, signature, options)
+                .await
+                .map(|(tx_hash, _follower)| tx_hash)
+                .map_err(Into::into),
+        };
+        match sent {
+            Ok(tx_hash) => {
+                reservation.commit();
+                Ok(PendingTransaction::new(tx_hash, self.inner().provider()))
+            }
+            Err(e) => {
+                reservation.resync();
+                Err(e)
+            }
+        }
+    }
+}
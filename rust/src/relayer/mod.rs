@@ -0,0 +1,70 @@
+//! EIP-2771 meta-transaction relaying.
+//!
+//! [`EIP2771GasRelayerMiddleware`] signs outgoing transactions as `ForwardRequest`s with a meta
+//! signer and submits them through the `Forwarder` contract using a separate, funded gas wallet.
+//! [`EIP2771GasRelayerTransformerMiddleware`] does the same on top of a stock ethers stack for the
+//! gas wallet, and [`EIP2771GasRelayerLayer`] does the same for alloy providers. [`Relayer`]
+//! submits `ForwardRequest`s that were signed elsewhere, and the [`RelayTransport`]
+//! implementations deliver signed requests to a relayer in-process or over JSON-RPC. A relayer's
+//! [`Allowlist`] limits what it pays for, and its [`Budget`] how much per meta signer. A
+//! [`GasWalletPool`] spreads its `execute` transactions over several gas wallets.
+
+pub mod abi;
+pub mod alloy_structs;
+pub mod budget;
+pub mod convert;
+mod domain;
+mod fees;
+mod forward_request;
+pub mod gas;
+mod layer;
+mod middleware;
+mod nonce;
+pub mod policy;
+pub mod pool;
+pub mod preflight;
+mod queue;
+pub mod rate_limit;
+mod receipt;
+mod relay;
+pub mod revert;
+pub mod rpc;
+mod signer;
+mod transformer;
+mod transport;
+mod verify;
+
+pub use budget::{Budget, BudgetConfig, BudgetError, SpendLedger, SpendRecord, SpendReport};
+pub use domain::{DomainError, ForwarderDomains};
+pub use fees::{EscalatingTransaction, EscalationPolicy, FeeError, FeeStrategy, Fees};
+pub use forward_request::{forwarder_domain, ForwardRequest, FORWARDER_NAME, FORWARDER_VERSION};
+pub use gas::GasError;
+pub use layer::{EIP2771GasRelayerLayer, EIP2771GasRelayerLayerError, EIP2771GasRelayerProvider};
+pub use middleware::{
+    EIP2771GasRelayerMiddleware, EIP2771GasRelayerMiddlewareError, OuterTransactionType,
+};
+pub use nonce::{ForwarderNonces, NonceError, NonceReservation};
+pub use policy::{AllowRule, Allowlist, PolicyError};
+pub use pool::{
+    BalanceMonitor, GasWallet, GasWalletPool, PoolConfig, PoolError, PoolEvent, WalletLease,
+    WalletSelection,
+};
+pub use preflight::{MetaTransactionOptions, PreflightError};
+pub use queue::{QueueError, QueuedRequest, RelayQueue};
+pub use rate_limit::{RateLimit, RateLimitConfig, RateLimitError, RateLimiter};
+pub use receipt::{MetaTransactionReceipt, PendingMetaTransaction, ReceiptError};
+pub use relay::{RelayError, Relayer};
+pub use revert::{CustomError, ForwarderRevert, RevertDecoder, RevertReason, TargetRevert};
+pub use signer::{AlloyMetaSigner, EthersMetaSigner, MetaSigner, MetaSignerError, NodeMetaSigner};
+pub use transformer::{
+    EIP2771GasRelayerTransformer, EIP2771GasRelayerTransformerError,
+    EIP2771GasRelayerTransformerMiddleware,
+};
+pub use transport::{
+    HttpRelayTransport, MockRelayTransport, RelayStatus, RelayTransport, RelayedTransaction,
+    TransportError,
+};
+pub use verify::{
+    forward_request_digest, forward_request_struct_hash, forward_request_typehash,
+    ForwardRequestVerifier, VerifyError, FORWARD_REQUEST_TYPE,
+};
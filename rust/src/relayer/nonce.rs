@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, U256},
+    providers::Provider,
+};
+use ethers::{
+    abi::{AbiDecode, AbiEncode},
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, BlockNumber, Eip1559TransactionRequest},
+};
+use thiserror::Error;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use super::{abi, alloy_structs::Forwarder, convert};
+
+#[derive(Error, Debug)]
+pub enum NonceError {
+    #[error("Failed to read getNonce({from}) from forwarder {forwarder}: {reason}")]
+    Unavailable {
+        forwarder: Address,
+        from: Address,
+        reason: String,
+    },
+}
+
+/// Reads `getNonce(from)` from `forwarder` at `block` through an ethers client.
+pub(super) async fn get_nonce<M: Middleware>(
+    client: &M,
+    forwarder: Address,
+    from: Address,
+    block: ethers::types::BlockId,
+) -> Result<U256, String> {
+    let tx: TypedTransaction = Eip1559TransactionRequest::new()
+        .to(convert::to_ethers_address(forwarder))
+        .data(
+            abi::forwarder::GetNonceCall {
+                from: convert::to_ethers_address(from),
+            }
+            .encode(),
+        )
+        .into();
+    let result = client
+        .call(&tx, Some(block))
+        .await
+        .map_err(|e| e.to_string())?;
+    ethers::types::U256::decode(result)
+        .map(convert::to_alloy_u256)
+        .map_err(|e| e.to_string())
+}
+
+/// Reads `getNonce(from)` from `forwarder` at the pending block through an alloy provider.
+pub(super) async fn get_nonce_alloy<P: Provider>(
+    provider: &P,
+    forwarder: Address,
+    from: Address,
+) -> Result<U256, String> {
+    Forwarder::new(forwarder, provider)
+        .getNonce(from)
+        .block(BlockId::pending())
+        .call()
+        .await
+        .map(|nonce| nonce._0)
+        .map_err(|e| e.to_string())
+}
+
+/// The next forwarder nonce of one meta signer, or `None` until it has been read from the chain.
+type Slot = Arc<AsyncMutex<Option<U256>>>;
+
+/// Hands out forwarder nonces per meta signer without calling `getNonce` on every send.
+///
+/// The nonce is read from the forwarder (at the pending block) on first use and then incremented
+/// locally. A [`NonceReservation`] holds the signer's nonce until it is committed or dropped, so
+/// that concurrent sends for the same signer are signed and submitted one after the other, in
+/// nonce order. This matters because `Forwarder.execute` only accepts `_nonces[from]` exactly: the
+/// gas wallet's transactions have to be mined in the same order as the nonces they carry.
+///
+/// Clones share the same nonces.
+#[derive(Debug, Clone, Default)]
+pub struct ForwarderNonces {
+    slots: Arc<Mutex<HashMap<(Address, Address), Slot>>>,
+}
+
+impl ForwarderNonces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn slot(&self, forwarder: Address, from: Address) -> Slot {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry((forwarder, from))
+            .or_default()
+            .clone()
+    }
+
+    /// Reserves the next nonce of `from` on `forwarder`, reading it through an ethers client if it
+    /// is not known yet.
+    ///
+    /// Waits for any earlier reservation for the same signer to be committed or dropped.
+    pub async fn reserve<M: Middleware>(
+        &self,
+        client: &M,
+        forwarder: Address,
+        from: Address,
+    ) -> Result<NonceReservation, NonceError> {
+        self.reserve_with(
+            forwarder,
+            from,
+            get_nonce(client, forwarder, from, BlockNumber::Pending.into()),
+        )
+        .await
+    }
+
+    /// Like [`reserve`](Self::reserve), but reads the nonce through an alloy provider.
+    pub async fn reserve_alloy<P: Provider>(
+        &self,
+        provider: &P,
+        forwarder: Address,
+        from: Address,
+    ) -> Result<NonceReservation, NonceError> {
+        self.reserve_with(forwarder, from, get_nonce_alloy(provider, forwarder, from))
+            .await
+    }
+
+    async fn reserve_with(
+        &self,
+        forwarder: Address,
+        from: Address,
+        fetch: impl Future<Output = Result<U256, String>>,
+    ) -> Result<NonceReservation, NonceError> {
+        let mut slot = self.slot(forwarder, from).lock_owned().await;
+
+        let nonce = match *slot {
+            Some(nonce) => nonce,
+            None => {
+                let nonce = fetch.await.map_err(|reason| NonceError::Unavailable {
+                    forwarder,
+                    from,
+                    reason,
+                })?;
+                *slot = Some(nonce);
+                nonce
+            }
+        };
+
+        Ok(NonceReservation { nonce, slot })
+    }
+
+    /// Forgets the local nonce of `from`, so that the next reservation reads it from the forwarder
+    /// again.
+    ///
+    /// Call this when a relayed transaction was dropped, reverted or reorged out, since every
+    /// later nonce is then out of step with the forwarder.
+    pub async fn resync(&self, forwarder: Address, from: Address) {
+        *self.slot(forwarder, from).lock().await = None;
+    }
+}
+
+/// A forwarder nonce held for one meta-transaction.
+///
+/// While it is alive, no other reservation can be made for the same signer. Call
+/// [`commit`](Self::commit) once the transaction carrying the nonce has been broadcast, or
+/// [`resync`](Self::resync) if submitting it failed. Dropping it releases the nonce unused.
+#[derive(Debug)]
+pub struct NonceReservation {
+    nonce: U256,
+    slot: OwnedMutexGuard<Option<U256>>,
+}
+
+impl NonceReservation {
+    pub fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    /// Marks the nonce as used, so that the next reservation gets the one after it.
+    pub fn commit(mut self) {
+        *self.slot = Some(self.nonce + U256::from(1));
+    }
+
+    /// Forgets the local nonce, so that the next reservation reads it from the forwarder again.
+    pub fn resync(mut self) {
+        *self.slot = None;
+    }
+}
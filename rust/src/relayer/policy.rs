@@ -0,0 +1,208 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+
+use alloy::primitives::{keccak256, Address, FixedBytes, U256};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+use super::ForwardRequest;
+
+/// The 4-byte function selector at the start of a call's data.
+pub type Selector = FixedBytes<4>;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyError {
+    #[error("No allowlist rule covers selector {selector:?} on {target}")]
+    NotAllowed {
+        target: Address,
+        /// `None` if the call data is shorter than a selector.
+        selector: Option<Selector>,
+    },
+
+    #[error("Allowlist rule {rule} caps value at {max}, request sends {value}")]
+    ValueTooHigh {
+        rule: String,
+        value: U256,
+        max: U256,
+    },
+
+    #[error("Allowlist rule {rule} caps gas at {max}, request asks for {gas}")]
+    GasTooHigh { rule: String, gas: U256, max: U256 },
+}
+
+/// Allows calls to one function of one contract.
+///
+/// In the config file, `selector` is either the 4-byte selector in hex or the function's
+/// signature:
+///
+/// ```toml
+/// [[profiles.local.allowlist]]
+/// name = "counter-increment"
+/// target = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+/// selector = "increment()"
+/// max_value = "0"
+/// max_gas = 100_000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowRule {
+    /// Names the rule in rejections.
+    pub name: String,
+    pub target: Address,
+    #[serde(deserialize_with = "deserialize_selector")]
+    pub selector: Selector,
+    /// The most wei a request may send. `None` allows any value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<U256>,
+    /// The most gas a request may give the call. `None` allows any gas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_gas: Option<u64>,
+}
+
+impl AllowRule {
+    pub fn new(name: impl Into<String>, target: Address, selector: Selector) -> Self {
+        Self {
+            name: name.into(),
+            target,
+            selector,
+            max_value: None,
+            max_gas: None,
+        }
+    }
+
+    /// A rule for the function with `signature`, e.g. `"increment()"`.
+    pub fn function(name: impl Into<String>, target: Address, signature: &str) -> Self {
+        Self::new(name, target, function_selector(signature))
+    }
+
+    pub fn with_max_value(mut self, max_value: U256) -> Self {
+        self.max_value = Some(max_value);
+        self
+    }
+
+    pub fn with_max_gas(mut self, max_gas: u64) -> Self {
+        self.max_gas = Some(max_gas);
+        self
+    }
+
+    /// Whether the rule is for the contract and function `request` calls.
+    pub fn covers(&self, request: &ForwardRequest) -> bool {
+        request.to == self.target && selector(request) == Some(self.selector)
+    }
+
+    /// Checks the caps of a request the rule covers.
+    pub fn check(&self, request: &ForwardRequest) -> Result<(), PolicyError> {
+        if let Some(max) = self.max_value {
+            if request.value > max {
+                return Err(PolicyError::ValueTooHigh {
+                    rule: self.name.clone(),
+                    value: request.value,
+                    max,
+                });
+            }
+        }
+        if let Some(max) = self.max_gas {
+            if request.gas > U256::from(max) {
+                return Err(PolicyError::GasTooHigh {
+                    rule: self.name.clone(),
+                    gas: request.gas,
+                    max: U256::from(max),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The selector of the function with `signature`, e.g. `"increment()"`.
+pub fn function_selector(signature: &str) -> Selector {
+    Selector::from_slice(&keccak256(signature.as_bytes())[..4])
+}
+
+fn selector(request: &ForwardRequest) -> Option<Selector> {
+    request.data.get(..4).map(Selector::from_slice)
+}
+
+fn deserialize_selector<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Selector, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if value.contains('(') {
+        return Ok(function_selector(&value));
+    }
+    value.parse::<Selector>().map_err(|_| {
+        de::Error::custom(format!(
+            "invalid selector {value}: expected 4 bytes in hex or a function signature"
+        ))
+    })
+}
+
+/// The `(target, selector)` pairs a relayer pays gas for.
+///
+/// Clones share the rules, so a relayer's allowlist can be [`replace`](Self::replace)d while it
+/// runs, e.g. after the config file changed. An allowlist without rules rejects every request,
+/// unless it was told to [allow any](Self::set_allow_any) request.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    rules: Arc<RwLock<Vec<AllowRule>>>,
+    allow_any: Arc<AtomicBool>,
+}
+
+impl Allowlist {
+    pub fn new(rules: Vec<AllowRule>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            allow_any: Arc::default(),
+        }
+    }
+
+    /// An allowlist that accepts every request, whatever its rules.
+    pub fn any() -> Self {
+        let allowlist = Self::default();
+        allowlist.set_allow_any(true);
+        allowlist
+    }
+
+    /// Accepts every request from now on if `allow_any` is set, and only what the rules allow
+    /// otherwise.
+    pub fn set_allow_any(&self, allow_any: bool) {
+        self.allow_any.store(allow_any, Ordering::Relaxed);
+    }
+
+    pub fn allows_any(&self) -> bool {
+        self.allow_any.load(Ordering::Relaxed)
+    }
+
+    /// Swaps in new rules for the next requests.
+    pub fn replace(&self, rules: Vec<AllowRule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn rules(&self) -> Vec<AllowRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Accepts `request` if one of the rules that cover it allows its value and gas. Otherwise
+    /// the error names the first covering rule, or says that none covers it.
+    pub fn check(&self, request: &ForwardRequest) -> Result<(), PolicyError> {
+        if self.allows_any() {
+            return Ok(());
+        }
+        let rules = self.rules.read().unwrap();
+        let mut rejection = None;
+        for rule in rules.iter().filter(|rule| rule.covers(request)) {
+            match rule.check(request) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    rejection.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(rejection.unwrap_or(PolicyError::NotAllowed {
+            target: request.to,
+            selector: selector(request),
+        }))
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use alloy::primitives::{Address, U256};
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, BlockId, TransactionRequest, H256},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    sync::{broadcast, Mutex as AsyncMutex},
+    task::JoinHandle,
+};
+
+use super::{abi, convert};
+
+/// How often a [`BalanceMonitor`] checks the balances when the config does not say, in seconds.
+pub const DEFAULT_CHECK_INTERVAL_SECS: u64 = 30;
+
+/// How many events a subscriber that falls behind can miss before it sees a lag.
+const EVENT_CAPACITY: usize = 64;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    #[error("A gas wallet pool needs at least one wallet")]
+    Empty,
+
+    #[error("Gas wallet {index} of the pool has no sender address")]
+    NoSender { index: usize },
+
+    #[error("No gas wallet is in rotation: all {wallets} are below the minimum balance")]
+    NoWalletAvailable { wallets: usize },
+}
+
+/// How a [`GasWalletPool`] picks the wallet for a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WalletSelection {
+    /// Each wallet in turn.
+    #[default]
+    RoundRobin,
+    /// The wallet with the fewest meta-transactions in flight, in turn among equals.
+    LeastPending,
+}
+
+/// A gas wallet pool, as written in the config file:
+///
+/// ```toml
+/// [profiles.local]
+/// gas_wallets = [{ private_key_env = "GAS_KEY_1" }, { private_key_env = "GAS_KEY_2" }]
+/// treasury_wallet = { private_key_env = "TREASURY_KEY" }
+///
+/// [profiles.local.gas_pool]
+/// selection = "least-pending"
+/// min_balance_wei = "50000000000000000"
+/// check_interval_secs = 30
+/// top_up_wei = "200000000000000000"
+/// ```
+///
+/// Without `min_balance_wei`, balances are not monitored. Without `top_up_wei` or a treasury
+/// wallet, wallets below the minimum stay out of rotation until they are funded by hand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub selection: WalletSelection,
+    pub min_balance_wei: Option<U256>,
+    pub check_interval_secs: u64,
+    pub top_up_wei: Option<U256>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            selection: WalletSelection::default(),
+            min_balance_wei: None,
+            check_interval_secs: DEFAULT_CHECK_INTERVAL_SECS,
+            top_up_wei: None,
+        }
+    }
+}
+
+/// What a [`BalanceMonitor`] reports about the wallets of a pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolEvent {
+    /// The wallet's balance fell below the minimum, so it was taken out of rotation.
+    LowBalance {
+        wallet: Address,
+        balance: U256,
+        min_balance: U256,
+    },
+    /// The wallet is back above the minimum, and in rotation again.
+    Restored {
+        wallet: Address,
+        balance: U256,
+    },
+    /// The treasury sent `amount` to the wallet.
+    ToppedUp {
+        wallet: Address,
+        amount: U256,
+        tx_hash: H256,
+    },
+    TopUpFailed {
+        wallet: Address,
+        reason: String,
+    },
+    /// The wallet's balance could not be read. The wallet stays in or out of rotation.
+    CheckFailed {
+        wallet: Address,
+        reason: String,
+    },
+}
+
+impl fmt::Display for PoolEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolEvent::LowBalance {
+                wallet,
+                balance,
+                min_balance,
+            } => write!(
+                f,
+                "Gas wallet {wallet} is out of rotation: its balance of {balance} wei is below {min_balance} wei"
+            ),
+            PoolEvent::Restored { wallet, balance } => write!(
+                f,
+                "Gas wallet {wallet} is back in rotation with {balance} wei"
+            ),
+            PoolEvent::ToppedUp {
+                wallet,
+                amount,
+                tx_hash,
+            } => write!(
+                f,
+                "Topped up gas wallet {wallet} with {amount} wei in {tx_hash:?}"
+            ),
+            PoolEvent::TopUpFailed { wallet, reason } => {
+                write!(f, "Failed to top up gas wallet {wallet}: {reason}")
+            }
+            PoolEvent::CheckFailed { wallet, reason } => {
+                write!(f, "Failed to check the balance of gas wallet {wallet}: {reason}")
+            }
+        }
+    }
+}
+
+/// One gas wallet of a [`GasWalletPool`]: the forwarder connected to the wallet's client, and the
+/// wallet's next nonce.
+#[derive(Debug)]
+pub struct GasWallet<M> {
+    forwarder: abi::Forwarder<M>,
+    address: Address,
+    /// `None` until it has been read from the node.
+    next_nonce: AsyncMutex<Option<ethers::types::U256>>,
+    /// Set when the wallet goes idle, so that the next send reads the nonce from the node again.
+    resync: AtomicBool,
+    pending: AtomicUsize,
+    active: AtomicBool,
+    balance: Mutex<Option<U256>>,
+}
+
+impl<M> GasWallet<M> {
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// The forwarder, connected to the wallet's client.
+    pub fn forwarder(&self) -> &abi::Forwarder<M> {
+        &self.forwarder
+    }
+
+    /// How many requests that were given this wallet are still in flight.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Whether the wallet is in rotation, i.e. its balance was not below the minimum at the
+    /// last check.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// The balance at the last check, or `None` if it has not been checked.
+    pub fn balance(&self) -> Option<U256> {
+        *self.balance.lock().unwrap()
+    }
+}
+
+impl<M: Middleware> GasWallet<M> {
+    /// Fills in `tx` with the wallet's next nonce and broadcasts it from the wallet.
+    ///
+    /// The nonce is read from the node (at `block`) on first use and then counted locally, so
+    /// that the pool's wallets do not wait on each other's nonce lookups. Sends from one wallet
+    /// are made one at a time. A failed send reads the nonce again next time, as does the first
+    /// send after all of the wallet's requests are done.
+    pub async fn send(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<H256, M::Error> {
+        let client = self.forwarder.client_ref();
+        let mut next_nonce = self.next_nonce.lock().await;
+        if self.resync.swap(false, Ordering::SeqCst) {
+            *next_nonce = None;
+        }
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => {
+                client
+                    .get_transaction_count(convert::to_ethers_address(self.address), block)
+                    .await?
+            }
+        };
+        tx.set_nonce(nonce);
+
+        let sent = match client.fill_transaction(tx, block).await {
+            Ok(()) => client
+                .send_transaction(tx.clone(), block)
+                .await
+                .map(|pending| pending.tx_hash()),
+            Err(e) => Err(e),
+        };
+        *next_nonce = sent.as_ref().ok().map(|_| nonce + 1);
+        sent
+    }
+}
+
+#[derive(Debug)]
+struct Wallets<M> {
+    wallets: Vec<GasWallet<M>>,
+    selection: WalletSelection,
+    next: AtomicUsize,
+    /// The wallet of each meta signer with requests in flight, and how many it has.
+    signers: Mutex<HashMap<Address, (usize, usize)>>,
+    events: broadcast::Sender<PoolEvent>,
+}
+
+/// Several gas wallets that take turns paying for `execute`, so that a relayer is not limited by
+/// one wallet's nonce sequence. See [`Relayer::with_gas_wallets`](super::Relayer::with_gas_wallets).
+///
+/// Each request is given a wallet by the pool's [`WalletSelection`], except that a meta signer's
+/// requests stay on one wallet while any of them is in flight: the forwarder only accepts a
+/// signer's nonces in order, and only one wallet's transactions are mined in the order they were
+/// sent. Wallets that a [`BalanceMonitor`] finds below the minimum balance are given no new
+/// signers.
+///
+/// Clones share the wallets.
+#[derive(Debug)]
+pub struct GasWalletPool<M> {
+    inner: Arc<Wallets<M>>,
+}
+
+impl<M> Clone for GasWalletPool<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: Middleware> GasWalletPool<M> {
+    /// A pool of the wallets behind `forwarders`, each connected to the client of one wallet.
+    /// Each client must have a default sender, e.g. a `SignerMiddleware`.
+    pub fn new(
+        forwarders: Vec<abi::Forwarder<M>>,
+        selection: WalletSelection,
+    ) -> Result<Self, PoolError> {
+        if forwarders.is_empty() {
+            return Err(PoolError::Empty);
+        }
+        let wallets = forwarders
+            .into_iter()
+            .enumerate()
+            .map(|(index, forwarder)| {
+                let address = forwarder
+                    .client_ref()
+                    .default_sender()
+                    .ok_or(PoolError::NoSender { index })?;
+                Ok(GasWallet {
+                    forwarder,
+                    address: convert::to_alloy_address(address),
+                    next_nonce: AsyncMutex::new(None),
+                    resync: AtomicBool::new(false),
+                    pending: AtomicUsize::new(0),
+                    active: AtomicBool::new(true),
+                    balance: Mutex::new(None),
+                })
+            })
+            .collect::<Result<_, PoolError>>()?;
+
+        Ok(Self {
+            inner: Arc::new(Wallets {
+                wallets,
+                selection,
+                next: AtomicUsize::new(0),
+                signers: Mutex::new(HashMap::new()),
+                events: broadcast::channel(EVENT_CAPACITY).0,
+            }),
+        })
+    }
+}
+
+impl<M> GasWalletPool<M> {
+    pub fn wallets(&self) -> &[GasWallet<M>] {
+        &self.inner.wallets
+    }
+
+    pub fn selection(&self) -> WalletSelection {
+        self.inner.selection
+    }
+
+    /// The wallet `lease` was given.
+    pub fn wallet(&self, lease: &WalletLease<M>) -> &GasWallet<M> {
+        &self.inner.wallets[lease.index]
+    }
+
+    /// Receives the events of the pool's [`BalanceMonitor`], e.g. to raise alerts.
+    pub fn subscribe(&self) -> broadcast::Receiver<PoolEvent> {
+        self.inner.events.subscribe()
+    }
+
+    fn emit(&self, event: PoolEvent) {
+        // Nobody may be listening.
+        let _ = self.inner.events.send(event);
+    }
+
+    /// Gives a request from `from` a wallet, which counts it as pending until the lease is
+    /// dropped.
+    pub fn select(&self, from: Address) -> Result<WalletLease<M>, PoolError> {
+        let wallets = &self.inner.wallets;
+        let mut signers = self.inner.signers.lock().unwrap();
+
+        // A signer with requests in flight stays on their wallet even if it was taken out of
+        // rotation meanwhile, since another wallet's `execute` could be mined before theirs.
+        let in_flight = signers.get(&from).map(|(index, _)| *index);
+        let index = match in_flight {
+            Some(index) => index,
+            None => {
+                let start = self.inner.next.fetch_add(1, Ordering::SeqCst);
+                let mut active = (0..wallets.len())
+                    .map(|offset| (start + offset) % wallets.len())
+                    .filter(|index| wallets[*index].is_active());
+                match self.inner.selection {
+                    WalletSelection::RoundRobin => active.next(),
+                    WalletSelection::LeastPending => {
+                        active.min_by_key(|index| wallets[*index].pending())
+                    }
+                }
+                .ok_or(PoolError::NoWalletAvailable {
+                    wallets: wallets.len(),
+                })?
+            }
+        };
+
+        wallets[index].pending.fetch_add(1, Ordering::SeqCst);
+        let signer = signers.entry(from).or_insert((index, 0));
+        *signer = (index, signer.1 + 1);
+        Ok(WalletLease {
+            pool: self.clone(),
+            index,
+            from,
+        })
+    }
+}
+
+/// A request's claim on a wallet of a [`GasWalletPool`], held until its `execute` transaction is
+/// mined or the request fails.
+#[derive(Debug)]
+pub struct WalletLease<M> {
+    pool: GasWalletPool<M>,
+    index: usize,
+    from: Address,
+}
+
+impl<M> WalletLease<M> {
+    pub fn wallet(&self) -> &GasWallet<M> {
+        self.pool.wallet(self)
+    }
+}
+
+impl<M> Drop for WalletLease<M> {
+    fn drop(&mut self) {
+        let mut signers = self.pool.inner.signers.lock().unwrap();
+        if let Some((_, count)) = signers.get_mut(&self.from) {
+            *count -= 1;
+            if *count == 0 {
+                signers.remove(&self.from);
+            }
+        }
+
+        let wallet = self.pool.wallet(self);
+        if wallet.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Nothing is in flight, so the node knows the nonce again, including after a dropped
+            // transaction. A send may hold the nonce, so it is reset on the next one.
+            wallet.resync.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Checks the balances of a pool's wallets in the background, takes those below `min_balance`
+/// out of rotation and puts them back once they are funded, and reports each change as a
+/// [`PoolEvent`] to the pool's subscribers.
+///
+/// With a treasury, a wallet below the minimum is sent `top_up` from the treasury's client, and
+/// the monitor waits for the transfer to be mined before it checks the next wallet.
+#[derive(Debug)]
+pub struct BalanceMonitor<M> {
+    pool: GasWalletPool<M>,
+    min_balance: U256,
+    interval: Duration,
+    treasury: Option<(Arc<M>, U256)>,
+}
+
+impl<M: Middleware + 'static> BalanceMonitor<M> {
+    pub fn new(pool: GasWalletPool<M>, min_balance: U256) -> Self {
+        Self {
+            pool,
+            min_balance,
+            interval: Duration::from_secs(DEFAULT_CHECK_INTERVAL_SECS),
+            treasury: None,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Tops wallets below the minimum up with `top_up` wei from the wallet of `treasury`.
+    pub fn with_treasury(mut self, treasury: Arc<M>, top_up: U256) -> Self {
+        self.treasury = Some((treasury, top_up));
+        self
+    }
+
+    /// Checks every wallet once.
+    pub async fn check(&self) {
+        for wallet in self.pool.wallets() {
+            let Some(balance) = self.refresh(wallet).await else {
+                continue;
+            };
+            let Some((treasury, amount)) = &self.treasury else {
+                continue;
+            };
+            if balance >= self.min_balance {
+                continue;
+            }
+
+            match top_up(treasury, wallet.address, *amount).await {
+                Ok(tx_hash) => {
+                    self.pool.emit(PoolEvent::ToppedUp {
+                        wallet: wallet.address,
+                        amount: *amount,
+                        tx_hash,
+                    });
+                    self.refresh(wallet).await;
+                }
+                Err(reason) => self.pool.emit(PoolEvent::TopUpFailed {
+                    wallet: wallet.address,
+                    reason,
+                }),
+            }
+        }
+    }
+
+    /// Checks every wallet every `interval` until the task is aborted.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                self.check().await;
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Reads `wallet`'s balance and moves it in or out of rotation.
+    async fn refresh(&self, wallet: &GasWallet<M>) -> Option<U256> {
+        let balance = match wallet
+            .forwarder
+            .client_ref()
+            .get_balance(convert::to_ethers_address(wallet.address), None)
+            .await
+        {
+            Ok(balance) => convert::to_alloy_u256(balance),
+            Err(e) => {
+                self.pool.emit(PoolEvent::CheckFailed {
+                    wallet: wallet.address,
+                    reason: e.to_string(),
+                });
+                return None;
+            }
+        };
+        *wallet.balance.lock().unwrap() = Some(balance);
+
+        let active = balance >= self.min_balance;
+        if wallet.active.swap(active, Ordering::SeqCst) != active {
+            self.pool.emit(if active {
+                PoolEvent::Restored {
+                    wallet: wallet.address,
+                    balance,
+                }
+            } else {
+                PoolEvent::LowBalance {
+                    wallet: wallet.address,
+                    balance,
+                    min_balance: self.min_balance,
+                }
+            });
+        }
+        Some(balance)
+    }
+}
+
+/// Sends `amount` to `wallet` from `treasury` and waits for the transfer to be mined.
+async fn top_up<M: Middleware>(
+    treasury: &M,
+    wallet: Address,
+    amount: U256,
+) -> Result<H256, String> {
+    let transfer = TransactionRequest::new()
+        .to(convert::to_ethers_address(wallet))
+        .value(convert::to_ethers_u256(amount));
+    let pending = treasury
+        .send_transaction(transfer, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let tx_hash = pending.tx_hash();
+    match pending.await.map_err(|e| e.to_string())? {
+        Some(receipt) if receipt.status == Some(1.into()) => Ok(tx_hash),
+        Some(_) => Err(format!("transfer {tx_hash:?} reverted")),
+        None => Err(format!("transfer {tx_hash:?} was dropped")),
+    }
+}
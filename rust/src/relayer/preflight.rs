@@ -0,0 +1,71 @@
+use ethers::{
+    abi::AbiDecode,
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, BlockId, BlockNumber, Bytes},
+};
+use thiserror::Error;
+
+use super::{abi::forwarder::ExecuteReturn, revert::revert_data, RevertDecoder, RevertReason};
+
+#[derive(Error, Debug)]
+pub enum PreflightError {
+    #[error("Meta-transaction would revert: {0}")]
+    Reverted(RevertReason),
+
+    #[error("Failed to simulate execute: {0}")]
+    Rpc(String),
+}
+
+/// How a meta-transaction is sent.
+#[derive(Debug, Clone, Default)]
+pub struct MetaTransactionOptions {
+    /// The block to estimate and fill at. Defaults to the pending block.
+    pub block: Option<BlockId>,
+    /// Broadcast `execute` even if simulating it fails, e.g. to get the failure on chain.
+    pub skip_preflight: bool,
+}
+
+impl MetaTransactionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(mut self, block: impl Into<BlockId>) -> Self {
+        self.block = Some(block.into());
+        self
+    }
+
+    pub fn skip_preflight(mut self) -> Self {
+        self.skip_preflight = true;
+        self
+    }
+}
+
+/// Simulates `tx`, an `execute` transaction from the gas wallet, with `eth_call` at the pending
+/// block (or `block`), and returns what the target returned.
+///
+/// Fails with [`PreflightError::Reverted`] if `execute` would revert or report that the call
+/// failed, so that no gas is spent on a meta-transaction that cannot succeed.
+pub async fn preflight<M: Middleware>(
+    client: &M,
+    tx: &TypedTransaction,
+    block: Option<BlockId>,
+    reverts: &RevertDecoder,
+) -> Result<Bytes, PreflightError> {
+    let block = block.or(Some(BlockNumber::Pending.into()));
+    let output = client
+        .call(tx, block)
+        .await
+        .map_err(|e| match revert_data(&e) {
+            Some(data) => PreflightError::Reverted(reverts.decode_execute(&data)),
+            None => PreflightError::Rpc(e.to_string()),
+        })?;
+
+    let ExecuteReturn(success, data) =
+        ExecuteReturn::decode(&output).map_err(|e| PreflightError::Rpc(e.to_string()))?;
+    if !success {
+        // This forwarder re-raises failures, but others return them.
+        return Err(PreflightError::Reverted(reverts.decode_target(&data)));
+    }
+    Ok(data)
+}
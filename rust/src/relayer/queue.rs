@@ -0,0 +1,234 @@
+use std::{path::Path, sync::Arc};
+
+use alloy::primitives::{Address, B256, U256};
+use ethers::types::Bytes;
+use redb::{backends::InMemoryBackend, Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{budget::unix_time, ForwardRequest, RelayStatus};
+
+/// Queued requests by `(from, nonce)`.
+const REQUESTS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("requests");
+/// The `(from, nonce)` of queued requests by id.
+const IDS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("ids");
+
+#[derive(Error, Debug)]
+pub enum QueueError {
+    #[error("Forwarder nonce {nonce} of {from} is taken by another queued request")]
+    NonceTaken { from: Address, nonce: U256 },
+
+    #[error("Relay queue store failed: {0}")]
+    Store(Box<redb::Error>),
+
+    #[error("Invalid entry in the relay queue: {0}")]
+    Corrupt(String),
+
+    #[error("The relay task stopped before execute was broadcast")]
+    SubmissionLost,
+}
+
+fn store(e: impl Into<redb::Error>) -> QueueError {
+    QueueError::Store(Box::new(e.into()))
+}
+
+/// A signed request the relayer accepted, and what has become of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedRequest {
+    /// The request's EIP-712 digest.
+    pub id: B256,
+    pub request: ForwardRequest,
+    pub signature: Bytes,
+    /// [`Accepted`](RelayStatus::Accepted) until `execute` is broadcast,
+    /// [`Pending`](RelayStatus::Pending) until it is mined, then
+    /// [`Mined`](RelayStatus::Mined) or [`Failed`](RelayStatus::Failed).
+    pub status: RelayStatus,
+    /// When the status last changed, in seconds since the Unix epoch.
+    pub updated: u64,
+}
+
+impl QueuedRequest {
+    /// Whether the request is still to be relayed or followed to its receipt.
+    pub fn is_unfinished(&self) -> bool {
+        matches!(
+            self.status,
+            RelayStatus::Accepted | RelayStatus::Pending { .. }
+        )
+    }
+
+    /// Whether the request holds its `(from, nonce)`, i.e. it has not failed.
+    pub fn is_live(&self) -> bool {
+        !matches!(self.status, RelayStatus::Failed { .. })
+    }
+}
+
+/// The signed requests a relayer accepted, keyed by `(from, forwarder nonce)`, in an embedded
+/// [redb](https://docs.rs/redb) store.
+///
+/// Every status change is committed as soon as the relayer knows of it, so after a crash the
+/// relayer can tell which `execute` transactions to follow and which requests may still need to
+/// be submitted. `Pending` is only known once `execute` is broadcast, so a request that is still
+/// `Accepted` may have been broadcast just before the crash; the relayer checks its forwarder
+/// nonce once the gas wallets' transactions are mined before it submits it again (see
+/// [`RelayServer::resume`](crate::server::RelayServer::resume)). At most one live request is kept
+/// per `(from, nonce)`; a failed one can be replaced. Clones share the store.
+#[derive(Debug, Clone)]
+pub struct RelayQueue {
+    db: Arc<Database>,
+}
+
+impl RelayQueue {
+    /// Opens the store at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QueueError> {
+        Self::new(Database::create(path).map_err(store)?)
+    }
+
+    /// A store that is not persisted.
+    pub fn in_memory() -> Result<Self, QueueError> {
+        Self::new(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .map_err(store)?,
+        )
+    }
+
+    fn new(db: Database) -> Result<Self, QueueError> {
+        // Create the tables, so that reads find them.
+        let txn = db.begin_write().map_err(store)?;
+        txn.open_table(REQUESTS).map_err(store)?;
+        txn.open_table(IDS).map_err(store)?;
+        txn.commit().map_err(store)?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    /// The request queued for `(from, nonce)`, if any.
+    pub fn get(&self, from: Address, nonce: U256) -> Result<Option<QueuedRequest>, QueueError> {
+        let txn = self.db.begin_read().map_err(store)?;
+        let requests = txn.open_table(REQUESTS).map_err(store)?;
+        let entry = requests.get(key(from, nonce).as_slice()).map_err(store)?;
+        entry.map(|entry| decode(entry.value())).transpose()
+    }
+
+    /// The request with id `id`, if it was queued.
+    pub fn get_by_id(&self, id: B256) -> Result<Option<QueuedRequest>, QueueError> {
+        let txn = self.db.begin_read().map_err(store)?;
+        let ids = txn.open_table(IDS).map_err(store)?;
+        let Some(key) = ids.get(id.as_slice()).map_err(store)? else {
+            return Ok(None);
+        };
+        let requests = txn.open_table(REQUESTS).map_err(store)?;
+        let entry = requests.get(key.value()).map_err(store)?;
+        match entry.map(|entry| decode(entry.value())).transpose()? {
+            // The id's request was replaced by another one with the same nonce.
+            Some(entry) if entry.id == id => Ok(Some(entry)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Queues `request` as [`Accepted`](RelayStatus::Accepted) and returns the queued request, or
+    /// `None` if it was queued before and has not failed. Fails with
+    /// [`NonceTaken`](QueueError::NonceTaken) if another live request has its `(from, nonce)`.
+    pub fn accept(
+        &self,
+        id: B256,
+        request: ForwardRequest,
+        signature: Bytes,
+    ) -> Result<Option<QueuedRequest>, QueueError> {
+        let key = key(request.from, request.nonce);
+        let txn = self.db.begin_write().map_err(store)?;
+        let entry = {
+            let mut requests = txn.open_table(REQUESTS).map_err(store)?;
+            let existing = requests.get(key.as_slice()).map_err(store)?;
+            if let Some(existing) = existing
+                .map(|entry| decode(entry.value()))
+                .transpose()?
+                .filter(QueuedRequest::is_live)
+            {
+                return if existing.id == id {
+                    Ok(None)
+                } else {
+                    Err(QueueError::NonceTaken {
+                        from: request.from,
+                        nonce: request.nonce,
+                    })
+                };
+            }
+
+            let entry = QueuedRequest {
+                id,
+                request,
+                signature,
+                status: RelayStatus::Accepted,
+                updated: unix_time(),
+            };
+            requests
+                .insert(key.as_slice(), encode(&entry).as_slice())
+                .map_err(store)?;
+            txn.open_table(IDS)
+                .map_err(store)?
+                .insert(id.as_slice(), key.as_slice())
+                .map_err(store)?;
+            entry
+        };
+        txn.commit().map_err(store)?;
+        Ok(Some(entry))
+    }
+
+    /// Moves the request queued for `(from, nonce)` to `status`.
+    pub fn set_status(
+        &self,
+        from: Address,
+        nonce: U256,
+        status: RelayStatus,
+    ) -> Result<(), QueueError> {
+        let key = key(from, nonce);
+        let txn = self.db.begin_write().map_err(store)?;
+        {
+            let mut requests = txn.open_table(REQUESTS).map_err(store)?;
+            let existing = requests.get(key.as_slice()).map_err(store)?;
+            let Some(mut entry) = existing.map(|entry| decode(entry.value())).transpose()? else {
+                return Err(QueueError::Corrupt(format!(
+                    "no request is queued for nonce {nonce} of {from}"
+                )));
+            };
+            entry.status = status;
+            entry.updated = unix_time();
+            requests
+                .insert(key.as_slice(), encode(&entry).as_slice())
+                .map_err(store)?;
+        }
+        txn.commit().map_err(store)
+    }
+
+    /// The requests that are still [`Accepted`](RelayStatus::Accepted) or
+    /// [`Pending`](RelayStatus::Pending), by signer and then nonce.
+    pub fn unfinished(&self) -> Result<Vec<QueuedRequest>, QueueError> {
+        let txn = self.db.begin_read().map_err(store)?;
+        let requests = txn.open_table(REQUESTS).map_err(store)?;
+        let mut unfinished = Vec::new();
+        for entry in requests.iter().map_err(store)? {
+            let (_, entry) = entry.map_err(store)?;
+            let entry = decode(entry.value())?;
+            if entry.is_unfinished() {
+                unfinished.push(entry);
+            }
+        }
+        Ok(unfinished)
+    }
+}
+
+/// `from` followed by the big-endian nonce, so that a signer's requests are ordered by nonce.
+fn key(from: Address, nonce: U256) -> [u8; 52] {
+    let mut key = [0; 52];
+    key[..20].copy_from_slice(from.as_slice());
+    key[20..].copy_from_slice(&nonce.to_be_bytes::<32>());
+    key
+}
+
+fn encode(entry: &QueuedRequest) -> Vec<u8> {
+    serde_json::to_vec(entry).expect("queued requests serialize")
+}
+
+fn decode(bytes: &[u8]) -> Result<QueuedRequest, QueueError> {
+    serde_json::from_slice(bytes).map_err(|e| QueueError::Corrupt(e.to_string()))
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use alloy::primitives::Address;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// How many buckets a limiter keeps before it first forgets those that have refilled completely.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitError {
+    #[error("Rate limit exceeded for {key}, retry after {}s", retry_after_secs(*.retry_after))]
+    Exceeded { key: RateKey, retry_after: Duration },
+}
+
+impl RateLimitError {
+    /// How long until the request would be allowed.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            RateLimitError::Exceeded { retry_after, .. } => *retry_after,
+        }
+    }
+}
+
+/// `retry_after` rounded up to whole seconds, as sent to clients.
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after
+        .as_secs()
+        .saturating_add(u64::from(retry_after.subsec_nanos() > 0))
+}
+
+/// What a rate limit is counted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    /// A meta signer, the `from` of its requests.
+    Signer(Address),
+    /// A client of the relayer.
+    Ip(IpAddr),
+}
+
+impl fmt::Display for RateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateKey::Signer(address) => write!(f, "signer {address}"),
+            RateKey::Ip(ip) => write!(f, "client {ip}"),
+        }
+    }
+}
+
+/// A token bucket: `requests_per_minute` on average, and up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests_per_minute: u32,
+    /// Defaults to `requests_per_minute`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn per_minute(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute,
+            burst: None,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.unwrap_or(self.requests_per_minute))
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.requests_per_minute) / 60.0
+    }
+}
+
+/// Limits for a named group of signers and clients, instead of the defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateTier {
+    pub name: String,
+    /// The limit of each of `signers`. `None` leaves them unlimited.
+    #[serde(default)]
+    pub signer: Option<RateLimit>,
+    /// The limit of each of `ips`. `None` leaves them unlimited.
+    #[serde(default)]
+    pub ip: Option<RateLimit>,
+    #[serde(default)]
+    pub signers: Vec<Address>,
+    #[serde(default)]
+    pub ips: Vec<IpAddr>,
+}
+
+/// Rate limits, as written in the config file:
+///
+/// ```toml
+/// [profiles.local.rate_limits]
+/// signer = { requests_per_minute = 30, burst = 10 }
+/// ip = { requests_per_minute = 120 }
+///
+/// [[profiles.local.rate_limits.tiers]]
+/// name = "partners"
+/// signer = { requests_per_minute = 600, burst = 100 }
+/// signers = ["0x70997970C51812dc3A010C7d01b50e0d17dc79C8"]
+/// ```
+///
+/// Signers and clients in a tier get its limits, and everyone else the default `signer` and `ip`
+/// limits. Without a limit, requests are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub signer: Option<RateLimit>,
+    pub ip: Option<RateLimit>,
+    pub tiers: Vec<RateTier>,
+}
+
+impl RateLimitConfig {
+    /// The limit that applies to `key`.
+    pub fn limit(&self, key: &RateKey) -> Option<RateLimit> {
+        match key {
+            RateKey::Signer(address) => self
+                .tiers
+                .iter()
+                .find(|tier| tier.signers.contains(address))
+                .map_or(self.signer, |tier| tier.signer),
+            RateKey::Ip(ip) => self
+                .tiers
+                .iter()
+                .find(|tier| tier.ips.contains(ip))
+                .map_or(self.ip, |tier| tier.ip),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second()).min(limit.capacity());
+        self.updated = now;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<RateKey, Bucket>,
+    /// How many buckets there may be before the full ones are forgotten: twice as many as were
+    /// left the last time, so that busy buckets are not all scanned again for every new key.
+    prune_at: usize,
+}
+
+impl Buckets {
+    fn prune(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.buckets.retain(|key, bucket| match config.limit(key) {
+            Some(limit) => {
+                bucket.refill(&limit, now);
+                bucket.tokens < limit.capacity()
+            }
+            None => false,
+        });
+        self.prune_at = (2 * self.buckets.len()).max(MAX_IDLE_BUCKETS);
+    }
+}
+
+/// Counts requests per meta signer and per client against a [`RateLimitConfig`].
+///
+/// Checking is local, so a relayer can refuse a request before it does any RPC work for it.
+/// Clones share the counts.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    config: Arc<RateLimitConfig>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Takes a token for a request from `from`, sent by the client at `ip` if known. The client is
+    /// counted first, so a client that is over its limit cannot use up a signer's.
+    pub fn check(&self, from: Address, ip: Option<IpAddr>) -> Result<(), RateLimitError> {
+        let now = Instant::now();
+        if let Some(ip) = ip {
+            self.check_at(RateKey::Ip(ip), now)?;
+        }
+        self.check_at(RateKey::Signer(from), now)
+    }
+
+    /// Takes a token from `key`'s bucket at `now`, or says how long until there is one.
+    pub fn check_at(&self, key: RateKey, now: Instant) -> Result<(), RateLimitError> {
+        let Some(limit) = self.config.limit(&key) else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.buckets.len() >= buckets.prune_at.max(MAX_IDLE_BUCKETS) {
+            buckets.prune(&self.config, now);
+        }
+
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: limit.capacity(),
+            updated: now,
+        });
+        bucket.refill(&limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let retry_after = Duration::try_from_secs_f64((1.0 - bucket.tokens) / limit.per_second())
+            .unwrap_or(Duration::MAX);
+        Err(RateLimitError::Exceeded { key, retry_after })
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    future::{Future, IntoFuture},
+    pin::Pin,
+    sync::Arc,
+};
+
+use alloy::primitives::{Address, U256};
+use ethers::{
+    abi::AbiDecode,
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Bytes, CallFrame, GethDebugBuiltInTracerType,
+        GethDebugTracerType, GethDebugTracingOptions, GethTrace, GethTraceFrame,
+        TransactionReceipt, TransactionRequest, H256,
+    },
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use super::{
+    abi::forwarder::ExecuteReturn,
+    budget::{unix_time, SpendRecord, SpendReservation},
+    convert, nonce,
+    pool::WalletLease,
+    BudgetError, EscalatingTransaction, FeeError, ForwardRequest, ForwarderNonces, SpendLedger,
+};
+
+#[derive(Error, Debug)]
+pub enum ReceiptError {
+    #[error("{0}")]
+    FeeError(FeeError),
+
+    #[error("Failed to inspect meta-transaction {hash:?}: {reason}")]
+    Rpc { hash: H256, reason: String },
+
+    #[error("Failed to record the spend of meta-transaction: {0}")]
+    Ledger(BudgetError),
+}
+
+/// The receipt of a meta-transaction: the gas wallet's `execute` receipt, and what became of the
+/// `ForwardRequest` it carried.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetaTransactionReceipt {
+    /// The receipt of the gas wallet's `execute` transaction.
+    pub receipt: TransactionReceipt,
+    pub forwarder: Address,
+    /// The signed request: its `from` is the meta signer, `nonce` the forwarder nonce it used and
+    /// `gas` the inner call's gas limit.
+    pub request: ForwardRequest,
+    /// Whether the call to the target succeeded, as returned by `execute`. `false` if `execute`
+    /// reverted; `true` if it was mined but its output could not be recovered.
+    pub success: bool,
+    /// What the target returned, or `None` if it could not be recovered.
+    pub return_data: Option<Bytes>,
+    /// Whether `getNonce(from)` had moved past the request's nonce at the mined block, i.e. the
+    /// forwarder consumed the request.
+    pub nonce_advanced: bool,
+}
+
+impl MetaTransactionReceipt {
+    /// Completes the receipt of `call`, the `execute` transaction that carried `request`.
+    ///
+    /// The return data comes from a `callTracer` trace of the transaction (`debug_traceTransaction`).
+    /// Nodes without the debug namespace fall back to replaying `call` with `eth_call` on top of
+    /// the block before the one it was mined in, which misses any earlier transaction in the same
+    /// block that changed the target's state.
+    pub async fn recover<M: Middleware>(
+        client: &M,
+        receipt: TransactionReceipt,
+        call: &TypedTransaction,
+        forwarder: Address,
+        request: ForwardRequest,
+    ) -> Result<Self, ReceiptError> {
+        let hash = receipt.transaction_hash;
+        let block = receipt.block_number.ok_or_else(|| ReceiptError::Rpc {
+            hash,
+            reason: "receipt has no block number".to_string(),
+        })?;
+
+        let (success, return_data) = if receipt.status == Some(1.into()) {
+            match trace(client, hash).await {
+                Some(output) => output,
+                None => replay(client, call, block.as_u64().saturating_sub(1)).await,
+            }
+        } else {
+            (false, None)
+        };
+
+        let mined_nonce = nonce::get_nonce(client, forwarder, request.from, block.into())
+            .await
+            .map_err(|reason| ReceiptError::Rpc { hash, reason })?;
+
+        Ok(Self {
+            receipt,
+            forwarder,
+            success,
+            return_data,
+            nonce_advanced: mined_nonce > request.nonce,
+            request,
+        })
+    }
+
+    /// The meta signer.
+    pub fn from(&self) -> Address {
+        self.request.from
+    }
+
+    /// The forwarder nonce the request used.
+    pub fn nonce(&self) -> U256 {
+        self.request.nonce
+    }
+
+    /// The gas limit of the call to the target.
+    pub fn gas(&self) -> U256 {
+        self.request.gas
+    }
+
+    /// What the gas wallet paid for the `execute` transaction, `gasUsed * effectiveGasPrice`, or
+    /// `None` if the node left either out of the receipt.
+    pub fn cost(&self) -> Option<U256> {
+        let gas_used = self.receipt.gas_used?;
+        let price = self.receipt.effective_gas_price?;
+        Some(convert::to_alloy_u256(gas_used.saturating_mul(price)))
+    }
+
+    /// Decodes the return data as `T`, e.g. the return type of the target function.
+    pub fn decode_return<T: AbiDecode>(&self) -> Option<T> {
+        T::decode(self.return_data.as_ref()?).ok()
+    }
+}
+
+/// The `execute` output of a mined transaction, from its call trace.
+async fn trace<M: Middleware>(client: &M, hash: H256) -> Option<(bool, Option<Bytes>)> {
+    let options = GethDebugTracingOptions {
+        tracer: Some(GethDebugTracerType::BuiltInTracer(
+            GethDebugBuiltInTracerType::CallTracer,
+        )),
+        ..Default::default()
+    };
+    let frame = match client.debug_trace_transaction(hash, options).await.ok()? {
+        GethTrace::Known(GethTraceFrame::CallTracer(frame)) => frame,
+        trace => serde_json::from_value::<CallFrame>(serde_json::to_value(trace).ok()?).ok()?,
+    };
+
+    if frame.error.is_some() {
+        return Some((false, None));
+    }
+    let ExecuteReturn(success, data) = ExecuteReturn::decode(frame.output?).ok()?;
+    Some((success, Some(data)))
+}
+
+/// The `execute` output of `call`, replayed on top of `block`.
+async fn replay<M: Middleware>(
+    client: &M,
+    call: &TypedTransaction,
+    block: u64,
+) -> (bool, Option<Bytes>) {
+    // Without fees, which may be below the base fee of an older block.
+    let mut replay = TransactionRequest::new();
+    replay.from = call.from().copied();
+    replay.to = call.to().cloned();
+    replay.gas = call.gas().copied();
+    replay.value = call.value().copied();
+    replay.data = call.data().cloned();
+
+    match client.call(&replay.into(), Some(block.into())).await {
+        Ok(output) => match ExecuteReturn::decode(output) {
+            Ok(ExecuteReturn(success, data)) => (success, Some(data)),
+            Err(_) => (true, None),
+        },
+        // The transaction was mined, so the replay is what is off.
+        Err(_) => (true, None),
+    }
+}
+
+/// A meta-transaction whose `execute` transaction has been broadcast.
+///
+/// Awaiting it follows the `execute` transaction through any fee escalation (see
+/// [`EscalatingTransaction`]) and resolves to its [`MetaTransactionReceipt`], or `None` if the gas
+/// wallet's nonce was used by some other transaction.
+#[derive(Debug)]
+pub struct PendingMetaTransaction<'a, M> {
+    escalating: EscalatingTransaction<'a, M>,
+    forwarder: Address,
+    request: ForwardRequest,
+    ledger: Option<SpendLedger>,
+    reservation: Option<SpendReservation>,
+    lease: Option<WalletLease<M>>,
+    nonces: Option<ForwarderNonces>,
+}
+
+impl<'a, M: Middleware> PendingMetaTransaction<'a, M> {
+    pub fn new(
+        escalating: EscalatingTransaction<'a, M>,
+        forwarder: Address,
+        request: ForwardRequest,
+    ) -> Self {
+        Self {
+            escalating,
+            forwarder,
+            request,
+            ledger: None,
+            reservation: None,
+            lease: None,
+            nonces: None,
+        }
+    }
+
+    /// Records what the gas wallet paid for the `execute` transaction in `ledger` once it is
+    /// mined.
+    pub fn with_ledger(mut self, ledger: SpendLedger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Holds `reservation` until the transaction is mined, then settles it against what the gas
+    /// wallet paid, which is recorded in the reservation's ledger. The reservation is released if
+    /// the transaction is not mined or this is dropped.
+    pub fn with_reservation(mut self, reservation: SpendReservation) -> Self {
+        self.reservation = Some(reservation);
+        self
+    }
+
+    /// Holds `lease` until the transaction is mined, so that its pool counts the request as
+    /// pending on the wallet that sent it.
+    pub fn with_lease(mut self, lease: WalletLease<M>) -> Self {
+        self.lease = Some(lease);
+        self
+    }
+
+    /// Resyncs the meta signer's nonce in `nonces` (see [`ForwarderNonces::resync`]) if the
+    /// forwarder does not consume the request: the `execute` transaction is dropped, reverts or is
+    /// reorged out.
+    pub fn with_nonces(mut self, nonces: ForwarderNonces) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// Sends the hash of every replacement to `broadcasts` as it is broadcast (see
+    /// [`EscalatingTransaction::with_broadcasts`]).
+    pub fn with_broadcasts(mut self, broadcasts: mpsc::UnboundedSender<H256>) -> Self {
+        self.escalating = self.escalating.with_broadcasts(broadcasts);
+        self
+    }
+
+    /// The hash of the latest broadcast.
+    pub fn tx_hash(&self) -> H256 {
+        self.escalating.tx_hash()
+    }
+
+    /// The hashes of every broadcast so far, oldest first.
+    pub fn hashes(&self) -> &[H256] {
+        self.escalating.hashes()
+    }
+
+    /// The signed request.
+    pub fn request(&self) -> &ForwardRequest {
+        &self.request
+    }
+
+    /// The gas wallet lease held until the transaction is mined, if it was sent from a pool.
+    pub fn lease(&self) -> Option<&WalletLease<M>> {
+        self.lease.as_ref()
+    }
+
+    /// Follows the transaction with `client`, which must be the gas wallet's client that sent
+    /// it, so that it can be followed from a task of its own (see
+    /// [`EscalatingTransaction::into_shared`]).
+    pub fn into_shared(self, client: Arc<M>) -> PendingMetaTransaction<'static, M>
+    where
+        M: 'static,
+    {
+        PendingMetaTransaction {
+            escalating: self.escalating.into_shared(client),
+            forwarder: self.forwarder,
+            request: self.request,
+            ledger: self.ledger,
+            reservation: self.reservation,
+            lease: self.lease,
+            nonces: self.nonces,
+        }
+    }
+
+    /// Waits for the `execute` transaction to be mined and inspects what it did.
+    pub async fn wait(mut self) -> Result<Option<MetaTransactionReceipt>, ReceiptError> {
+        let call = self.escalating.tx().clone();
+        let receipt = match self.escalating.wait().await {
+            Ok(Some(receipt)) => receipt,
+            Ok(None) => {
+                self.resync().await;
+                return Ok(None);
+            }
+            Err(e) => {
+                self.resync().await;
+                return Err(ReceiptError::FeeError(e));
+            }
+        };
+
+        let client = self.escalating.client();
+        let receipt = MetaTransactionReceipt::recover(
+            client,
+            receipt,
+            &call,
+            self.forwarder,
+            self.request.clone(),
+        )
+        .await?;
+        if !receipt.nonce_advanced {
+            // The forwarder did not consume the request, e.g. `execute` reverted, so it still
+            // expects this nonce.
+            self.resync().await;
+        }
+
+        if self.ledger.is_some() || self.reservation.is_some() {
+            // Without the effective gas price, the price the transaction offered is an upper
+            // bound.
+            let wei = receipt.cost().unwrap_or_else(|| {
+                let gas_used = receipt.receipt.gas_used.unwrap_or_default();
+                let price = call.gas_price().unwrap_or_default();
+                convert::to_alloy_u256(gas_used.saturating_mul(price))
+            });
+            let record = SpendRecord::new(&receipt, wei, unix_time());
+            match (self.reservation.take(), &self.ledger) {
+                (Some(reservation), _) => reservation.settle(record),
+                (None, Some(ledger)) => ledger.record(record),
+                (None, None) => Ok(()),
+            }
+            .map_err(ReceiptError::Ledger)?;
+        }
+        Ok(Some(receipt))
+    }
+
+    async fn resync(&self) {
+        if let Some(nonces) = &self.nonces {
+            nonces.resync(self.forwarder, self.request.from).await;
+        }
+    }
+}
+
+impl<'a, M: Middleware + 'a> IntoFuture for PendingMetaTransaction<'a, M> {
+    type Output = Result<Option<MetaTransactionReceipt>, ReceiptError>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.wait())
+    }
+}
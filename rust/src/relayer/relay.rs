@@ -0,0 +1,467 @@
+use std::future::IntoFuture;
+
+use alloy::{primitives::B256, sol_types::Eip712Domain};
+use ethers::{
+    contract::ContractError,
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, BlockNumber, Bytes, H256},
+};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use super::{
+    abi, convert, forward_request_digest, gas, nonce, preflight::preflight, revert::revert_data,
+    Allowlist, Budget, BudgetError, DomainError, EscalatingTransaction, EscalationPolicy, FeeError,
+    FeeStrategy, ForwardRequest, ForwardRequestVerifier, ForwarderDomains, ForwarderNonces,
+    GasError, GasWalletPool, MetaTransactionOptions, MetaTransactionReceipt, OuterTransactionType,
+    PendingMetaTransaction, PolicyError, PoolError, PreflightError, QueueError, RateLimitError,
+    ReceiptError, RevertDecoder, RevertReason, VerifyError,
+};
+
+#[derive(Error, Debug)]
+pub enum RelayError<M: Middleware> {
+    #[error("{0}")]
+    PolicyRejected(PolicyError),
+
+    #[error("{0}")]
+    OverBudget(BudgetError),
+
+    #[error("{0}")]
+    RateLimited(RateLimitError),
+
+    #[error("{0}")]
+    Queue(QueueError),
+
+    #[error("{0}")]
+    GasWallets(PoolError),
+
+    #[error("Failed to get chain ID: {0}")]
+    MissingChainID(M::Error),
+
+    #[error("Failed to get nonce: {0}")]
+    FailedToGetNonce(String),
+
+    #[error("{0}")]
+    DomainError(DomainError),
+
+    #[error("Meta-transaction would be rejected by the forwarder: {0}")]
+    VerificationFailed(VerifyError),
+
+    #[error("Failed to estimate gas: {0}")]
+    FailedToEstimateGas(M::Error),
+
+    #[error("{0}")]
+    GasError(GasError),
+
+    #[error("{0}")]
+    FeeError(FeeError),
+
+    #[error("{0}")]
+    PreflightFailed(PreflightError),
+
+    #[error("{0}")]
+    Reverted(RevertReason),
+
+    #[error("{0}")]
+    ContractError(ContractError<M>),
+}
+
+/// Submits signed `ForwardRequest`s through `Forwarder.execute` from a funded gas wallet.
+///
+/// This is the half of [`EIP2771GasRelayerMiddleware`](super::EIP2771GasRelayerMiddleware) that
+/// needs no meta signer, so it also relays requests signed elsewhere, e.g. by the clients of
+/// [`RelayServer`](crate::server::RelayServer). Requests from untrusted clients should be checked
+/// with [`verify`](Self::verify) first.
+///
+/// With a [`GasWalletPool`], each request is submitted from a wallet of the pool instead, and
+/// the forwarder's own client is only used to read from the chain.
+#[derive(Debug)]
+pub struct Relayer<M> {
+    forwarder_with_gas_signer: abi::Forwarder<M>,
+    domains: ForwarderDomains,
+    fees: Option<FeeStrategy>,
+    escalation: Option<EscalationPolicy>,
+    transaction_type: Option<OuterTransactionType>,
+    reverts: RevertDecoder,
+    allowlist: Option<Allowlist>,
+    budget: Option<Budget>,
+    gas_wallets: Option<GasWalletPool<M>>,
+    nonces: Option<ForwarderNonces>,
+}
+
+impl<M> Relayer<M> {
+    pub fn new(forwarder_with_gas_signer: abi::Forwarder<M>) -> Self {
+        Self {
+            forwarder_with_gas_signer,
+            domains: ForwarderDomains::new(),
+            fees: None,
+            escalation: None,
+            transaction_type: None,
+            reverts: RevertDecoder::new(),
+            allowlist: None,
+            budget: None,
+            gas_wallets: None,
+            nonces: None,
+        }
+    }
+
+    /// Shares a cache of forwarder domains, e.g. between several relayers.
+    pub fn with_domains(mut self, domains: ForwarderDomains) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Prices the gas wallet's `execute` transactions with `strategy`, instead of leaving the
+    /// fees to the gas wallet's client.
+    pub fn with_fee_strategy(mut self, strategy: FeeStrategy) -> Self {
+        self.fees = Some(strategy);
+        self
+    }
+
+    /// Rebroadcasts `execute` transactions that are not getting mined with higher fees.
+    pub fn with_escalation(mut self, policy: EscalationPolicy) -> Self {
+        self.escalation = Some(policy);
+        self
+    }
+
+    /// Sends the gas wallet's `execute` transactions as `transaction_type`, e.g. legacy on a
+    /// chain without EIP-1559. By default they are EIP-1559 transactions.
+    pub fn with_transaction_type(mut self, transaction_type: OuterTransactionType) -> Self {
+        self.transaction_type = Some(transaction_type);
+        self
+    }
+
+    /// Decodes reverts with `reverts`, e.g. one that knows the custom errors of more targets.
+    pub fn with_revert_decoder(mut self, reverts: RevertDecoder) -> Self {
+        self.reverts = reverts;
+        self
+    }
+
+    /// Only relays requests that `allowlist` allows. Without an allowlist, any request is relayed,
+    /// which suits a relayer for requests its owner signs; a relayer for other signers should
+    /// always have one, if need be an [`Allowlist::any`] that it opts into explicitly.
+    pub fn with_allowlist(mut self, allowlist: Allowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    pub fn allowlist(&self) -> Option<&Allowlist> {
+        self.allowlist.as_ref()
+    }
+
+    /// Stops relaying for a meta signer once it has used up `budget`. Each relayed request
+    /// reserves the most it may cost before it is broadcast (see [`Budget::reserve`]), and its
+    /// spend is recorded in the budget's ledger in place of the reservation when its receipt is
+    /// awaited.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn budget(&self) -> Option<&Budget> {
+        self.budget.as_ref()
+    }
+
+    /// Pays for requests from the wallets of `pool`, which count their own nonces, instead of
+    /// the forwarder's gas wallet. The pool's forwarders should be at the same address.
+    pub fn with_gas_wallets(mut self, pool: GasWalletPool<M>) -> Self {
+        self.gas_wallets = Some(pool);
+        self
+    }
+
+    pub fn gas_wallets(&self) -> Option<&GasWalletPool<M>> {
+        self.gas_wallets.as_ref()
+    }
+
+    /// Resyncs the nonces the requests were signed with in `nonces` when the forwarder does not
+    /// consume them (see [`PendingMetaTransaction::with_nonces`]), e.g. the nonces of the
+    /// middleware that signs them.
+    pub fn with_nonces(mut self, nonces: ForwarderNonces) -> Self {
+        self.nonces = Some(nonces);
+        self
+    }
+
+    /// The forwarder, connected to the gas wallet's client.
+    pub fn forwarder(&self) -> &abi::Forwarder<M> {
+        &self.forwarder_with_gas_signer
+    }
+
+    pub fn domains(&self) -> &ForwarderDomains {
+        &self.domains
+    }
+
+    pub fn reverts(&self) -> &RevertDecoder {
+        &self.reverts
+    }
+
+    /// Checks `request` against the allowlist, if any, without contacting the node.
+    pub fn check_policy(&self, request: &ForwardRequest) -> Result<(), PolicyError> {
+        match &self.allowlist {
+            Some(allowlist) => allowlist.check(request),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that `request`'s signer has budget left, if the relayer has a budget.
+    pub fn check_budget(&self, request: &ForwardRequest) -> Result<(), BudgetError> {
+        match &self.budget {
+            Some(budget) => budget.check(request.from),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<M: Middleware> Relayer<M> {
+    /// Checks `request` against the allowlist and the budget, without contacting the node.
+    pub fn admit(&self, request: &ForwardRequest) -> Result<(), RelayError<M>> {
+        self.check_policy(request)
+            .map_err(RelayError::PolicyRejected)?;
+        self.check_budget(request).map_err(RelayError::OverBudget)
+    }
+
+    /// The EIP-712 digest of `request` under the forwarder's domain, which identifies it.
+    pub async fn digest(&self, request: &ForwardRequest) -> Result<B256, RelayError<M>> {
+        let domain = self.domain().await?;
+        Ok(forward_request_digest(request, &domain))
+    }
+
+    async fn domain(&self) -> Result<Eip712Domain, RelayError<M>> {
+        let client = self.forwarder_with_gas_signer.client_ref();
+        let chain_id = client
+            .get_chainid()
+            .await
+            .map_err(RelayError::MissingChainID)?
+            .as_u64();
+        self.domains
+            .get(
+                client,
+                convert::to_alloy_address(self.forwarder_with_gas_signer.address()),
+                chain_id,
+            )
+            .await
+            .map_err(RelayError::DomainError)
+    }
+
+    /// Checks a request signed elsewhere against the allowlist and budget, then the way `Forwarder.execute`
+    /// will: the signature against the forwarder's domain, the nonce against `getNonce(from)` at
+    /// the pending block, and the gas against what the call to the target needs. Returns the
+    /// request's [`digest`](Self::digest).
+    pub async fn verify(
+        &self,
+        request: &ForwardRequest,
+        signature: &[u8],
+    ) -> Result<B256, RelayError<M>> {
+        self.admit(request)?;
+
+        let client = self.forwarder_with_gas_signer.client_ref();
+        let forwarder = convert::to_alloy_address(self.forwarder_with_gas_signer.address());
+
+        let domain = self.domain().await?;
+        let nonce = nonce::get_nonce(client, forwarder, request.from, BlockNumber::Pending.into())
+            .await
+            .map_err(RelayError::FailedToGetNonce)?;
+
+        let digest = forward_request_digest(request, &domain);
+        ForwardRequestVerifier::new(domain)
+            .verify(request, signature, nonce)
+            .map_err(RelayError::VerificationFailed)?;
+
+        let required = gas::estimate_inner_gas(
+            client,
+            forwarder,
+            request.from,
+            request.to,
+            request.value,
+            &request.data,
+            None,
+        )
+        .await
+        .map_err(|e| match revert_data(&e) {
+            // The target was called directly, so its revert data is intact.
+            Some(data) => RelayError::Reverted(self.reverts.decode_target(&data)),
+            None => RelayError::FailedToEstimateGas(e),
+        })?;
+        gas::request_gas(required, Some(request.gas)).map_err(RelayError::GasError)?;
+
+        Ok(digest)
+    }
+
+    /// Submits `request`, signed with `signature`, through `Forwarder.execute` from the gas
+    /// wallet, or a wallet of the gas wallet pool, if the allowlist allows it and its signer has
+    /// budget left for the most it may cost.
+    ///
+    /// Before broadcasting, `execute` is simulated from the gas wallet at the pending block, and
+    /// a request that would fail is refused with
+    /// [`PreflightFailed`](RelayError::PreflightFailed). Set
+    /// [`skip_preflight`](MetaTransactionOptions::skip_preflight) to broadcast it anyway.
+    pub async fn relay(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        options: MetaTransactionOptions,
+    ) -> Result<PendingMetaTransaction<'_, M>, RelayError<M>> {
+        self.admit(&request)?;
+        let forwarder = convert::to_alloy_address(self.forwarder_with_gas_signer.address());
+        let lease = self
+            .gas_wallets
+            .as_ref()
+            .map(|pool| pool.select(request.from))
+            .transpose()
+            .map_err(RelayError::GasWallets)?;
+        let wallet = self.gas_wallets.as_ref().zip(lease.as_ref());
+        let wallet = wallet.map(|(pool, lease)| pool.wallet(lease));
+        let forwarder_with_gas_signer =
+            wallet.map_or(&self.forwarder_with_gas_signer, |wallet| wallet.forwarder());
+
+        // Give `execute` enough gas to pass `req.gas` on to the target.
+        let req_gas = request.gas;
+        let fn_call = forwarder_with_gas_signer
+            .execute(request.clone().into(), signature)
+            .value(convert::to_ethers_u256(request.value));
+        let execute_calldata = fn_call.calldata().unwrap_or_default();
+        let mut outer = match self.transaction_type {
+            Some(transaction_type) => transaction_type.convert(fn_call.tx),
+            None => fn_call.tx,
+        };
+        outer.set_gas(convert::to_ethers_u256(gas::outer_gas_limit(
+            req_gas,
+            &execute_calldata,
+        )));
+
+        let gas_client = forwarder_with_gas_signer.client_ref();
+        if let Some(strategy) = &self.fees {
+            strategy
+                .estimate(gas_client)
+                .await
+                .map_err(RelayError::FeeError)?
+                .apply(&mut outer);
+        }
+
+        // Simulate `execute` before the gas wallet's nonce is taken, so that a refused
+        // meta-transaction leaves no gap in it.
+        if !options.skip_preflight {
+            let mut call = outer.clone();
+            if call.from().is_none() {
+                if let Some(from) = gas_client.default_sender() {
+                    call.set_from(from);
+                }
+            }
+            preflight(gas_client, &call, options.block, &self.reverts)
+                .await
+                .map_err(RelayError::PreflightFailed)?;
+        }
+
+        // Set aside the most the meta-transaction may cost in the signer's budget, so that the
+        // requests in flight cannot take it over.
+        let reservation = match &self.budget {
+            Some(budget) => {
+                fill_fees(gas_client, &mut outer)
+                    .await
+                    .map_err(|e| RelayError::ContractError(ContractError::MiddlewareError { e }))?;
+                let cost = self
+                    .fees
+                    .clone()
+                    .unwrap_or_default()
+                    .max_cost(&outer, self.escalation.as_ref());
+                let reservation = budget
+                    .reserve(request.from, convert::to_alloy_u256(cost))
+                    .map_err(RelayError::OverBudget)?;
+                Some(reservation)
+            }
+            None => None,
+        };
+
+        // The gas wallet's nonce is read at the pending block, after the earlier
+        // meta-transactions that are still pending. A pool's wallets count theirs.
+        let block = Some(BlockNumber::Pending.into());
+        let sent = match wallet {
+            Some(wallet) => wallet.send(&mut outer, block).await,
+            None => match gas_client.fill_transaction(&mut outer, block).await {
+                Ok(()) => gas_client
+                    .send_transaction(outer.clone(), block)
+                    .await
+                    .map(|pending| pending.tx_hash()),
+                Err(e) => Err(e),
+            },
+        };
+
+        match sent {
+            Err(e) => match revert_data(&e) {
+                Some(data) => Err(RelayError::Reverted(self.reverts.decode_execute(&data))),
+                None => Err(RelayError::ContractError(ContractError::MiddlewareError {
+                    e,
+                })),
+            },
+            Ok(hash) => {
+                let escalating = EscalatingTransaction::new(
+                    gas_client,
+                    outer,
+                    hash,
+                    self.fees.clone().unwrap_or_default(),
+                    self.escalation.clone(),
+                    block,
+                );
+                let mut pending = PendingMetaTransaction::new(escalating, forwarder, request);
+                if let Some(reservation) = reservation {
+                    pending = pending.with_reservation(reservation);
+                }
+                if let Some(lease) = lease {
+                    pending = pending.with_lease(lease);
+                }
+                if let Some(nonces) = &self.nonces {
+                    pending = pending.with_nonces(nonces.clone());
+                }
+                Ok(pending)
+            }
+        }
+    }
+
+    /// Relays `request` like [`relay`](Self::relay), then follows the `execute` transaction to
+    /// its receipt from a task of its own. The spend is recorded in the budget's ledger, and the
+    /// pool's wallet stays leased, until the transaction is mined, even if nobody awaits the task.
+    ///
+    /// Returns the hash of the first broadcast and the task's handle.
+    pub async fn relay_in_background(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        options: MetaTransactionOptions,
+    ) -> Result<
+        (
+            H256,
+            JoinHandle<Result<Option<MetaTransactionReceipt>, ReceiptError>>,
+        ),
+        RelayError<M>,
+    >
+    where
+        M: 'static,
+    {
+        let pending = self.relay(request, signature, options).await?;
+        let gas_client = match pending.lease() {
+            Some(lease) => lease.wallet().forwarder().client(),
+            None => self.forwarder_with_gas_signer.client(),
+        };
+        let tx_hash = pending.tx_hash();
+        let follower = pending.into_shared(gas_client).into_future();
+        Ok((tx_hash, tokio::spawn(follower)))
+    }
+}
+
+/// Prices `tx` the way the gas wallet's client would when sending it, if it has no price yet.
+async fn fill_fees<M: Middleware>(client: &M, tx: &mut TypedTransaction) -> Result<(), M::Error> {
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            if tx.max_fee_per_gas.is_none() || tx.max_priority_fee_per_gas.is_none() {
+                let (max_fee, priority_fee) = client.estimate_eip1559_fees(None).await?;
+                tx.max_fee_per_gas.get_or_insert(max_fee);
+                tx.max_priority_fee_per_gas.get_or_insert(priority_fee);
+            }
+        }
+        tx => {
+            if tx.gas_price().is_none() {
+                tx.set_gas_price(client.get_gas_price().await?);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use ethers::{
+    abi::{Abi, AbiDecode, Token},
+    contract::EthError,
+    providers::MiddlewareError,
+    types::{Bytes, U256},
+};
+use thiserror::Error;
+
+use super::abi;
+
+/// The revert string `Forwarder.execute` uses for a request whose nonce was already used.
+pub const NONCE_NOT_INCREASING: &str = "Nonce is not strictly increasing";
+
+/// The revert string `Forwarder.execute` substitutes for target revert data shorter than 68
+/// bytes.
+pub const REVERTED_SILENTLY: &str = "Transaction reverted silently";
+
+/// Selector of the compiler's `Panic(uint256)` error.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Why a meta-transaction reverted.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RevertReason {
+    /// The forwarder rejected the request, or failed after the call; the target was not at fault.
+    #[error("Forwarder rejected the request: {0}")]
+    Forwarder(ForwarderRevert),
+
+    /// The call to the target reverted.
+    #[error("Target reverted: {0}")]
+    Target(TargetRevert),
+
+    /// Revert data that matches no known error. It is empty for a bare `revert()` or when the
+    /// forwarder could not re-raise the target's revert data (see [`RevertDecoder`]).
+    #[error("Reverted with unknown data {0}")]
+    Unknown(Bytes),
+}
+
+/// A failure in `Forwarder.execute` itself.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ForwarderRevert {
+    /// The signature is not the request signer's, or the nonce is not the current one.
+    #[error("signature does not match")]
+    SignatureDoesNotMatch,
+
+    #[error("nonce is not strictly increasing")]
+    NonceNotIncreasing,
+
+    /// A compiler panic. Code `0x01` is `assert(gasleft() > req.gas / 63)`: the `execute`
+    /// transaction did not have enough gas to pass `req.gas` on to the target.
+    #[error("panic {0:#x}")]
+    Panic(U256),
+
+    /// Another custom error of the forwarder, e.g. `ECDSAInvalidSignatureLength` for a malformed
+    /// signature.
+    #[error("{0}")]
+    Custom(CustomError),
+}
+
+/// A revert of the target contract.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TargetRevert {
+    /// `Error(string)`, e.g. from `require(cond, "message")`.
+    #[error("{0}")]
+    Message(String),
+
+    /// The target reverted with less than 68 bytes of data, which the forwarder replaces with
+    /// "Transaction reverted silently". This covers custom errors without arguments, panics and
+    /// bare reverts.
+    #[error("reverted without a reason the forwarder could pass on")]
+    Silent,
+
+    /// A compiler panic. Only seen when the target is called directly, e.g. while estimating gas.
+    #[error("panic {0:#x}")]
+    Panic(U256),
+
+    /// A custom error of one of the registered contracts.
+    #[error("{0}")]
+    Custom(CustomError),
+}
+
+/// A decoded custom error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomError {
+    /// The name the contract was registered under.
+    pub contract: String,
+    pub name: String,
+    pub args: Vec<Token>,
+    /// The raw revert data, e.g. for `abi::ForwarderErrors::decode_with_selector`.
+    pub data: Bytes,
+}
+
+impl fmt::Display for CustomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}(", self.contract, self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Decodes revert data into a [`RevertReason`], with a registry of contracts whose custom errors
+/// it knows.
+///
+/// `Forwarder.execute` re-raises a target's revert data by decoding it as a string. Only
+/// `Error(string)` survives this intact: shorter data becomes [`TargetRevert::Silent`], and a
+/// custom error with arguments usually becomes an empty revert. The target's own errors are
+/// decoded in full where the target is called directly, i.e. when the relayer estimates the
+/// inner call's gas (see [`decode_target`](Self::decode_target)).
+#[derive(Debug, Clone)]
+pub struct RevertDecoder {
+    contracts: Vec<(String, Abi)>,
+}
+
+impl Default for RevertDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RevertDecoder {
+    /// A decoder that knows the errors of `Forwarder` and `CounterByAddress`.
+    pub fn new() -> Self {
+        Self { contracts: vec![] }
+            .with_contract("Forwarder", abi::FORWARDER_ABI.clone())
+            .with_contract("CounterByAddress", abi::COUNTERBYADDRESS_ABI.clone())
+    }
+
+    /// Registers the custom errors of another contract.
+    pub fn with_contract(mut self, name: impl Into<String>, abi: Abi) -> Self {
+        self.contracts.push((name.into(), abi));
+        self
+    }
+
+    /// Decodes the revert data of `Forwarder.execute`.
+    pub fn decode_execute(&self, data: &[u8]) -> RevertReason {
+        if data.starts_with(&abi::forwarder::SignatureDoesNotMatch::selector()) {
+            return RevertReason::Forwarder(ForwarderRevert::SignatureDoesNotMatch);
+        }
+        if let Some(error) = decode_custom("Forwarder", &abi::FORWARDER_ABI, data) {
+            return RevertReason::Forwarder(ForwarderRevert::Custom(error));
+        }
+
+        match self.decode_target(data) {
+            RevertReason::Target(TargetRevert::Message(message)) => match message.as_str() {
+                NONCE_NOT_INCREASING => {
+                    RevertReason::Forwarder(ForwarderRevert::NonceNotIncreasing)
+                }
+                REVERTED_SILENTLY => RevertReason::Target(TargetRevert::Silent),
+                _ => RevertReason::Target(TargetRevert::Message(message)),
+            },
+            // The forwarder turns the target's panics into silent reverts, so a panic is its own.
+            RevertReason::Target(TargetRevert::Panic(code)) => {
+                RevertReason::Forwarder(ForwarderRevert::Panic(code))
+            }
+            reason => reason,
+        }
+    }
+
+    /// Decodes the revert data of a direct call to a target contract.
+    pub fn decode_target(&self, data: &[u8]) -> RevertReason {
+        if let Some(message) = String::decode_with_selector(data) {
+            return RevertReason::Target(TargetRevert::Message(message));
+        }
+        if let Some(code) = data
+            .strip_prefix(&PANIC_SELECTOR)
+            .and_then(|code| U256::decode(code).ok())
+        {
+            return RevertReason::Target(TargetRevert::Panic(code));
+        }
+
+        if let Some(error) = self
+            .contracts
+            .iter()
+            .find_map(|(contract, abi)| decode_custom(contract, abi, data))
+        {
+            return RevertReason::Target(TargetRevert::Custom(error));
+        }
+
+        RevertReason::Unknown(Bytes::from(data.to_vec()))
+    }
+}
+
+fn decode_custom(contract: &str, abi: &Abi, data: &[u8]) -> Option<CustomError> {
+    let selector = data.get(..4)?;
+    abi.errors().find_map(|error| {
+        if &error.signature()[..4] != selector {
+            return None;
+        }
+        let args = error.decode(&data[4..]).ok()?;
+        Some(CustomError {
+            contract: contract.to_string(),
+            name: error.name.clone(),
+            args,
+            data: Bytes::from(data.to_vec()),
+        })
+    })
+}
+
+/// Extracts the revert data from a JSON-RPC error, if it is a revert.
+pub fn revert_data<E: MiddlewareError>(error: &E) -> Option<Bytes> {
+    error.as_error_response()?.as_revert_data()
+}
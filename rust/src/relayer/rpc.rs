@@ -0,0 +1,105 @@
+//! The JSON-RPC methods and error codes of a [`RelayServer`](crate::server::RelayServer), shared
+//! with [`HttpRelayTransport`](super::HttpRelayTransport).
+
+use alloy::primitives::{Address, U256};
+use ethers::providers::Middleware;
+use serde::{Deserialize, Serialize};
+
+use super::{BudgetError, PolicyError, RelayError, TransportError};
+
+pub const SEND_FORWARD_REQUEST: &str = "relay_sendForwardRequest";
+pub const GET_STATUS: &str = "relay_getStatus";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The request is well-formed but will not be relayed: the allowlist does not allow it, its
+/// signature, nonce or gas is wrong, or the call would revert. Allowlist and budget rejections
+/// carry their [`Rejection`] as the error's data.
+pub const REQUEST_REJECTED: i64 = -32000;
+/// The signer or client sent too many requests. The error's data says when to retry.
+pub const RATE_LIMITED: i64 = -32005;
+
+/// The data of a [`REQUEST_REJECTED`] error that names the allowlist rule or the budget it
+/// failed, so that clients get back the typed error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    Policy(PolicyError),
+    OverBudget {
+        from: Address,
+        spent: U256,
+        max: U256,
+        window_secs: u64,
+    },
+    InsufficientBudget {
+        from: Address,
+        remaining: U256,
+        cost: U256,
+        window_secs: u64,
+    },
+}
+
+impl Rejection {
+    /// The rejection `error` stands for, if it is an allowlist or budget rejection.
+    pub fn of<M: Middleware>(error: &RelayError<M>) -> Option<Self> {
+        match error {
+            RelayError::PolicyRejected(e) => Some(Rejection::Policy(e.clone())),
+            RelayError::OverBudget(BudgetError::Exhausted {
+                from,
+                spent,
+                max,
+                window_secs,
+            }) => Some(Rejection::OverBudget {
+                from: *from,
+                spent: *spent,
+                max: *max,
+                window_secs: *window_secs,
+            }),
+            RelayError::OverBudget(BudgetError::Insufficient {
+                from,
+                remaining,
+                cost,
+                window_secs,
+            }) => Some(Rejection::InsufficientBudget {
+                from: *from,
+                remaining: *remaining,
+                cost: *cost,
+                window_secs: *window_secs,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<Rejection> for TransportError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::Policy(e) => TransportError::PolicyRejected(e),
+            Rejection::OverBudget {
+                from,
+                spent,
+                max,
+                window_secs,
+            } => TransportError::OverBudget(BudgetError::Exhausted {
+                from,
+                spent,
+                max,
+                window_secs,
+            }),
+            Rejection::InsufficientBudget {
+                from,
+                remaining,
+                cost,
+                window_secs,
+            } => TransportError::OverBudget(BudgetError::Insufficient {
+                from,
+                remaining,
+                cost,
+                window_secs,
+            }),
+        }
+    }
+}
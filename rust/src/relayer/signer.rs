@@ -0,0 +1,251 @@
+use std::fmt::Debug;
+
+use alloy::{
+    dyn_abi::TypedData,
+    primitives::{keccak256, Address, PrimitiveSignature},
+    signers::{local::PrivateKeySigner, Signer},
+    sol_types::{Eip712Domain, SolStruct},
+};
+use async_trait::async_trait;
+use ethers::{
+    providers::Middleware,
+    signers::{LocalWallet, Signer as EthersSigner},
+    types::{transaction::eip712::EIP712Domain, Bytes},
+};
+use thiserror::Error;
+
+use super::{alloy_structs, convert, ForwardRequest};
+
+#[derive(Error, Debug)]
+pub enum MetaSignerError {
+    #[error("{0}")]
+    SignerError(String),
+
+    #[error("eth_signTypedData_v4 failed: {0}")]
+    RpcError(String),
+
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+/// Signs `ForwardRequest`s on behalf of the meta-transaction sender.
+///
+/// Implemented for alloy's [`PrivateKeySigner`] and ethers' [`LocalWallet`]. Any other alloy or
+/// ethers signer (keystores, mnemonics, hardware wallets, ...) can be used through
+/// [`AlloyMetaSigner`] or [`EthersMetaSigner`], and accounts managed by a node through
+/// [`NodeMetaSigner`].
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait MetaSigner: Debug + Send + Sync {
+    /// The address requests are signed by, i.e. the `from` of every `ForwardRequest`.
+    fn address(&self) -> Address;
+
+    /// Returns the EIP-712 signature over `request` in `domain`.
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError>;
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl MetaSigner for PrivateKeySigner {
+    fn address(&self) -> Address {
+        Signer::address(self)
+    }
+
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        AlloyMetaSigner::sign(self, request, domain).await
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl MetaSigner for LocalWallet {
+    fn address(&self) -> Address {
+        convert::to_alloy_address(EthersSigner::address(self))
+    }
+
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        EthersMetaSigner::sign(self, request, domain).await
+    }
+}
+
+/// Adapts any alloy [`Signer`] into a [`MetaSigner`].
+#[derive(Debug, Clone)]
+pub struct AlloyMetaSigner<S>(pub S);
+
+impl<S: Signer + Send + Sync> AlloyMetaSigner<S> {
+    async fn sign(
+        signer: &S,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        let request: alloy_structs::ForwardRequest = request.clone().into();
+        signer
+            .sign_typed_data(&request, domain)
+            .await
+            .map_err(|e| MetaSignerError::SignerError(e.to_string()))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<S> MetaSigner for AlloyMetaSigner<S>
+where
+    S: Signer + Debug + Send + Sync,
+{
+    fn address(&self) -> Address {
+        self.0.address()
+    }
+
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        Self::sign(&self.0, request, domain).await
+    }
+}
+
+/// Adapts any ethers [`Signer`](EthersSigner) into a [`MetaSigner`].
+#[derive(Debug, Clone)]
+pub struct EthersMetaSigner<S>(pub S);
+
+impl<S: EthersSigner> EthersMetaSigner<S> {
+    async fn sign(
+        signer: &S,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        let payload = TypedForwardRequest {
+            request: request.clone().into(),
+            domain: domain.clone(),
+        };
+        let signature = signer
+            .sign_typed_data(&payload)
+            .await
+            .map_err(|e| MetaSignerError::SignerError(e.to_string()))?;
+        PrimitiveSignature::from_raw(&signature.to_vec())
+            .map_err(|e| MetaSignerError::InvalidSignature(e.to_string()))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<S> MetaSigner for EthersMetaSigner<S>
+where
+    S: EthersSigner,
+{
+    fn address(&self) -> Address {
+        convert::to_alloy_address(self.0.address())
+    }
+
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        Self::sign(&self.0, request, domain).await
+    }
+}
+
+/// A `ForwardRequest` as ethers' `Eip712` payload.
+///
+/// The hashes are computed by alloy because ethers encodes `bytes` fields incorrectly.
+struct TypedForwardRequest {
+    request: alloy_structs::ForwardRequest,
+    domain: Eip712Domain,
+}
+
+impl ethers::types::transaction::eip712::Eip712 for TypedForwardRequest {
+    type Error = std::convert::Infallible;
+
+    fn domain_separator(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(self.domain.separator().0)
+    }
+
+    fn domain(&self) -> Result<EIP712Domain, Self::Error> {
+        Ok(EIP712Domain {
+            name: self.domain.name.as_ref().map(|name| name.to_string()),
+            version: self
+                .domain
+                .version
+                .as_ref()
+                .map(|version| version.to_string()),
+            chain_id: self.domain.chain_id.map(convert::to_ethers_u256),
+            verifying_contract: self
+                .domain
+                .verifying_contract
+                .map(convert::to_ethers_address),
+            salt: self.domain.salt.map(|salt| salt.0),
+        })
+    }
+
+    fn type_hash() -> Result<[u8; 32], Self::Error> {
+        Ok(keccak256(alloy_structs::ForwardRequest::eip712_encode_type().as_bytes()).0)
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(self.request.eip712_hash_struct().0)
+    }
+
+    fn encode_eip712(&self) -> Result<[u8; 32], Self::Error> {
+        Ok(self.request.eip712_signing_hash(&self.domain).0)
+    }
+}
+
+/// A [`MetaSigner`] for an account whose key is held by the node, e.g. one of anvil's unlocked
+/// accounts. Requests are signed with `eth_signTypedData_v4`.
+#[derive(Debug, Clone)]
+pub struct NodeMetaSigner<M> {
+    client: M,
+    address: Address,
+}
+
+impl<M> NodeMetaSigner<M> {
+    pub fn new(client: M, address: Address) -> Self {
+        Self { client, address }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M> MetaSigner for NodeMetaSigner<M>
+where
+    M: Middleware,
+{
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_forward_request(
+        &self,
+        request: &ForwardRequest,
+        domain: &Eip712Domain,
+    ) -> Result<PrimitiveSignature, MetaSignerError> {
+        let request: alloy_structs::ForwardRequest = request.clone().into();
+        let typed_data = TypedData::from_struct(&request, Some(domain.clone()));
+
+        let signature: Bytes = self
+            .client
+            .provider()
+            .request(
+                "eth_signTypedData_v4",
+                (convert::to_ethers_address(self.address), typed_data),
+            )
+            .await
+            .map_err(|e| MetaSignerError::RpcError(e.to_string()))?;
+        PrimitiveSignature::from_raw(&signature)
+            .map_err(|e| MetaSignerError::InvalidSignature(e.to_string()))
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    abi::{AbiDecode, AbiEncode},
+    providers::{Middleware, MiddlewareError, PendingTransaction},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockId, BlockNumber, Bytes,
+        Eip1559TransactionRequest, U256,
+    },
+};
+use thiserror::Error;
+
+use super::{
+    abi, convert, gas, nonce, revert::revert_data, DomainError, ForwardRequest,
+    ForwardRequestVerifier, ForwarderDomains, ForwarderNonces, GasError, MetaSigner,
+    NonceReservation, RevertDecoder, RevertReason, VerifyError,
+};
+
+/// Rewrites a transaction into a `Forwarder.execute` call carrying it as a signed `ForwardRequest`.
+///
+/// Unlike ethers' `Transformer`, [`transform`](Self::transform) is async: the forwarder nonce, the
+/// inner call's gas and the chain id are all resolved through the client it is given. The gas
+/// wallet's `from` and `nonce` are cleared so that they are filled in by the layers below, and
+/// `gas` is set to the limit `execute` needs to pass the signed `ForwardRequest.gas` on.
+/// Forwarder nonces are handed out by [`ForwarderNonces`], so concurrent transforms for the same
+/// meta signer get consecutive nonces.
+#[derive(Debug, Clone)]
+pub struct EIP2771GasRelayerTransformer {
+    /// This is the signer that will sign the meta-transaction. This is NOT the signer that
+    /// will send the transaction to the Forwarder contract.
+    transaction_signer: Arc<dyn MetaSigner>,
+    forwarder: Address,
+    domains: ForwarderDomains,
+    nonces: ForwarderNonces,
+    reverts: RevertDecoder,
+}
+
+impl EIP2771GasRelayerTransformer {
+    pub fn new<S: MetaSigner + 'static>(transaction_signer: S, forwarder: Address) -> Self {
+        Self {
+            transaction_signer: Arc::new(transaction_signer),
+            forwarder,
+            domains: ForwarderDomains::new(),
+            nonces: ForwarderNonces::new(),
+            reverts: RevertDecoder::new(),
+        }
+    }
+
+    /// Shares a cache of forwarder domains, e.g. between several transformers.
+    pub fn with_domains(mut self, domains: ForwarderDomains) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Shares the forwarder nonces, e.g. between several transformers for the same meta signer.
+    pub fn with_nonces(mut self, nonces: ForwarderNonces) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    /// The forwarder nonces handed out to the meta signer.
+    pub fn nonces(&self) -> &ForwarderNonces {
+        &self.nonces
+    }
+
+    /// Decodes reverts with `reverts`, e.g. one that knows the custom errors of more targets.
+    pub fn with_revert_decoder(mut self, reverts: RevertDecoder) -> Self {
+        self.reverts = reverts;
+        self
+    }
+
+    /// The decoder for the reverts of `execute` and of the targets.
+    pub fn reverts(&self) -> &RevertDecoder {
+        &self.reverts
+    }
+
+    /// The address the meta-transactions are signed by.
+    pub fn address(&self) -> Address {
+        convert::to_ethers_address(self.transaction_signer.address())
+    }
+
+    pub fn forwarder(&self) -> Address {
+        self.forwarder
+    }
+
+    /// Returns the forwarder's current nonce for the meta signer.
+    pub async fn get_nonce<M: Middleware>(
+        &self,
+        client: &M,
+        block: Option<BlockId>,
+    ) -> Result<U256, EIP2771GasRelayerTransformerError<M>> {
+        let call = abi::forwarder::GetNonceCall {
+            from: self.address(),
+        };
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(self.forwarder)
+            .data(call.encode())
+            .into();
+
+        let result = client
+            .call(&tx, block)
+            .await
+            .map_err(|e| EIP2771GasRelayerTransformerError::FailedToGetNonce(e.to_string()))?;
+        U256::decode(result)
+            .map_err(|e| EIP2771GasRelayerTransformerError::FailedToGetNonce(e.to_string()))
+    }
+
+    /// Signs `tx` as a `ForwardRequest` from the meta signer and rewrites it in place into the
+    /// `Forwarder.execute` call that submits it. The transaction type is preserved.
+    ///
+    /// The returned reservation holds the forwarder nonce the request was signed with. Commit it
+    /// once `tx` has been broadcast; until then, other transforms for the same signer wait.
+    pub async fn transform<M: Middleware>(
+        &self,
+        client: &M,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<NonceReservation, EIP2771GasRelayerTransformerError<M>> {
+        let from = self.address();
+        let to = *tx
+            .to()
+            .ok_or(EIP2771GasRelayerTransformerError::MissingToAddress)?
+            .as_address()
+            .ok_or(EIP2771GasRelayerTransformerError::ConversionError(
+                "To is not an address".to_string(),
+            ))?;
+        let value = tx.value().copied().unwrap_or_default();
+        let data = tx.data().cloned().unwrap_or_default();
+
+        let reservation = self
+            .nonces
+            .reserve(
+                client,
+                convert::to_alloy_address(self.forwarder),
+                convert::to_alloy_address(from),
+            )
+            .await
+            .map_err(|e| EIP2771GasRelayerTransformerError::FailedToGetNonce(e.to_string()))?;
+        let nonce = convert::to_ethers_u256(reservation.nonce());
+
+        // Estimate the gas needed for the inner call, as the forwarder makes it. A gas limit set
+        // on the transaction is taken as the requested `ForwardRequest.gas`.
+        let required = gas::estimate_inner_gas(
+            client,
+            convert::to_alloy_address(self.forwarder),
+            convert::to_alloy_address(from),
+            convert::to_alloy_address(to),
+            convert::to_alloy_u256(value),
+            &data,
+            block,
+        )
+        .await
+        .map_err(|e| match revert_data(&e) {
+            // The target was called directly, so its revert data is intact.
+            Some(data) => {
+                EIP2771GasRelayerTransformerError::Reverted(self.reverts.decode_target(&data))
+            }
+            None => EIP2771GasRelayerTransformerError::FailedToEstimateGas(e),
+        })?;
+        let gas = gas::request_gas(required, tx.gas().copied().map(convert::to_alloy_u256))
+            .map_err(EIP2771GasRelayerTransformerError::GasError)?;
+
+        let chain_id = match tx.chain_id() {
+            Some(chain_id) => chain_id.as_u64(),
+            None => client
+                .get_chainid()
+                .await
+                .map_err(EIP2771GasRelayerTransformerError::MissingChainID)?
+                .as_u64(),
+        };
+
+        let request = ForwardRequest {
+            from: convert::to_alloy_address(from),
+            to: convert::to_alloy_address(to),
+            value: convert::to_alloy_u256(value),
+            gas,
+            nonce: convert::to_alloy_u256(nonce),
+            data: convert::to_alloy_bytes(data),
+        };
+
+        let signature = {
+            let domain = self
+                .domains
+                .get(client, convert::to_alloy_address(self.forwarder), chain_id)
+                .await
+                .map_err(EIP2771GasRelayerTransformerError::DomainError)?;
+            let signature = self
+                .transaction_signer
+                .sign_forward_request(&request, &domain)
+                .await
+                .map_err(|e| EIP2771GasRelayerTransformerError::SignerError(e.to_string()))?;
+
+            // Replay `Forwarder.verify` against the forwarder's own nonce, so that a local nonce
+            // that is out of step is diagnosed, and read again, before any gas is spent.
+            let expected = nonce::get_nonce(
+                client,
+                convert::to_alloy_address(self.forwarder),
+                request.from,
+                BlockNumber::Pending.into(),
+            )
+            .await
+            .map_err(EIP2771GasRelayerTransformerError::FailedToGetNonce)?;
+            if let Err(e) = ForwardRequestVerifier::new(domain).verify(
+                &request,
+                &signature.as_bytes(),
+                expected,
+            ) {
+                if matches!(e, VerifyError::NonceMismatch { .. }) {
+                    reservation.resync();
+                }
+                return Err(EIP2771GasRelayerTransformerError::VerificationFailed(e));
+            }
+
+            Bytes::from(signature.as_bytes())
+        };
+
+        let execute = abi::forwarder::ExecuteCall {
+            req: request.into(),
+            signature,
+        }
+        .encode();
+        let outer_gas = gas::outer_gas_limit(gas, &execute);
+
+        // The forwarder passes `req.value` on to the target, so the outer transaction has to
+        // carry it.
+        tx.set_to(self.forwarder)
+            .set_value(value)
+            .set_data(execute.into())
+            .set_chain_id(chain_id)
+            .set_gas(convert::to_ethers_u256(outer_gas));
+        clear_sender_fields(tx);
+
+        Ok(reservation)
+    }
+}
+
+/// Clears `from` and `nonce`, which belong to the gas wallet rather than the meta signer.
+fn clear_sender_fields(tx: &mut TypedTransaction) {
+    match tx {
+        TypedTransaction::Legacy(tx) => {
+            tx.from = None;
+            tx.nonce = None;
+        }
+        TypedTransaction::Eip2930(tx) => {
+            tx.tx.from = None;
+            tx.tx.nonce = None;
+        }
+        TypedTransaction::Eip1559(tx) => {
+            tx.from = None;
+            tx.nonce = None;
+        }
+    }
+}
+
+/// A middleware that relays every transaction through [`EIP2771GasRelayerTransformer`].
+///
+/// It is meant to sit on top of a stock ethers stack for the gas wallet, which keeps ownership of
+/// the gas wallet's nonce and fees:
+///
+/// ```ignore
+/// let client = SignerMiddleware::new(provider, gas_wallet);
+/// let client = GasEscalatorMiddleware::new(client, escalator, Frequency::PerBlock);
+/// let client = NonceManagerMiddleware::new(client, gas_wallet_address);
+/// let client = EIP2771GasRelayerTransformerMiddleware::new(
+///     client,
+///     EIP2771GasRelayerTransformer::new(meta_signer, forwarder),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct EIP2771GasRelayerTransformerMiddleware<M> {
+    inner: M,
+    transformer: EIP2771GasRelayerTransformer,
+}
+
+impl<M> EIP2771GasRelayerTransformerMiddleware<M> {
+    pub fn new(inner: M, transformer: EIP2771GasRelayerTransformer) -> Self {
+        Self { inner, transformer }
+    }
+
+    pub fn transformer(&self) -> &EIP2771GasRelayerTransformer {
+        &self.transformer
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EIP2771GasRelayerTransformerError<M: Middleware> {
+    #[error("{0}")]
+    SignerError(String),
+
+    #[error("{0}")]
+    MiddlewareError(M::Error),
+
+    #[error("Failed to get nonce: {0}")]
+    FailedToGetNonce(String),
+
+    #[error("Failed to estimate gas: {0}")]
+    FailedToEstimateGas(M::Error),
+
+    #[error("{0}")]
+    Reverted(RevertReason),
+
+    #[error("Failed to get chain ID: {0}")]
+    MissingChainID(M::Error),
+
+    #[error("{0}")]
+    DomainError(DomainError),
+
+    #[error("Meta-transaction would be rejected by the forwarder: {0}")]
+    VerificationFailed(VerifyError),
+
+    #[error("{0}")]
+    GasError(GasError),
+
+    #[error("Missing to address")]
+    MissingToAddress,
+
+    #[error("Conversion error: {0}")]
+    ConversionError(String),
+}
+
+impl<M> MiddlewareError for EIP2771GasRelayerTransformerError<M>
+where
+    M: Middleware,
+{
+    type Inner = M::Error;
+
+    fn from_err(src: M::Error) -> Self {
+        EIP2771GasRelayerTransformerError::MiddlewareError(src)
+    }
+
+    fn as_inner(&self) -> Option<&Self::Inner> {
+        match self {
+            EIP2771GasRelayerTransformerError::MiddlewareError(e) => Some(e),
+            EIP2771GasRelayerTransformerError::FailedToEstimateGas(e) => Some(e),
+            EIP2771GasRelayerTransformerError::MissingChainID(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M> Middleware for EIP2771GasRelayerTransformerMiddleware<M>
+where
+    M: Middleware,
+{
+    type Error = EIP2771GasRelayerTransformerError<M>;
+    type Provider = M::Provider;
+    type Inner = M;
+
+    fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    async fn send_transaction<Tx: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: Tx,
+        block: Option<BlockId>,
+    ) -> Result<PendingTransaction<'_, Self::Provider>, Self::Error> {
+        let mut tx = tx.into();
+
+        // construct the forwarder tx.
+        let reservation = self
+            .transformer
+            .transform(&self.inner, &mut tx, block)
+            .await?;
+
+        // let the gas wallet's layers fill in its nonce and fees. The nonce has to come after the
+        // earlier meta-transactions that are still pending.
+        let block = block.or(Some(BlockNumber::Pending.into()));
+        let sent = match self.inner.fill_transaction(&mut tx, block).await {
+            Ok(()) => self.inner.send_transaction(tx, block).await,
+            Err(e) => Err(e),
+        };
+
+        match sent {
+            Ok(pending) => {
+                reservation.commit();
+                Ok(pending)
+            }
+            Err(e) => {
+                reservation.resync();
+                match revert_data(&e) {
+                    Some(data) => Err(EIP2771GasRelayerTransformerError::Reverted(
+                        self.transformer.reverts().decode_execute(&data),
+                    )),
+                    None => Err(EIP2771GasRelayerTransformerError::MiddlewareError(e)),
+                }
+            }
+        }
+    }
+}
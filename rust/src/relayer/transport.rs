@@ -0,0 +1,355 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::{Future, IntoFuture},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alloy::primitives::B256;
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, Middleware, Provider, ProviderError, RpcError},
+    types::{Bytes, H256},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    rpc::{Rejection, GET_STATUS, RATE_LIMITED, REQUEST_REJECTED, SEND_FORWARD_REQUEST},
+    BudgetError, ForwardRequest, MetaTransactionOptions, MetaTransactionReceipt,
+    PendingMetaTransaction, PolicyError, PreflightError, QueueError, ReceiptError, RelayError,
+    Relayer, RevertReason,
+};
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    /// The relayer refused the request, e.g. because its signature, nonce or gas is wrong.
+    #[error("Relayer rejected the request: {0}")]
+    Rejected(String),
+
+    #[error("{0}")]
+    PolicyRejected(PolicyError),
+
+    #[error("{0}")]
+    OverBudget(BudgetError),
+
+    /// The relayer is refusing requests from this signer or client for now.
+    #[error("{reason}")]
+    RateLimited {
+        reason: String,
+        retry_after: Duration,
+    },
+
+    #[error("{0}")]
+    Reverted(RevertReason),
+
+    #[error("{0}")]
+    PreflightFailed(PreflightError),
+
+    /// The relayer could not be reached, or failed to submit the request.
+    #[error("Relay failed: {0}")]
+    Failed(String),
+
+    #[error("{0}")]
+    Receipt(ReceiptError),
+}
+
+impl<M: Middleware> From<RelayError<M>> for TransportError {
+    fn from(e: RelayError<M>) -> Self {
+        match e {
+            RelayError::PolicyRejected(e) => TransportError::PolicyRejected(e),
+            RelayError::OverBudget(e) => TransportError::OverBudget(e),
+            RelayError::RateLimited(e) => TransportError::RateLimited {
+                reason: e.to_string(),
+                retry_after: e.retry_after(),
+            },
+            RelayError::Reverted(reason) => TransportError::Reverted(reason),
+            RelayError::PreflightFailed(e) => TransportError::PreflightFailed(e),
+            e @ (RelayError::VerificationFailed(_)
+            | RelayError::GasError(_)
+            | RelayError::Queue(QueueError::NonceTaken { .. })) => {
+                TransportError::Rejected(e.to_string())
+            }
+            e => TransportError::Failed(e.to_string()),
+        }
+    }
+}
+
+/// Delivers signed `ForwardRequest`s to a relayer, which submits them through
+/// `Forwarder.execute`.
+///
+/// [`Relayer`] delivers them in-process from a local gas wallet, [`HttpRelayTransport`] to a
+/// [`RelayServer`](crate::server::RelayServer) over JSON-RPC, and [`MockRelayTransport`] only
+/// records them. See [`EIP2771GasRelayerMiddleware::with_transport`](super::EIP2771GasRelayerMiddleware::with_transport).
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait RelayTransport: fmt::Debug + Send + Sync {
+    /// Delivers `request`, signed with `signature`, and returns once its `execute` transaction
+    /// has been broadcast.
+    async fn send(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        options: MetaTransactionOptions,
+    ) -> Result<RelayedTransaction<'_>, TransportError>;
+}
+
+type ReceiptFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<Option<MetaTransactionReceipt>, TransportError>> + Send + 'a>,
+>;
+
+/// A meta-transaction whose `execute` transaction has been broadcast by a [`RelayTransport`].
+///
+/// Awaiting it resolves to the [`MetaTransactionReceipt`], or `None` if the `execute`
+/// transaction was never mined, e.g. because the gas wallet's nonce was used by another
+/// transaction.
+pub struct RelayedTransaction<'a> {
+    tx_hash: H256,
+    request: ForwardRequest,
+    receipt: ReceiptFuture<'a>,
+}
+
+impl<'a> RelayedTransaction<'a> {
+    pub fn new(
+        tx_hash: H256,
+        request: ForwardRequest,
+        receipt: impl Future<Output = Result<Option<MetaTransactionReceipt>, TransportError>>
+            + Send
+            + 'a,
+    ) -> Self {
+        Self {
+            tx_hash,
+            request,
+            receipt: Box::pin(receipt),
+        }
+    }
+
+    /// The hash of the first broadcast of the `execute` transaction.
+    pub fn tx_hash(&self) -> H256 {
+        self.tx_hash
+    }
+
+    /// The signed request.
+    pub fn request(&self) -> &ForwardRequest {
+        &self.request
+    }
+}
+
+impl fmt::Debug for RelayedTransaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayedTransaction")
+            .field("tx_hash", &self.tx_hash)
+            .field("request", &self.request)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> IntoFuture for RelayedTransaction<'a> {
+    type Output = Result<Option<MetaTransactionReceipt>, TransportError>;
+    type IntoFuture = ReceiptFuture<'a>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.receipt
+    }
+}
+
+impl<'a, M: Middleware + 'a> From<PendingMetaTransaction<'a, M>> for RelayedTransaction<'a> {
+    fn from(pending: PendingMetaTransaction<'a, M>) -> Self {
+        let tx_hash = pending.tx_hash();
+        let request = pending.request().clone();
+        RelayedTransaction::new(tx_hash, request, async move {
+            pending.await.map_err(TransportError::Receipt)
+        })
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M: Middleware> RelayTransport for Relayer<M> {
+    async fn send(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        options: MetaTransactionOptions,
+    ) -> Result<RelayedTransaction<'_>, TransportError> {
+        let pending = self.relay(request, signature, options).await?;
+        Ok(pending.into())
+    }
+}
+
+/// What became of a request sent to a [`RelayServer`](crate::server::RelayServer).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RelayStatus {
+    /// The request was accepted, and `execute` is not broadcast yet.
+    Accepted,
+
+    /// `execute` was broadcast with this hash and is not mined yet. Fee escalation may mine a
+    /// replacement with another hash; those broadcast so far are listed, oldest first.
+    Pending {
+        tx_hash: H256,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        replacements: Vec<H256>,
+    },
+
+    /// `execute` was mined.
+    Mined(Box<MetaTransactionReceipt>),
+
+    /// `execute` could not be followed to a receipt, e.g. because the gas wallet's nonce was used
+    /// by another transaction.
+    Failed { reason: String },
+}
+
+/// Delivers requests to a [`RelayServer`](crate::server::RelayServer) with
+/// `relay_sendForwardRequest`, and follows them with `relay_getStatus`.
+///
+/// The server decides how requests are simulated and priced, so the
+/// [`MetaTransactionOptions`] are not sent.
+#[derive(Debug, Clone)]
+pub struct HttpRelayTransport {
+    client: Provider<Http>,
+    poll_interval: Duration,
+}
+
+impl HttpRelayTransport {
+    /// A transport to the server at `client`'s URL, e.g.
+    /// `Provider::<Http>::try_from("http://127.0.0.1:8546")`.
+    pub fn new(client: Provider<Http>) -> Self {
+        Self {
+            client,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// How often to ask the server for the status of a pending request. Defaults to a second.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    async fn status(&self, id: B256) -> Result<RelayStatus, TransportError> {
+        let status: Option<RelayStatus> = self
+            .client
+            .request(GET_STATUS, [id])
+            .await
+            .map_err(rpc_error)?;
+        status.ok_or_else(|| TransportError::Failed(format!("Relayer does not know request {id}")))
+    }
+}
+
+fn rpc_error(e: ProviderError) -> TransportError {
+    match e.as_error_response() {
+        Some(response) if response.code == REQUEST_REJECTED => {
+            match response
+                .data
+                .clone()
+                .and_then(|data| serde_json::from_value::<Rejection>(data).ok())
+            {
+                Some(rejection) => rejection.into(),
+                None => TransportError::Rejected(response.message.clone()),
+            }
+        }
+        Some(response) if response.code == RATE_LIMITED => TransportError::RateLimited {
+            reason: response.message.clone(),
+            retry_after: Duration::from_secs(
+                response
+                    .data
+                    .as_ref()
+                    .and_then(|data| data["retry_after"].as_u64())
+                    .unwrap_or_default(),
+            ),
+        },
+        _ => TransportError::Failed(e.to_string()),
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl RelayTransport for HttpRelayTransport {
+    async fn send(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        _options: MetaTransactionOptions,
+    ) -> Result<RelayedTransaction<'_>, TransportError> {
+        let id: B256 = self
+            .client
+            .request(SEND_FORWARD_REQUEST, (&request, signature))
+            .await
+            .map_err(rpc_error)?;
+
+        // A request the server accepted before it restarted may not be broadcast yet.
+        let tx_hash = loop {
+            match self.status(id).await? {
+                RelayStatus::Accepted => tokio::time::sleep(self.poll_interval).await,
+                RelayStatus::Pending { tx_hash, .. } => break tx_hash,
+                RelayStatus::Mined(receipt) => break receipt.receipt.transaction_hash,
+                RelayStatus::Failed { reason } => return Err(TransportError::Failed(reason)),
+            }
+        };
+
+        Ok(RelayedTransaction::new(tx_hash, request, async move {
+            loop {
+                match self.status(id).await? {
+                    RelayStatus::Accepted | RelayStatus::Pending { .. } => {
+                        tokio::time::sleep(self.poll_interval).await
+                    }
+                    RelayStatus::Mined(receipt) => return Ok(Some(*receipt)),
+                    RelayStatus::Failed { reason } => return Err(TransportError::Failed(reason)),
+                }
+            }
+        }))
+    }
+}
+
+/// Records the requests it is sent instead of relaying them, for tests of code that signs
+/// meta-transactions.
+///
+/// The n-th request gets the transaction hash `H256::from_low_u64_be(n)`, and its handle resolves
+/// to `None`, as if it was never mined. Clones share the recorded requests.
+#[derive(Debug, Clone, Default)]
+pub struct MockRelayTransport {
+    sent: Arc<Mutex<Vec<(ForwardRequest, Bytes)>>>,
+    errors: Arc<Mutex<VecDeque<TransportError>>>,
+}
+
+impl MockRelayTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails the next send that has not been given an error yet with `error`, without recording
+    /// its request.
+    pub fn push_error(&self, error: TransportError) {
+        self.errors.lock().unwrap().push_back(error);
+    }
+
+    /// The requests sent so far and their signatures, oldest first.
+    pub fn requests(&self) -> Vec<(ForwardRequest, Bytes)> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl RelayTransport for MockRelayTransport {
+    async fn send(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+        _options: MetaTransactionOptions,
+    ) -> Result<RelayedTransaction<'_>, TransportError> {
+        if let Some(error) = self.errors.lock().unwrap().pop_front() {
+            return Err(error);
+        }
+
+        let mut sent = self.sent.lock().unwrap();
+        sent.push((request.clone(), signature));
+        let tx_hash = H256::from_low_u64_be(sent.len() as u64);
+        Ok(RelayedTransaction::new(tx_hash, request, async {
+            Ok(None)
+        }))
+    }
+}
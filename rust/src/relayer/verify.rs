@@ -0,0 +1,192 @@
+use alloy::{
+    primitives::{keccak256, uint, Address, PrimitiveSignature, B256, U256},
+    sol_types::{Eip712Domain, SolValue},
+};
+use thiserror::Error;
+
+use super::{forwarder_domain, ForwardRequest};
+
+/// The EIP-712 type of `ForwardRequest`, as hashed into the `Forwarder`'s `TYPEHASH`.
+pub const FORWARD_REQUEST_TYPE: &str =
+    "ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,bytes data)";
+
+/// `secp256k1n / 2`. OpenZeppelin's `ECDSA.recover` rejects signatures with a larger `s`.
+const MAX_S: U256 = uint!(0x7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF5D576E7357A4501DDFE92F46681B20A0_U256);
+
+/// Why [`ForwardRequestVerifier::verify`] rejected a request.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// `Forwarder.verify` would revert inside `ECDSA.recover` rather than return `false`.
+    #[error("Malformed signature: {0}")]
+    MalformedSignature(String),
+
+    #[error("Wrong signer: request is from {expected}, signature recovers to {recovered}")]
+    WrongSigner {
+        expected: Address,
+        recovered: Address,
+    },
+
+    #[error("Wrong domain: signature is by {from}, but over chain ID {signed_chain_id:?} and forwarder {signed_forwarder:?} instead of chain ID {chain_id:?} and forwarder {forwarder:?}")]
+    WrongDomain {
+        from: Address,
+        chain_id: Option<u64>,
+        forwarder: Option<Address>,
+        signed_chain_id: Option<u64>,
+        signed_forwarder: Option<Address>,
+    },
+
+    #[error("Nonce mismatch: expected {expected}, got {got}")]
+    NonceMismatch { expected: U256, got: U256 },
+}
+
+/// Returns the `Forwarder`'s `TYPEHASH`.
+pub fn forward_request_typehash() -> B256 {
+    keccak256(FORWARD_REQUEST_TYPE)
+}
+
+/// Returns the struct hash `Forwarder.verify` computes for `req`.
+pub fn forward_request_struct_hash(req: &ForwardRequest) -> B256 {
+    keccak256(
+        (
+            forward_request_typehash(),
+            req.from,
+            req.to,
+            req.value,
+            req.gas,
+            req.nonce,
+            keccak256(&req.data),
+        )
+            .abi_encode(),
+    )
+}
+
+/// Returns the digest `Forwarder.verify` recovers the signer from (`_hashTypedDataV4`).
+pub fn forward_request_digest(req: &ForwardRequest, domain: &Eip712Domain) -> B256 {
+    let mut digest = [0u8; 66];
+    digest[..2].copy_from_slice(&[0x19, 0x01]);
+    digest[2..34].copy_from_slice(domain.separator().as_slice());
+    digest[34..].copy_from_slice(forward_request_struct_hash(req).as_slice());
+    keccak256(digest)
+}
+
+/// An offline replica of `Forwarder.verify` that explains why a request would be rejected.
+///
+/// `Forwarder.verify` folds the nonce and signer checks into a single `bool`, so a failed
+/// `execute` only reverts with `SignatureDoesNotMatch()`. This checks the signature the same way
+/// and, when it does not recover to `req.from`, also tries a few likely wrong domains (see
+/// [`with_candidate`](Self::with_candidate)) to tell a wrong signer from a wrong domain.
+#[derive(Debug, Clone)]
+pub struct ForwardRequestVerifier {
+    domain: Eip712Domain,
+    candidates: Vec<Eip712Domain>,
+}
+
+impl ForwardRequestVerifier {
+    /// Creates a verifier for the forwarder with `domain`.
+    ///
+    /// The candidate domains are the default domain of the same forwarder on anvil (chain ID
+    /// 31337) and on mainnet, since a hard-coded chain ID is the most common domain mistake.
+    pub fn new(domain: Eip712Domain) -> Self {
+        let forwarder = domain.verifying_contract.unwrap_or_default();
+        let candidates = [31337, 1]
+            .into_iter()
+            .map(|chain_id| forwarder_domain(chain_id, forwarder))
+            .filter(|candidate| *candidate != domain)
+            .collect();
+
+        Self { domain, candidates }
+    }
+
+    /// Also tries `domain` when diagnosing a signature that does not match.
+    pub fn with_candidate(mut self, domain: Eip712Domain) -> Self {
+        self.candidates.push(domain);
+        self
+    }
+
+    pub fn domain(&self) -> &Eip712Domain {
+        &self.domain
+    }
+
+    /// Checks `signature` over `req`, then `req.nonce` against the forwarder's current nonce for
+    /// `req.from`.
+    pub fn verify(
+        &self,
+        req: &ForwardRequest,
+        signature: &[u8],
+        nonce: U256,
+    ) -> Result<(), VerifyError> {
+        self.verify_signature(req, signature)?;
+
+        if req.nonce != nonce {
+            return Err(VerifyError::NonceMismatch {
+                expected: nonce,
+                got: req.nonce,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks only the signature, i.e. that it recovers to `req.from` in this verifier's domain.
+    pub fn verify_signature(
+        &self,
+        req: &ForwardRequest,
+        signature: &[u8],
+    ) -> Result<(), VerifyError> {
+        let signature = parse_signature(signature)?;
+
+        let recovered = recover(&signature, forward_request_digest(req, &self.domain))?;
+        if recovered == req.from {
+            return Ok(());
+        }
+
+        for candidate in &self.candidates {
+            if recover(&signature, forward_request_digest(req, candidate))? == req.from {
+                return Err(VerifyError::WrongDomain {
+                    from: req.from,
+                    chain_id: self.domain.chain_id.map(|id| id.saturating_to()),
+                    forwarder: self.domain.verifying_contract,
+                    signed_chain_id: candidate.chain_id.map(|id| id.saturating_to()),
+                    signed_forwarder: candidate.verifying_contract,
+                });
+            }
+        }
+
+        Err(VerifyError::WrongSigner {
+            expected: req.from,
+            recovered,
+        })
+    }
+}
+
+/// Parses a 65-byte `r || s || v` signature, with the same restrictions as OpenZeppelin's
+/// `ECDSA.recover`.
+fn parse_signature(signature: &[u8]) -> Result<PrimitiveSignature, VerifyError> {
+    if signature.len() != 65 {
+        return Err(VerifyError::MalformedSignature(format!(
+            "expected 65 bytes, got {}",
+            signature.len()
+        )));
+    }
+    let v = signature[64];
+    if v != 27 && v != 28 {
+        return Err(VerifyError::MalformedSignature(format!(
+            "v must be 27 or 28, got {v}"
+        )));
+    }
+
+    let signature = PrimitiveSignature::from_raw(signature)
+        .map_err(|e| VerifyError::MalformedSignature(e.to_string()))?;
+    if signature.s() > MAX_S {
+        return Err(VerifyError::MalformedSignature(
+            "s is in the upper half of the curve order".to_string(),
+        ));
+    }
+    Ok(signature)
+}
+
+fn recover(signature: &PrimitiveSignature, digest: B256) -> Result<Address, VerifyError> {
+    signature
+        .recover_address_from_prehash(&digest)
+        .map_err(|e| VerifyError::MalformedSignature(e.to_string()))
+}
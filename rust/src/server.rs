@@ -0,0 +1,603 @@
+//! A JSON-RPC relayer service for `ForwardRequest`s signed by its clients.
+//!
+//! [`RelayServer`] serves two methods over HTTP `POST`:
+//!
+//! - `relay_sendForwardRequest(request, signature)` checks a signed `ForwardRequest` with
+//!   [`Relayer::verify`], submits it through `Forwarder.execute` from the server's gas wallet and
+//!   returns its id, the request's EIP-712 digest. Sending the same request again returns the
+//!   same id without submitting it twice.
+//! - `relay_getStatus(id)` returns the [`RelayStatus`] of a request, or `null` for an unknown id.
+//!
+//! The request is the JSON form of [`ForwardRequest`] and the signature a hex string. Requests
+//! that cannot be relayed fail with [`REQUEST_REJECTED`] and the reason as the message, and with
+//! the [`Rejection`] as the error's data if the allowlist or the budget refused them; failures of
+//! the relayer itself, e.g. of its RPC endpoint, with [`INTERNAL_ERROR`]. With a
+//! [`RateLimiter`], signers and clients that send too many requests fail with [`RATE_LIMITED`],
+//! and the error's data is `{"retry_after": seconds}`. Clients can use
+//! [`HttpRelayTransport`](crate::relayer::HttpRelayTransport).
+
+use std::{
+    future::IntoFuture,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use alloy::primitives::B256;
+use axum::{
+    extract::{ConnectInfo, State},
+    routing::post,
+    Json, Router,
+};
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, Bytes, TransactionRequest, H256},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+
+/// How long [`RelayServer::resume`] waits for the gas wallets' transactions in the mempool to be
+/// mined before it gives up on the requests that were not known to be broadcast.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub use crate::relayer::rpc::{
+    Rejection, GET_STATUS, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+    PARSE_ERROR, RATE_LIMITED, REQUEST_REJECTED, SEND_FORWARD_REQUEST,
+};
+use crate::relayer::{
+    budget::unix_time, convert, rate_limit::retry_after_secs, ForwardRequest,
+    MetaTransactionOptions, MetaTransactionReceipt, QueueError, QueuedRequest, RateLimiter,
+    RelayError, RelayQueue, RelayStatus, Relayer, SpendRecord,
+};
+
+/// Relays `ForwardRequest`s signed by clients, over JSON-RPC (see the [module docs](self)).
+///
+/// Requests are submitted one at a time so that the gas wallet's `execute` transactions are
+/// ordered like the requests, and followed to their receipts in the background. Every accepted
+/// request is kept with its status in a [`RelayQueue`], in memory unless the server is given one
+/// on disk with [`with_queue`](Self::with_queue). After a restart, [`resume`](Self::resume) picks
+/// up the requests that were still in flight.
+#[derive(Debug)]
+pub struct RelayServer<M> {
+    relayer: Arc<Relayer<M>>,
+    queue: RelayQueue,
+    submissions: Arc<tokio::sync::Mutex<()>>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl<M> Clone for RelayServer<M> {
+    fn clone(&self) -> Self {
+        Self {
+            relayer: self.relayer.clone(),
+            queue: self.queue.clone(),
+            submissions: self.submissions.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+        }
+    }
+}
+
+impl<M> RelayServer<M> {
+    pub fn new(relayer: Relayer<M>) -> Self {
+        Self {
+            relayer: Arc::new(relayer),
+            queue: RelayQueue::in_memory().expect("an in-memory relay queue always opens"),
+            submissions: Default::default(),
+            rate_limiter: None,
+        }
+    }
+
+    /// Keeps the accepted requests in `queue`, e.g. one opened from a file with
+    /// [`RelayQueue::open`] so that they survive restarts.
+    pub fn with_queue(mut self, queue: RelayQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Limits how many requests each meta signer and each client may send. The limits are
+    /// checked before any RPC work, so spam does not reach the node either.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn relayer(&self) -> &Relayer<M> {
+        &self.relayer
+    }
+
+    pub fn queue(&self) -> &RelayQueue {
+        &self.queue
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// The status of the request with id `id`, or `None` if it was never accepted.
+    pub fn status(&self, id: B256) -> Result<Option<RelayStatus>, QueueError> {
+        Ok(self.queue.get_by_id(id)?.map(|entry| entry.status))
+    }
+
+    fn set_status(&self, entry: &QueuedRequest, status: RelayStatus) {
+        // If this fails, the queue keeps the previous status, and `resume` checks the request
+        // against the chain again.
+        let _ = self
+            .queue
+            .set_status(entry.request.from, entry.request.nonce, status);
+    }
+}
+
+impl<M: Middleware + 'static> RelayServer<M> {
+    /// Verifies and submits `request`, and returns its id once `execute` has been broadcast.
+    pub async fn send_forward_request(
+        &self,
+        request: ForwardRequest,
+        signature: Bytes,
+    ) -> Result<B256, RelayError<M>> {
+        self.send_forward_request_from(None, request, signature)
+            .await
+    }
+
+    /// Like [`send_forward_request`](Self::send_forward_request), for a request sent by the client
+    /// at `client`, which is rate limited too.
+    pub async fn send_forward_request_from(
+        &self,
+        client: Option<IpAddr>,
+        request: ForwardRequest,
+        signature: Bytes,
+    ) -> Result<B256, RelayError<M>> {
+        // Refuse spam, what the allowlist does not allow and signers over budget before any RPC
+        // work.
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter
+                .check(request.from, client)
+                .map_err(RelayError::RateLimited)?;
+        }
+        self.relayer.admit(&request)?;
+
+        // A request that was already queued no longer verifies once its nonce is used.
+        let id = self.relayer.digest(&request).await?;
+        let queued = self
+            .queue
+            .get(request.from, request.nonce)
+            .map_err(RelayError::Queue)?;
+        if let Some(queued) = queued.filter(QueuedRequest::is_live) {
+            return if queued.id == id {
+                Ok(id)
+            } else {
+                Err(RelayError::Queue(QueueError::NonceTaken {
+                    from: request.from,
+                    nonce: request.nonce,
+                }))
+            };
+        }
+        self.relayer.verify(&request, &signature).await?;
+
+        let Some(entry) = self
+            .queue
+            .accept(id, request, signature)
+            .map_err(RelayError::Queue)?
+        else {
+            // Another client sent the same request meanwhile.
+            return Ok(id);
+        };
+        match self.spawn_submission(entry.clone()).await {
+            Ok(sent) => sent?,
+            Err(_) => {
+                // The task panicked before `execute` was broadcast, so the client may send the
+                // request again.
+                self.set_status(
+                    &entry,
+                    RelayStatus::Failed {
+                        reason: QueueError::SubmissionLost.to_string(),
+                    },
+                );
+                return Err(RelayError::Queue(QueueError::SubmissionLost));
+            }
+        }
+        Ok(id)
+    }
+
+    /// Submits `entry` from a task of its own, so that it is followed to its receipt even if the
+    /// client goes away. The returned channel says whether `execute` was broadcast.
+    fn spawn_submission(
+        &self,
+        entry: QueuedRequest,
+    ) -> oneshot::Receiver<Result<(), RelayError<M>>> {
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let server = self.clone();
+        tokio::spawn(async move {
+            let pending = {
+                let _submitting = server.submissions.lock().await;
+                match server
+                    .relayer
+                    .relay(
+                        entry.request.clone(),
+                        entry.signature.clone(),
+                        MetaTransactionOptions::new(),
+                    )
+                    .await
+                {
+                    Ok(pending) => {
+                        server.set_status(
+                            &entry,
+                            RelayStatus::Pending {
+                                tx_hash: pending.tx_hash(),
+                                replacements: Vec::new(),
+                            },
+                        );
+                        let _ = sent_tx.send(Ok(()));
+                        pending
+                    }
+                    Err(e) => {
+                        server.set_status(
+                            &entry,
+                            RelayStatus::Failed {
+                                reason: e.to_string(),
+                            },
+                        );
+                        let _ = sent_tx.send(Err(e));
+                        return;
+                    }
+                }
+            };
+
+            // Every replacement is queued as it is broadcast, so that `resume` can find whichever
+            // one is mined.
+            let tx_hash = pending.tx_hash();
+            let (broadcasts, mut replacements) = mpsc::unbounded_channel();
+            let mut receipt = pending.with_broadcasts(broadcasts).into_future();
+            let mut hashes = Vec::new();
+            let outcome = loop {
+                tokio::select! {
+                    outcome = &mut receipt => break outcome,
+                    Some(hash) = replacements.recv() => {
+                        hashes.push(hash);
+                        server.set_status(
+                            &entry,
+                            RelayStatus::Pending {
+                                tx_hash,
+                                replacements: hashes.clone(),
+                            },
+                        );
+                    }
+                }
+            };
+
+            let status = match outcome {
+                Ok(Some(receipt)) => RelayStatus::Mined(Box::new(receipt)),
+                Ok(None) => RelayStatus::Failed {
+                    reason: "execute was replaced by another transaction from the gas wallet"
+                        .to_string(),
+                },
+                Err(e) => RelayStatus::Failed {
+                    reason: e.to_string(),
+                },
+            };
+            server.set_status(&entry, status);
+        });
+        sent_rx
+    }
+
+    /// Picks up the requests that were still [`Accepted`](RelayStatus::Accepted) or
+    /// [`Pending`](RelayStatus::Pending) in the queue when the server stopped, in the background,
+    /// and returns how many there are.
+    ///
+    /// A pending `execute` transaction is followed to its receipt. A request that was not
+    /// broadcast, or whose `execute` transaction was dropped, is verified again and resubmitted.
+    /// If its forwarder nonce was used meanwhile, it fails instead of wasting gas on an `execute`
+    /// that cannot succeed.
+    ///
+    /// A request that is still `Accepted` may have been broadcast just before the server stopped,
+    /// before its `Pending` status was written. So it is only verified once the transactions the
+    /// gas wallets had in the mempool are mined, and fails if that takes longer than ten minutes.
+    pub async fn resume(&self) -> Result<usize, QueueError> {
+        let unfinished = self.queue.unfinished()?;
+        let count = unfinished.len();
+
+        // The queue lists each signer's requests by nonce, so they are resubmitted in order.
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut settled = false;
+            for entry in unfinished {
+                match entry.status {
+                    RelayStatus::Pending {
+                        tx_hash,
+                        ref replacements,
+                    } => {
+                        let hashes = std::iter::once(tx_hash)
+                            .chain(replacements.iter().copied())
+                            .collect();
+                        let server = server.clone();
+                        tokio::spawn(async move { server.follow(entry, hashes).await });
+                    }
+                    _ => {
+                        if !settled {
+                            if let Err(reason) = server.settle_gas_wallets().await {
+                                server.set_status(
+                                    &entry,
+                                    RelayStatus::Failed {
+                                        reason: format!(
+                                            "Not resubmitted after a restart: {reason}"
+                                        ),
+                                    },
+                                );
+                                continue;
+                            }
+                            settled = true;
+                        }
+                        server.resubmit(entry).await
+                    }
+                }
+            }
+        });
+        Ok(count)
+    }
+
+    /// Waits until the gas wallets' transactions that are in the mempool now are mined, so that
+    /// any `execute` broadcast before a restart has used its request's forwarder nonce.
+    async fn settle_gas_wallets(&self) -> Result<(), String> {
+        let client = self.relayer.forwarder().client_ref();
+        let mut wallets: Vec<(&M, Address)> = client
+            .default_sender()
+            .map(|sender| (client, sender))
+            .into_iter()
+            .collect();
+        if let Some(pool) = self.relayer.gas_wallets() {
+            for wallet in pool.wallets() {
+                let address = convert::to_ethers_address(wallet.address());
+                if !wallets.iter().any(|(_, known)| *known == address) {
+                    wallets.push((wallet.forwarder().client_ref(), address));
+                }
+            }
+        }
+
+        let settle = async {
+            for (client, address) in wallets {
+                let count = |block: BlockNumber| async move {
+                    client
+                        .get_transaction_count(address, Some(block.into()))
+                        .await
+                        .map_err(|e| {
+                            format!("Failed to get the nonce of gas wallet {address:?}: {e}")
+                        })
+                };
+                let pending = count(BlockNumber::Pending).await?;
+                while count(BlockNumber::Latest).await? < pending {
+                    tokio::time::sleep(client.provider().get_interval()).await;
+                }
+            }
+            Ok(())
+        };
+        tokio::time::timeout(SETTLE_TIMEOUT, settle)
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "the gas wallets' transactions were not mined within {}s",
+                    SETTLE_TIMEOUT.as_secs()
+                ))
+            })
+    }
+
+    async fn resubmit(&self, entry: QueuedRequest) {
+        if let Err(e) = self.relayer.verify(&entry.request, &entry.signature).await {
+            self.set_status(
+                &entry,
+                RelayStatus::Failed {
+                    reason: format!("Not resubmitted after a restart: {e}"),
+                },
+            );
+            return;
+        }
+        let _ = self.spawn_submission(entry).await;
+    }
+
+    /// Follows the broadcasts of the `execute` transaction that carried `entry` before a
+    /// restart, `hashes` oldest first, until one of them is mined.
+    async fn follow(&self, entry: QueuedRequest, hashes: Vec<H256>) {
+        let client = self.relayer.forwarder().client_ref();
+        let failed = |e: &dyn std::fmt::Display| RelayStatus::Failed {
+            reason: format!("Failed to follow {:?} after a restart: {e}", hashes[0]),
+        };
+
+        let (receipt, tx) = 'mined: loop {
+            let mut known = false;
+            for &tx_hash in hashes.iter().rev() {
+                let tx = match client.get_transaction(tx_hash).await {
+                    Ok(Some(tx)) => tx,
+                    Ok(None) => continue,
+                    Err(e) => return self.set_status(&entry, failed(&e)),
+                };
+                known = true;
+                match client.get_transaction_receipt(tx_hash).await {
+                    Ok(Some(receipt)) => break 'mined (receipt, tx),
+                    Ok(None) => {}
+                    Err(e) => return self.set_status(&entry, failed(&e)),
+                }
+            }
+            if !known {
+                // All dropped, so the request may not have been relayed.
+                return self.resubmit(entry).await;
+            }
+            tokio::time::sleep(client.provider().get_interval()).await;
+        };
+
+        // Only what a replay of `execute` needs.
+        let mut call = TransactionRequest::new();
+        call.from = Some(tx.from);
+        call.to = tx.to.map(Into::into);
+        call.gas = Some(tx.gas);
+        call.value = Some(tx.value);
+        call.data = Some(tx.input);
+
+        let forwarder = convert::to_alloy_address(self.relayer.forwarder().address());
+        let status = match MetaTransactionReceipt::recover(
+            client,
+            receipt,
+            &call.into(),
+            forwarder,
+            entry.request.clone(),
+        )
+        .await
+        {
+            Ok(receipt) => {
+                if let Some(budget) = self.relayer.budget() {
+                    let wei = receipt.cost().unwrap_or_default();
+                    let _ = budget
+                        .ledger()
+                        .record(SpendRecord::new(&receipt, wei, unix_time()));
+                }
+                RelayStatus::Mined(Box::new(receipt))
+            }
+            Err(e) => failed(&e),
+        };
+        self.set_status(&entry, status);
+    }
+
+    /// An axum router that serves the JSON-RPC methods at `/`.
+    pub fn router(self) -> Router {
+        Router::new().route("/", post(handle::<M>)).with_state(self)
+    }
+
+    /// Serves the JSON-RPC methods on `listener` until the process stops, rate limiting clients
+    /// by their IP address.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        axum::serve(
+            listener,
+            self.router()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    }
+}
+
+/// The JSON-RPC error code for a relay failure.
+pub fn error_code<M: Middleware>(error: &RelayError<M>) -> i64 {
+    match error {
+        RelayError::PolicyRejected(_)
+        | RelayError::OverBudget(_)
+        | RelayError::VerificationFailed(_)
+        | RelayError::GasError(_)
+        | RelayError::PreflightFailed(_)
+        | RelayError::Reverted(_)
+        | RelayError::Queue(QueueError::NonceTaken { .. }) => REQUEST_REJECTED,
+        RelayError::RateLimited(_) => RATE_LIMITED,
+        _ => INTERNAL_ERROR,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn relay<M: Middleware>(error: RelayError<M>) -> Self {
+        let mut rpc_error = Self::new(error_code(&error), error.to_string());
+        if let RelayError::RateLimited(e) = &error {
+            rpc_error.data = Some(json!({ "retry_after": retry_after_secs(e.retry_after()) }));
+        }
+        if let Some(rejection) = Rejection::of(&error) {
+            rpc_error.data = serde_json::to_value(rejection).ok();
+        }
+        rpc_error
+    }
+}
+
+async fn handle<M: Middleware + 'static>(
+    State(server): State<RelayServer<M>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    body: axum::body::Bytes,
+) -> Json<Value> {
+    let request = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(response(
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, e.to_string())),
+            ))
+        }
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let request = match serde_json::from_value::<RpcRequest>(request) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(response(
+                id,
+                Err(RpcError::new(INVALID_REQUEST, e.to_string())),
+            ))
+        }
+    };
+
+    let client = client.map(|ConnectInfo(address)| address.ip());
+    let result = dispatch(&server, client, &request.method, request.params).await;
+    Json(response(request.id, result))
+}
+
+async fn dispatch<M: Middleware + 'static>(
+    server: &RelayServer<M>,
+    client: Option<IpAddr>,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        SEND_FORWARD_REQUEST => {
+            let (request, signature) = parse_params::<(ForwardRequest, Bytes)>(params)?;
+            let id = server
+                .send_forward_request_from(client, request, signature)
+                .await
+                .map_err(RpcError::relay)?;
+            Ok(json!(id))
+        }
+        GET_STATUS => {
+            let (id,) = parse_params::<(B256,)>(params)?;
+            let status = server
+                .status(id)
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+            Ok(json!(status))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method {method} not found"),
+        )),
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => {
+            let mut body = json!({ "code": error.code, "message": error.message });
+            if let Some(data) = error.data {
+                body["data"] = data;
+            }
+            json!({ "jsonrpc": "2.0", "id": id, "error": body })
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use alloy::{
+    primitives::{address, Address, Bytes, FixedBytes, U256},
+    signers::local::PrivateKeySigner,
+    sol_types::SolValue,
+};
+use counter_client::{
+    config::ConfigFile,
+    relayer::{
+        abi::{self, forwarder::ExecuteReturn},
+        budget::{unix_time, SpendTotal, DEFAULT_WINDOW_SECS},
+        convert,
+        policy::function_selector,
+        Budget, BudgetConfig, BudgetError, EIP2771GasRelayerMiddleware, ForwardRequest,
+        MetaTransactionOptions, OuterTransactionType, RelayError, Relayer, SpendLedger,
+        SpendRecord, SpendReport, FORWARDER_NAME, FORWARDER_VERSION,
+    },
+};
+use ethers::{
+    abi::AbiEncode,
+    providers::{Middleware, Provider},
+    types::{Eip1559TransactionRequest, TransactionReceipt, H256},
+};
+
+const ALICE: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+const BOB: Address = address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC");
+const COUNTER: Address = address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512");
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+
+fn temp_ledger(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "counter-client-spend-{}-{test}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn spend(from: Address, target: Address, wei: u64, timestamp: u64) -> SpendRecord {
+    SpendRecord {
+        from,
+        target,
+        tx_hash: H256::from_low_u64_be(timestamp),
+        wei: U256::from(wei),
+        timestamp,
+    }
+}
+
+fn increment(from: Address) -> ForwardRequest {
+    ForwardRequest {
+        from,
+        to: COUNTER,
+        value: U256::ZERO,
+        gas: U256::from(50_000),
+        nonce: U256::ZERO,
+        data: Bytes::copy_from_slice(function_selector("increment()").as_slice()),
+    }
+}
+
+#[test]
+fn ledger_survives_reopening() {
+    let path = temp_ledger("reopen");
+    let ledger = SpendLedger::open(&path).unwrap();
+    assert!(ledger.records().is_empty());
+
+    ledger.record(spend(ALICE, COUNTER, 100, 10)).unwrap();
+    ledger.record(spend(BOB, COUNTER, 50, 20)).unwrap();
+
+    let reopened = SpendLedger::open(&path).unwrap();
+    assert_eq!(reopened.records(), ledger.records());
+    assert_eq!(reopened.spent(ALICE, 0), U256::from(100));
+
+    std::fs::write(&path, "{\"from\": \"nope\"}\n").unwrap();
+    assert!(matches!(
+        SpendLedger::open(&path),
+        Err(BudgetError::Corrupt { line: 1, .. })
+    ));
+}
+
+#[test]
+fn reports_spend_per_user_and_target() {
+    let other = Address::repeat_byte(0x42);
+    let ledger = SpendLedger::in_memory();
+    ledger.record(spend(ALICE, COUNTER, 100, 10)).unwrap();
+    ledger.record(spend(BOB, COUNTER, 300, 20)).unwrap();
+    ledger.record(spend(ALICE, other, 50, 30)).unwrap();
+    ledger.record(spend(ALICE, COUNTER, 1_000, 1)).unwrap();
+
+    let report = ledger.report(10);
+    let users: Vec<_> = report
+        .users
+        .iter()
+        .map(|total| (total.address, total.wei, total.transactions))
+        .collect();
+    assert_eq!(
+        users,
+        vec![(BOB, U256::from(300), 1), (ALICE, U256::from(150), 2)]
+    );
+    let targets: Vec<_> = report
+        .targets
+        .iter()
+        .map(|total| (total.address, total.wei, total.transactions))
+        .collect();
+    assert_eq!(
+        targets,
+        vec![(COUNTER, U256::from(400), 2), (other, U256::from(50), 1)]
+    );
+}
+
+#[test]
+fn budgets_roll_over_the_window() {
+    let ledger = SpendLedger::in_memory();
+    let budget = Budget::new(U256::from(100), Duration::from_secs(60), ledger.clone());
+
+    ledger.record(spend(ALICE, COUNTER, 60, 1_000)).unwrap();
+    budget.check_at(ALICE, 1_010).unwrap();
+
+    ledger.record(spend(ALICE, COUNTER, 40, 1_020)).unwrap();
+    assert!(matches!(
+        budget.check_at(ALICE, 1_030),
+        Err(BudgetError::Exhausted { from, spent, window_secs: 60, .. })
+            if from == ALICE && spent == U256::from(100)
+    ));
+    budget.check_at(BOB, 1_030).unwrap();
+
+    // The first spend has left the window, and the ledger forgets it.
+    budget.check_at(ALICE, 1_061).unwrap();
+    assert_eq!(ledger.records(), vec![spend(ALICE, COUNTER, 40, 1_020)]);
+}
+
+#[test]
+fn reserves_the_cost_of_requests_in_flight() {
+    let ledger = SpendLedger::in_memory();
+    let budget = Budget::new(U256::from(100), Duration::from_secs(60), ledger.clone());
+
+    let first = budget.reserve_at(ALICE, U256::from(60), 1_000).unwrap();
+    assert_eq!(ledger.reserved(ALICE), U256::from(60));
+    assert!(matches!(
+        budget.reserve_at(ALICE, U256::from(50), 1_000),
+        Err(BudgetError::Insufficient { from, remaining, cost, window_secs: 60 })
+            if from == ALICE && remaining == U256::from(40) && cost == U256::from(50)
+    ));
+    budget.reserve_at(BOB, U256::from(50), 1_000).unwrap();
+
+    // Settling replaces the reservation with what was actually spent.
+    first.settle(spend(ALICE, COUNTER, 30, 1_000)).unwrap();
+    assert_eq!(ledger.reserved(ALICE), U256::ZERO);
+    assert_eq!(ledger.records(), vec![spend(ALICE, COUNTER, 30, 1_000)]);
+
+    let second = budget.reserve_at(ALICE, U256::from(70), 1_010).unwrap();
+    assert!(matches!(
+        budget.check_at(ALICE, 1_010),
+        Err(BudgetError::Exhausted { spent, .. }) if spent == U256::from(100)
+    ));
+
+    // A reservation that is dropped, e.g. because the request was not mined, is released.
+    drop(second);
+    assert_eq!(ledger.reserved(ALICE), U256::ZERO);
+    budget.check_at(ALICE, 1_010).unwrap();
+}
+
+#[test]
+fn only_forgets_spend_outside_the_ledger_window() {
+    let now = unix_time();
+    let ledger = SpendLedger::in_memory();
+    let hourly = Budget::new(U256::from(100), Duration::from_secs(3_600), ledger.clone());
+    let minutely = Budget::new(U256::from(100), Duration::from_secs(60), ledger.clone());
+    ledger
+        .record(spend(ALICE, COUNTER, 100, now - 120))
+        .unwrap();
+
+    // Asking for a shorter window does not drop what the ledger's window still holds.
+    minutely.check(ALICE).unwrap();
+    assert_eq!(ledger.spent(ALICE, now - 60), U256::ZERO);
+    assert_eq!(ledger.records().len(), 1);
+    assert!(matches!(
+        hourly.check(ALICE),
+        Err(BudgetError::Exhausted { .. })
+    ));
+}
+
+#[test]
+fn reads_only_the_window_into_memory() {
+    let path = temp_ledger("window");
+    let now = unix_time();
+    let ledger = SpendLedger::open(&path).unwrap();
+    ledger
+        .record(spend(ALICE, COUNTER, 100, now - 120))
+        .unwrap();
+    ledger.record(spend(BOB, COUNTER, 30, now - 10)).unwrap();
+    ledger.record(spend(ALICE, COUNTER, 50, now)).unwrap();
+
+    let windowed = SpendLedger::open_with_window(&path, Duration::from_secs(60)).unwrap();
+    assert_eq!(windowed.records().len(), 2);
+    assert_eq!(windowed.spent(ALICE, now - 60), U256::from(50));
+
+    // The report streams the whole file.
+    let report = SpendReport::read(&path, 0).unwrap();
+    assert_eq!(
+        report.users[0],
+        SpendTotal {
+            address: ALICE,
+            wei: U256::from(150),
+            transactions: 2,
+        }
+    );
+    assert_eq!(report, ledger.report(0));
+}
+
+#[test]
+fn parses_budget_from_config() {
+    let file: ConfigFile = toml::from_str(
+        r#"
+        [profiles.local.budget]
+        max_wei = "10000000000000000"
+        ledger = "spend.jsonl"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        file.profiles["local"].budget,
+        Some(BudgetConfig {
+            max_wei: U256::from(10_000_000_000_000_000u64),
+            window_secs: DEFAULT_WINDOW_SECS,
+            ledger: PathBuf::from("spend.jsonl"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn relayer_rejects_signers_over_budget_before_any_rpc() {
+    // The mock has no responses, so any RPC call would fail with a different error.
+    let (provider, _mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(Address::repeat_byte(0x11)),
+        Arc::new(provider),
+    );
+    let ledger = SpendLedger::in_memory();
+    ledger
+        .record(spend(ALICE, COUNTER, 100, unix_time()))
+        .unwrap();
+    let relayer = Relayer::new(forwarder).with_budget(Budget::new(
+        U256::from(100),
+        Duration::from_secs(60),
+        ledger,
+    ));
+
+    let error = relayer
+        .verify(&increment(ALICE), &[0; 65])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::OverBudget(BudgetError::Exhausted { .. })
+    ));
+
+    let error = relayer
+        .relay(
+            increment(ALICE),
+            vec![0; 65].into(),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::OverBudget(BudgetError::Exhausted { .. })
+    ));
+
+    // Others still get through to the node.
+    let error = relayer.verify(&increment(BOB), &[0; 65]).await.unwrap_err();
+    assert!(matches!(error, RelayError::MissingChainID(_)));
+}
+
+/// The ABI-encoded return value of `eip712Domain()`.
+fn eip712_domain() -> ethers::types::Bytes {
+    (
+        FixedBytes::<1>::from([0x0f]),
+        FORWARDER_NAME.to_string(),
+        FORWARDER_VERSION.to_string(),
+        U256::from(31337),
+        FORWARDER,
+        FixedBytes::<32>::ZERO,
+        Vec::<U256>::new(),
+    )
+        .abi_encode_params()
+        .into()
+}
+
+fn word(value: u64) -> ethers::types::Bytes {
+    ethers::types::Bytes::from(ethers::types::U256::from(value).encode())
+}
+
+#[tokio::test]
+async fn middleware_records_spend_without_being_awaited() {
+    // The meta signer's node answers, last in first out, `getNonce(from)`, the inner gas
+    // estimate, the forwarder's domain and `getNonce(from)` again for the verification.
+    let (provider, mock) = Provider::mocked();
+    mock.push::<ethers::types::Bytes, _>(word(0)).unwrap();
+    mock.push::<ethers::types::Bytes, _>(eip712_domain())
+        .unwrap();
+    mock.push(ethers::types::U256::from(21_000)).unwrap();
+    mock.push::<ethers::types::Bytes, _>(word(0)).unwrap();
+
+    // The gas wallet's node answers the preflight, the gas price and the broadcast, then the
+    // follower's receipt, call trace and `getNonce(from)`.
+    let (gas_provider, gas_mock) = Provider::mocked();
+    let gas_provider = gas_provider
+        .with_sender(ethers::types::Address::repeat_byte(0x42))
+        .interval(Duration::from_millis(1));
+    let tx_hash = H256::repeat_byte(0xab);
+    let output = ethers::types::Bytes::from(ExecuteReturn(true, Default::default()).encode());
+    gas_mock.push::<ethers::types::Bytes, _>(word(1)).unwrap();
+    gas_mock
+        .push(serde_json::json!({
+            "type": "CALL",
+            "from": "0x4242424242424242424242424242424242424242",
+            "to": FORWARDER,
+            "gas": "0x186a0",
+            "gasUsed": "0xc350",
+            "input": "0x",
+            "output": output,
+        }))
+        .unwrap();
+    gas_mock
+        .push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(100.into()),
+            status: Some(1.into()),
+            gas_used: Some(50_000.into()),
+            effective_gas_price: Some(2.into()),
+            ..Default::default()
+        })
+        .unwrap();
+    gas_mock.push(tx_hash).unwrap();
+    gas_mock.push(ethers::types::U256::from(2)).unwrap();
+    gas_mock.push::<ethers::types::Bytes, _>(output).unwrap();
+
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(FORWARDER),
+        Arc::new(gas_provider),
+    );
+    // anvil account #1
+    let signer: PrivateKeySigner =
+        "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+            .parse()
+            .unwrap();
+    let ledger = SpendLedger::in_memory();
+    let middleware = EIP2771GasRelayerMiddleware::new(provider, signer, forwarder)
+        .with_transaction_type(OuterTransactionType::Legacy)
+        .with_budget(Budget::new(
+            U256::from(1_000_000),
+            Duration::from_secs(60),
+            ledger.clone(),
+        ));
+
+    let increment = Eip1559TransactionRequest::new()
+        .to(convert::to_ethers_address(COUNTER))
+        .data(function_selector("increment()").to_vec())
+        .chain_id(31337);
+    let pending = middleware.send_transaction(increment, None).await.unwrap();
+    assert_eq!(pending.tx_hash(), tx_hash);
+    drop(pending);
+
+    // The receipt is followed in the background, although the `PendingTransaction` is gone.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while ledger.records().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("the spend was recorded");
+    let records = ledger.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].from, ALICE);
+    assert_eq!(records[0].target, COUNTER);
+    assert_eq!(records[0].tx_hash, tx_hash);
+    assert_eq!(records[0].wei, U256::from(100_000));
+    assert_eq!(ledger.reserved(ALICE), U256::ZERO);
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use counter_client::config::{ConfigError, ConfigFile, ProfileConfig, SignerConfig};
+use ethers::signers::Signer;
+
+fn shipped_config() -> ConfigFile {
+    ConfigFile::from_path(&Path::new(env!("CARGO_MANIFEST_DIR")).join("counter-client.toml"))
+        .unwrap()
+}
+
+#[test]
+fn shipped_local_profile_resolves() {
+    let file = shipped_config();
+    assert_eq!(file.default_profile(), "local");
+    assert!(file.profiles.contains_key("staging"));
+
+    let profile = file.profiles["local"].clone().resolve("local").unwrap();
+    assert_eq!(profile.chain_id, Some(31337));
+    assert_eq!(
+        profile.forwarder().unwrap(),
+        "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+            .parse()
+            .unwrap()
+    );
+    // Derived from anvil's test mnemonic.
+    assert_eq!(
+        profile.gas_wallet().unwrap().address(),
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+            .parse()
+            .unwrap()
+    );
+    assert_eq!(
+        profile.meta_wallet().unwrap().address(),
+        "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+            .parse()
+            .unwrap()
+    );
+}
+
+#[test]
+fn overrides_take_precedence() {
+    let mut config = shipped_config().profiles["local"].clone();
+    config.merge(ProfileConfig {
+        counter: Some("0x0000000000000000000000000000000000000001".to_string()),
+        ..Default::default()
+    });
+
+    let profile = config.resolve("local").unwrap();
+    assert_eq!(
+        profile.counter().unwrap(),
+        ethers::types::Address::from_low_u64_be(1)
+    );
+    assert_eq!(profile.rpc_url, "http://localhost:8545");
+}
+
+#[test]
+fn rejects_malformed_address() {
+    let config = ProfileConfig {
+        rpc_url: Some("http://localhost:8545".to_string()),
+        forwarder: Some("0x5FbDB231".to_string()),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        config.resolve("local"),
+        Err(ConfigError::InvalidAddress {
+            field: "forwarder",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn rejects_malformed_private_key() {
+    let config = ProfileConfig {
+        rpc_url: Some("http://localhost:8545".to_string()),
+        gas_wallet: Some(SignerConfig::PrivateKey("0x1234".to_string())),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        config.resolve("local"),
+        Err(ConfigError::InvalidPrivateKey {
+            field: "gas_wallet",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn reports_missing_fields() {
+    let profile = ProfileConfig {
+        rpc_url: Some("http://localhost:8545".to_string()),
+        ..Default::default()
+    }
+    .resolve("staging")
+    .unwrap();
+
+    assert!(matches!(
+        profile.counter(),
+        Err(ConfigError::MissingField {
+            field: "counter",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn loads_wallets_on_first_use() {
+    let profile = ProfileConfig {
+        rpc_url: Some("http://localhost:8545".to_string()),
+        chain_id: Some(31337),
+        gas_wallet: Some(SignerConfig::PrivateKey(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string(),
+        )),
+        meta_wallet: Some(SignerConfig::Identity(
+            "no-such-identity-for-this-test".to_string(),
+        )),
+        ..Default::default()
+    }
+    .resolve("local")
+    .unwrap();
+
+    // A missing identity only fails the command that signs with it.
+    assert!(profile.meta_wallet().is_err());
+    assert_eq!(profile.gas_wallet().unwrap().chain_id(), 31337);
+}
@@ -0,0 +1,86 @@
+use alloy::{
+    primitives::{address, Address, FixedBytes, U256},
+    sol_types::SolValue,
+};
+use counter_client::relayer::{
+    forwarder_domain, DomainError, ForwarderDomains, FORWARDER_NAME, FORWARDER_VERSION,
+};
+use ethers::{providers::Provider, types::Bytes};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+
+/// The ABI-encoded return value of `eip712Domain()`.
+fn eip712_domain(name: &str, version: &str, chain_id: u64) -> Bytes {
+    (
+        FixedBytes::<1>::from([0x0f]),
+        name.to_string(),
+        version.to_string(),
+        U256::from(chain_id),
+        FORWARDER,
+        FixedBytes::<32>::ZERO,
+        Vec::<U256>::new(),
+    )
+        .abi_encode_params()
+        .into()
+}
+
+#[tokio::test]
+async fn reads_and_caches_the_domain() {
+    let (provider, mock) = Provider::mocked();
+    mock.push::<Bytes, _>(eip712_domain(FORWARDER_NAME, FORWARDER_VERSION, 31337))
+        .unwrap();
+
+    let domains = ForwarderDomains::new();
+    let domain = domains.get(&provider, FORWARDER, 31337).await.unwrap();
+    assert_eq!(domain, forwarder_domain(31337, FORWARDER));
+
+    // The mock has no responses left, so this must come from the cache.
+    let cached = domains
+        .clone()
+        .get(&provider, FORWARDER, 31337)
+        .await
+        .unwrap();
+    assert_eq!(cached, domain);
+}
+
+#[tokio::test]
+async fn reports_a_redeployed_version() {
+    let (provider, mock) = Provider::mocked();
+    mock.push::<Bytes, _>(eip712_domain(FORWARDER_NAME, "0.0.2", 31337))
+        .unwrap();
+
+    let error = ForwarderDomains::new()
+        .get(&provider, FORWARDER, 31337)
+        .await
+        .unwrap_err();
+    match error {
+        DomainError::Mismatch {
+            field,
+            expected,
+            actual,
+            ..
+        } => {
+            assert_eq!(field, "version");
+            assert_eq!(expected, FORWARDER_VERSION);
+            assert_eq!(actual, "0.0.2");
+        }
+        e => panic!("Expected a version mismatch, got {e:?}"),
+    }
+}
+
+#[tokio::test]
+async fn reports_a_chain_id_mismatch() {
+    let (provider, mock) = Provider::mocked();
+    mock.push::<Bytes, _>(eip712_domain(FORWARDER_NAME, FORWARDER_VERSION, 1))
+        .unwrap();
+
+    assert!(matches!(
+        ForwarderDomains::new()
+            .get(&provider, FORWARDER, 31337)
+            .await,
+        Err(DomainError::Mismatch {
+            field: "chainId",
+            ..
+        })
+    ));
+}
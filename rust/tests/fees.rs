@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use counter_client::relayer::{
+    EscalatingTransaction, EscalationPolicy, FeeError, FeeStrategy, Fees,
+};
+use ethers::{
+    providers::Provider,
+    types::{
+        transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+        Address, Eip1559TransactionRequest, FeeHistory, Transaction, TransactionReceipt,
+        TransactionRequest, H256, U256,
+    },
+};
+
+const GWEI: u64 = 1_000_000_000;
+
+fn gwei(amount: u64) -> U256 {
+    U256::from(amount * GWEI)
+}
+
+#[test]
+fn leaves_room_for_the_base_fee() {
+    let fees = FeeStrategy::default().fees(gwei(10), gwei(1)).unwrap();
+    assert_eq!(
+        fees,
+        Fees {
+            max_fee_per_gas: gwei(21),
+            max_priority_fee_per_gas: gwei(1),
+            base_fee_per_gas: gwei(10),
+        }
+    );
+}
+
+#[test]
+fn applies_the_floor_and_caps() {
+    let strategy = FeeStrategy {
+        min_priority_fee_per_gas: 2 * GWEI,
+        max_fee_per_gas: Some(15 * GWEI),
+        ..Default::default()
+    };
+    assert_eq!(
+        strategy.fees(gwei(10), gwei(1)).unwrap(),
+        Fees {
+            max_fee_per_gas: gwei(15),
+            max_priority_fee_per_gas: gwei(2),
+            base_fee_per_gas: gwei(10),
+        }
+    );
+
+    let strategy = FeeStrategy {
+        max_priority_fee_per_gas: Some(GWEI),
+        ..Default::default()
+    };
+    assert_eq!(
+        strategy
+            .fees(gwei(10), gwei(5))
+            .unwrap()
+            .max_priority_fee_per_gas,
+        gwei(1)
+    );
+}
+
+#[test]
+fn refuses_to_price_above_the_cap() {
+    let strategy = FeeStrategy {
+        max_fee_per_gas: Some(10 * GWEI),
+        ..Default::default()
+    };
+    assert!(matches!(
+        strategy.fees(gwei(10), gwei(1)),
+        Err(FeeError::AboveCap { cap, .. }) if cap == gwei(10)
+    ));
+}
+
+#[test]
+fn bumps_within_the_caps() {
+    let strategy = FeeStrategy {
+        max_fee_per_gas: Some(30 * GWEI),
+        ..Default::default()
+    };
+
+    let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+        .max_fee_per_gas(gwei(20))
+        .max_priority_fee_per_gas(gwei(2))
+        .into();
+    assert!(strategy.bump(&mut tx, 12));
+    let TypedTransaction::Eip1559(bumped) = &tx else {
+        unreachable!()
+    };
+    assert_eq!(bumped.max_fee_per_gas, Some(gwei(20) * 112 / 100 + 1));
+    assert_eq!(
+        bumped.max_priority_fee_per_gas,
+        Some(gwei(2) * 112 / 100 + 1)
+    );
+
+    // The max fee hits the cap, and the priority fee never goes above it.
+    assert!(strategy.bump(&mut tx, 50));
+    assert!(strategy.bump(&mut tx, 1000));
+    let TypedTransaction::Eip1559(bumped) = &tx else {
+        unreachable!()
+    };
+    assert_eq!(bumped.max_fee_per_gas, Some(gwei(30)));
+    assert_eq!(bumped.max_priority_fee_per_gas, Some(gwei(30)));
+    assert!(!strategy.bump(&mut tx, 12));
+
+    let mut legacy: TypedTransaction = TransactionRequest::new().gas_price(gwei(29)).into();
+    assert!(strategy.bump(&mut legacy, 12));
+    assert_eq!(legacy.gas_price(), Some(gwei(30)));
+    assert!(!strategy.bump(&mut legacy, 12));
+}
+
+#[tokio::test]
+async fn prices_from_the_fee_history() {
+    let (provider, mock) = Provider::mocked();
+    mock.push(FeeHistory {
+        base_fee_per_gas: vec![gwei(8), gwei(9), gwei(10)],
+        gas_used_ratio: vec![0.5, 0.5],
+        oldest_block: U256::from(100),
+        reward: vec![vec![gwei(3)], vec![gwei(1)], vec![gwei(2)]],
+    })
+    .unwrap();
+
+    let fees = FeeStrategy::default().estimate(&provider).await.unwrap();
+    assert_eq!(
+        fees,
+        Fees {
+            max_fee_per_gas: gwei(22),
+            max_priority_fee_per_gas: gwei(2),
+            base_fee_per_gas: gwei(10),
+        }
+    );
+}
+
+/// A gas wallet transaction at nonce 7.
+fn gas_wallet_tx() -> TypedTransaction {
+    Eip1559TransactionRequest::new()
+        .from(Address::repeat_byte(0x42))
+        .to(Address::repeat_byte(0x43))
+        .nonce(7)
+        .gas(100_000)
+        .max_fee_per_gas(gwei(20))
+        .max_priority_fee_per_gas(gwei(2))
+        .into()
+}
+
+/// `eth_getTransactionByHash` for a transaction in the mempool.
+fn pending(hash: H256) -> Transaction {
+    Transaction {
+        hash,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn follows_the_replacement_that_is_mined() {
+    let (provider, mock) = Provider::mocked();
+    let provider = provider.interval(Duration::from_millis(1));
+
+    let original = H256::repeat_byte(1);
+    let replacement = H256::repeat_byte(2);
+    let tx = gas_wallet_tx();
+
+    // Responses are popped last first: the original has no receipt, its nonce is unused and it
+    // is still known to the node, so it is rebroadcast, and the replacement is then mined.
+    mock.push(TransactionReceipt {
+        transaction_hash: replacement,
+        ..Default::default()
+    })
+    .unwrap();
+    mock.push(replacement).unwrap();
+    mock.push(pending(original)).unwrap();
+    mock.push(U256::from(7)).unwrap();
+    mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+
+    let escalating = EscalatingTransaction::new(
+        &provider,
+        tx,
+        original,
+        FeeStrategy::default(),
+        Some(EscalationPolicy {
+            interval_secs: 0,
+            ..Default::default()
+        }),
+        None,
+    );
+    let (broadcasts, mut replacements) = tokio::sync::mpsc::unbounded_channel();
+    let receipt = escalating
+        .with_broadcasts(broadcasts)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receipt.transaction_hash, replacement);
+    assert_eq!(replacements.try_recv(), Ok(replacement));
+}
+
+#[tokio::test]
+async fn gives_up_on_a_transaction_that_is_dropped_or_never_mined() {
+    let (provider, mock) = Provider::mocked();
+    let provider = provider.interval(Duration::from_millis(1));
+    let hash = H256::repeat_byte(1);
+
+    // No receipt, the nonce is unused, and the node no longer knows the transaction.
+    mock.push::<Option<Transaction>, _>(None).unwrap();
+    mock.push(U256::from(7)).unwrap();
+    mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+    let escalating = EscalatingTransaction::new(
+        &provider,
+        gas_wallet_tx(),
+        hash,
+        FeeStrategy::default(),
+        None,
+        None,
+    );
+    assert!(matches!(
+        escalating.await,
+        Err(FeeError::Dropped { hash: dropped }) if dropped == hash
+    ));
+
+    // Still in the mempool after the bumps are used up and the timeout has passed.
+    mock.push(pending(hash)).unwrap();
+    mock.push(U256::from(7)).unwrap();
+    mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+    let escalating = EscalatingTransaction::new(
+        &provider,
+        gas_wallet_tx(),
+        hash,
+        FeeStrategy::default(),
+        Some(EscalationPolicy {
+            max_bumps: 0,
+            timeout_secs: 0,
+            ..Default::default()
+        }),
+        None,
+    );
+    assert!(matches!(
+        escalating.await,
+        Err(FeeError::TimedOut { hash: stuck, secs: 0 }) if stuck == hash
+    ));
+}
+
+#[tokio::test]
+async fn prices_from_the_gas_price_without_a_base_fee() {
+    let (provider, mock) = Provider::mocked();
+    mock.push(gwei(3)).unwrap();
+    mock.push(FeeHistory {
+        base_fee_per_gas: vec![U256::zero(), U256::zero()],
+        gas_used_ratio: vec![0.5],
+        oldest_block: U256::from(100),
+        reward: vec![],
+    })
+    .unwrap();
+
+    let fees = FeeStrategy::default().estimate(&provider).await.unwrap();
+    assert_eq!(
+        fees,
+        Fees {
+            max_fee_per_gas: gwei(3),
+            max_priority_fee_per_gas: gwei(3),
+            base_fee_per_gas: U256::zero(),
+        }
+    );
+
+    let mut tx: TypedTransaction = TransactionRequest::new().into();
+    fees.apply(&mut tx);
+    assert_eq!(tx.gas_price(), Some(gwei(3)));
+
+    // With a base fee, a legacy transaction pays the base fee plus the priority fee, not the max
+    // fee, which leaves room for the base fee to double.
+    mock.push(FeeHistory {
+        base_fee_per_gas: vec![gwei(9), gwei(10)],
+        gas_used_ratio: vec![0.5],
+        oldest_block: U256::from(100),
+        reward: vec![vec![gwei(2)]],
+    })
+    .unwrap();
+    let fees = FeeStrategy::default().estimate(&provider).await.unwrap();
+    assert_eq!(fees.max_fee_per_gas, gwei(22));
+
+    let mut tx: TypedTransaction = TransactionRequest::new().into();
+    fees.apply(&mut tx);
+    assert_eq!(tx.gas_price(), Some(gwei(12)));
+    let mut tx: TypedTransaction = TransactionRequest::new()
+        .with_access_list(AccessList::default())
+        .into();
+    fees.apply(&mut tx);
+    assert_eq!(tx.gas_price(), Some(gwei(12)));
+}
@@ -0,0 +1,106 @@
+use alloy::{
+    primitives::{address, keccak256, Address, Bytes, B256, U256},
+    sol_types::{eip712_domain, Eip712Domain, SolStruct},
+};
+use counter_client::relayer::{abi, alloy_structs, ForwardRequest};
+
+fn domain() -> Eip712Domain {
+    eip712_domain! {
+        name: "GSNv2 Forwarder",
+        version: "0.0.1",
+        chain_id: 31337,
+        verifying_contract: address!("5FbDB2315678afecb367f032d93F642f64180aa3"),
+    }
+}
+
+fn edge_requests() -> Vec<ForwardRequest> {
+    vec![
+        ForwardRequest::default(),
+        ForwardRequest {
+            from: address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266"),
+            to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+            value: U256::ZERO,
+            gas: U256::from(30000),
+            nonce: U256::ZERO,
+            data: Bytes::new(),
+        },
+        ForwardRequest {
+            from: Address::repeat_byte(0xff),
+            to: Address::repeat_byte(0xff),
+            value: U256::MAX,
+            gas: U256::MAX,
+            nonce: U256::MAX,
+            data: Bytes::from(vec![0xd0, 0x9d, 0xe0, 0x8a]),
+        },
+        ForwardRequest {
+            from: Address::repeat_byte(0x01),
+            to: Address::repeat_byte(0x02),
+            value: U256::from(u64::MAX) + U256::from(1),
+            gas: U256::from(u64::MAX),
+            nonce: U256::from(1) << 255,
+            data: Bytes::from((0..256 * 1024).map(|i| i as u8).collect::<Vec<_>>()),
+        },
+    ]
+}
+
+/// Computes the signing hash by hand, following `Forwarder.verify`.
+fn expected_signing_hash(req: &ForwardRequest, domain: &Eip712Domain) -> B256 {
+    let typehash = keccak256(
+        "ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,bytes data)",
+    );
+
+    let mut encoded = Vec::with_capacity(7 * 32);
+    encoded.extend_from_slice(typehash.as_slice());
+    encoded.extend_from_slice(req.from.into_word().as_slice());
+    encoded.extend_from_slice(req.to.into_word().as_slice());
+    encoded.extend_from_slice(&req.value.to_be_bytes::<32>());
+    encoded.extend_from_slice(&req.gas.to_be_bytes::<32>());
+    encoded.extend_from_slice(&req.nonce.to_be_bytes::<32>());
+    encoded.extend_from_slice(keccak256(&req.data).as_slice());
+    let struct_hash = keccak256(encoded);
+
+    let mut digest = vec![0x19, 0x01];
+    digest.extend_from_slice(domain.separator().as_slice());
+    digest.extend_from_slice(struct_hash.as_slice());
+    keccak256(digest)
+}
+
+#[test]
+fn round_trips_through_ethers_struct() {
+    for req in edge_requests() {
+        let ethers_req: abi::forwarder::ForwardRequest = req.clone().into();
+        assert_eq!(ForwardRequest::from(ethers_req), req);
+    }
+}
+
+#[test]
+fn round_trips_through_alloy_struct() {
+    for req in edge_requests() {
+        let alloy_req: alloy_structs::ForwardRequest = req.clone().into();
+        assert_eq!(ForwardRequest::from(alloy_req), req);
+    }
+}
+
+#[test]
+fn round_trips_through_json() {
+    for req in edge_requests() {
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(serde_json::from_str::<ForwardRequest>(&json).unwrap(), req);
+    }
+}
+
+#[test]
+fn signing_hash_matches_forwarder_encoding() {
+    let domain = domain();
+    for req in edge_requests() {
+        let alloy_req: alloy_structs::ForwardRequest = req.clone().into();
+        assert_eq!(
+            req.eip712_signing_hash(&domain),
+            alloy_req.eip712_signing_hash(&domain)
+        );
+        assert_eq!(
+            req.eip712_signing_hash(&domain),
+            expected_signing_hash(&req, &domain)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use alloy::primitives::{address, bytes, Address, U256};
+use counter_client::relayer::{
+    convert,
+    gas::{self, EXECUTE_OVERHEAD},
+    GasError,
+};
+use ethers::{
+    providers::Provider,
+    types::{transaction::eip2718::TypedTransaction, Eip1559TransactionRequest},
+};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const COUNTER: Address = address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512");
+const FROM: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+#[test]
+fn intrinsic_gas_prices_calldata() {
+    assert_eq!(gas::intrinsic_gas(&[]), 21_000);
+    assert_eq!(gas::intrinsic_gas(&[0, 0, 1]), 21_000 + 4 + 4 + 16);
+}
+
+#[test]
+fn appends_the_sender() {
+    let calldata = gas::forwarded_calldata(&bytes!("d09de08a"), FROM);
+    assert_eq!(calldata.len(), 24);
+    assert_eq!(&calldata[..4], bytes!("d09de08a").as_ref());
+    assert_eq!(&calldata[4..], FROM.as_slice());
+}
+
+#[test]
+fn rejects_a_request_below_the_requirement() {
+    let required = U256::from(26_000);
+
+    assert_eq!(gas::request_gas(required, None), Ok(required));
+    assert_eq!(
+        gas::request_gas(required, Some(U256::from(40_000))),
+        Ok(U256::from(40_000))
+    );
+    assert_eq!(
+        gas::request_gas(required, Some(U256::from(25_999))),
+        Err(GasError::GasTooLow {
+            requested: U256::from(25_999),
+            required,
+        })
+    );
+}
+
+#[test]
+fn outer_limit_leaves_64_63rds_at_the_call() {
+    let execute_calldata = vec![1u8; 100];
+    let req_gas = U256::from(26_000);
+
+    let outer = gas::outer_gas_limit(req_gas, &execute_calldata);
+    let at_call = outer - U256::from(gas::intrinsic_gas(&execute_calldata) + EXECUTE_OVERHEAD);
+
+    // `CALL` forwards all but 1/64 of what is left, which must still cover `req.gas`...
+    assert!(at_call - at_call / U256::from(64) >= req_gas);
+    // ...and what is left after it must pass `assert(gasleft() > req.gas / 63)`.
+    assert!(at_call - req_gas > req_gas / U256::from(63));
+}
+
+#[tokio::test]
+async fn simulates_the_call_the_forwarder_makes() {
+    let (provider, mock) = Provider::mocked();
+    let data = bytes!("d09de08a");
+    let calldata = gas::forwarded_calldata(&data, FROM);
+    mock.push(ethers::types::U256::from(47_000)).unwrap();
+
+    let required =
+        gas::estimate_inner_gas(&provider, FORWARDER, FROM, COUNTER, U256::ZERO, &data, None)
+            .await
+            .unwrap();
+    assert_eq!(required, U256::from(47_000 - gas::intrinsic_gas(&calldata)));
+
+    // From the forwarder, with the meta signer appended.
+    let expected: TypedTransaction = Eip1559TransactionRequest::new()
+        .from(convert::to_ethers_address(FORWARDER))
+        .to(convert::to_ethers_address(COUNTER))
+        .value(0)
+        .data(convert::to_ethers_bytes(calldata))
+        .into();
+    mock.assert_request("eth_estimateGas", [expected]).unwrap();
+}
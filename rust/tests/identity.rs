@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use counter_client::{
+    config::{ConfigError, SignerConfig},
+    identity::{IdentityStore, KEYSTORE_PASSWORD_ENV},
+};
+use ethers::signers::Signer;
+
+const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+fn temp_store(test: &str) -> IdentityStore {
+    let dir: PathBuf = std::env::temp_dir().join(format!(
+        "counter-client-identity-{}-{test}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    IdentityStore::new(dir)
+}
+
+#[test]
+fn keystore_identity_round_trips() {
+    let store = temp_store("keystore");
+    std::env::set_var(KEYSTORE_PASSWORD_ENV, "hunter2");
+
+    let created = store.create_keystore("alice", "hunter2").unwrap();
+    let loaded = store.get("alice").unwrap();
+    assert_eq!(loaded.address, created.address);
+    assert_eq!(loaded.wallet().unwrap().address(), created.address);
+
+    // The key itself is only stored encrypted.
+    let contents = std::fs::read_to_string(store.dir().join("alice.toml")).unwrap();
+    assert!(contents.contains("alice.keystore.json"));
+
+    assert!(matches!(
+        store.create_keystore("alice", "hunter2"),
+        Err(ConfigError::IdentityExists(_))
+    ));
+}
+
+#[test]
+fn mnemonic_identities_derive_by_index() {
+    let store = temp_store("mnemonic");
+    std::env::set_var("COUNTER_CLIENT_TEST_MNEMONIC", MNEMONIC);
+
+    let bob = store
+        .create_mnemonic("bob", "COUNTER_CLIENT_TEST_MNEMONIC", 1)
+        .unwrap();
+    let carol = store
+        .create_mnemonic("carol", "COUNTER_CLIENT_TEST_MNEMONIC", 2)
+        .unwrap();
+
+    assert_eq!(
+        bob.address,
+        "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+            .parse()
+            .unwrap()
+    );
+    assert_eq!(
+        carol.address,
+        "0x3C44CdDdB6a900fa2b585dd299e03d12FA4293BC"
+            .parse()
+            .unwrap()
+    );
+
+    let contents = std::fs::read_to_string(store.dir().join("bob.toml")).unwrap();
+    assert!(!contents.contains("junk"));
+
+    let names: Vec<_> = store
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|identity| identity.name)
+        .collect();
+    assert_eq!(names, ["bob", "carol"]);
+}
+
+#[test]
+fn rejects_unknown_and_invalid_names() {
+    let store = temp_store("names");
+    assert!(store.list().unwrap().is_empty());
+    assert!(matches!(
+        store.get("dave"),
+        Err(ConfigError::UnknownIdentity(_))
+    ));
+    assert!(matches!(
+        store.get("../dave"),
+        Err(ConfigError::InvalidIdentityName(_))
+    ));
+}
+
+#[test]
+fn mnemonic_requires_a_phrase() {
+    let signer = SignerConfig::Mnemonic {
+        phrase: None,
+        phrase_env: None,
+        index: 0,
+    };
+    assert!(matches!(
+        signer.resolve("meta_wallet"),
+        Err(ConfigError::InvalidMnemonic {
+            field: "meta_wallet",
+            ..
+        })
+    ));
+}
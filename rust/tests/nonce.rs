@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use alloy::primitives::{address, Address, U256};
+use counter_client::relayer::{ForwarderNonces, NonceError};
+use ethers::{
+    abi::AbiEncode,
+    providers::{MockProvider, Provider},
+    types::Bytes,
+};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const FROM: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+/// A provider whose next `getNonce` call returns `nonce`.
+fn provider_with_nonce(nonce: u64) -> (Provider<MockProvider>, MockProvider) {
+    let (provider, mock) = Provider::mocked();
+    push_nonce(&mock, nonce);
+    (provider, mock)
+}
+
+fn push_nonce(mock: &MockProvider, nonce: u64) {
+    let result = Bytes::from(ethers::types::U256::from(nonce).encode());
+    mock.push::<Bytes, _>(result).unwrap();
+}
+
+#[tokio::test]
+async fn reads_once_then_increments_locally() {
+    let (provider, _mock) = provider_with_nonce(7);
+    let nonces = ForwarderNonces::new();
+
+    for expected in 7..10 {
+        // The mock only has one response, so every later nonce must be local.
+        let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+        assert_eq!(reservation.nonce(), U256::from(expected));
+        reservation.commit();
+    }
+}
+
+#[tokio::test]
+async fn dropped_reservations_release_the_nonce() {
+    let (provider, _mock) = provider_with_nonce(3);
+    let nonces = ForwarderNonces::new();
+
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), U256::from(3));
+    drop(reservation);
+
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), U256::from(3));
+}
+
+#[tokio::test]
+async fn concurrent_reservations_are_consecutive() {
+    let (provider, _mock) = provider_with_nonce(0);
+    let nonces = ForwarderNonces::new();
+
+    let first = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+
+    // The second reservation for the same signer waits for the first one.
+    let waiting = tokio::spawn({
+        let nonces = nonces.clone();
+        let provider = provider.clone();
+        async move {
+            nonces
+                .reserve(&provider, FORWARDER, FROM)
+                .await
+                .unwrap()
+                .nonce()
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    // Other signers are not held up.
+    let (other_provider, _other_mock) = provider_with_nonce(11);
+    let other = nonces
+        .reserve(&other_provider, FORWARDER, Address::repeat_byte(0x42))
+        .await
+        .unwrap();
+    assert_eq!(other.nonce(), U256::from(11));
+
+    first.commit();
+    assert_eq!(waiting.await.unwrap(), U256::from(1));
+}
+
+#[tokio::test]
+async fn resyncs_from_the_forwarder() {
+    let (provider, mock) = provider_with_nonce(5);
+    let nonces = ForwarderNonces::new();
+
+    nonces
+        .reserve(&provider, FORWARDER, FROM)
+        .await
+        .unwrap()
+        .commit();
+
+    // e.g. the transaction carrying nonce 5 was dropped
+    push_nonce(&mock, 5);
+    nonces.resync(FORWARDER, FROM).await;
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), U256::from(5));
+
+    // e.g. submitting it failed
+    push_nonce(&mock, 6);
+    reservation.resync();
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), U256::from(6));
+}
+
+#[tokio::test]
+async fn reports_an_unreadable_nonce() {
+    let (provider, _mock) = Provider::mocked();
+
+    assert!(matches!(
+        ForwarderNonces::new()
+            .reserve(&provider, FORWARDER, FROM)
+            .await,
+        Err(NonceError::Unavailable {
+            forwarder: FORWARDER,
+            from: FROM,
+            ..
+        })
+    ));
+}
@@ -0,0 +1,295 @@
+use std::sync::{Arc, Mutex};
+
+use alloy::primitives::{address, Address, Bytes, U256};
+use async_trait::async_trait;
+use counter_client::{
+    config::ConfigFile,
+    relayer::{
+        abi, convert, policy::function_selector, AllowRule, Allowlist, ForwardRequest,
+        MetaTransactionOptions, PolicyError, RelayError, Relayer,
+    },
+};
+use ethers::{
+    providers::{Middleware, MockProvider, Provider, ProviderError},
+    types::{transaction::eip2718::TypedTransaction, BlockId},
+};
+
+const COUNTER: Address = address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512");
+
+fn request(data: &[u8]) -> ForwardRequest {
+    ForwardRequest {
+        from: address!("70997970C51812dc3A010C7d01b50e0d17dc79C8"),
+        to: COUNTER,
+        value: U256::ZERO,
+        gas: U256::from(50_000),
+        nonce: U256::ZERO,
+        data: Bytes::copy_from_slice(data),
+    }
+}
+
+fn increment() -> ForwardRequest {
+    request(function_selector("increment()").as_slice())
+}
+
+/// A mocked node that records the `eth_call`s made through it.
+#[derive(Debug)]
+struct RecordingProvider {
+    inner: Provider<MockProvider>,
+    calls: Mutex<Vec<TypedTransaction>>,
+}
+
+#[async_trait]
+impl Middleware for RecordingProvider {
+    type Error = ProviderError;
+    type Provider = MockProvider;
+    type Inner = Provider<MockProvider>;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.inner
+    }
+
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<ethers::types::Bytes, Self::Error> {
+        self.calls.lock().unwrap().push(tx.clone());
+        self.inner.call(tx, block).await
+    }
+}
+
+#[test]
+fn allows_only_listed_functions() {
+    let allowlist = Allowlist::new(vec![AllowRule::function(
+        "counter-increment",
+        COUNTER,
+        "increment()",
+    )]);
+    allowlist.check(&increment()).unwrap();
+
+    let reverts = request(function_selector("definitelyReverts()").as_slice());
+    assert_eq!(
+        allowlist.check(&reverts),
+        Err(PolicyError::NotAllowed {
+            target: COUNTER,
+            selector: Some(function_selector("definitelyReverts()")),
+        })
+    );
+
+    let mut elsewhere = increment();
+    elsewhere.to = Address::repeat_byte(0x42);
+    assert!(matches!(
+        allowlist.check(&elsewhere),
+        Err(PolicyError::NotAllowed { .. })
+    ));
+
+    assert_eq!(
+        allowlist.check(&request(&[])),
+        Err(PolicyError::NotAllowed {
+            target: COUNTER,
+            selector: None,
+        })
+    );
+}
+
+#[test]
+fn caps_value_and_gas_per_rule() {
+    let allowlist = Allowlist::new(vec![AllowRule::function(
+        "counter-increment",
+        COUNTER,
+        "increment()",
+    )
+    .with_max_value(U256::ZERO)
+    .with_max_gas(40_000)]);
+
+    let mut request = increment();
+    assert_eq!(
+        allowlist.check(&request),
+        Err(PolicyError::GasTooHigh {
+            rule: "counter-increment".to_string(),
+            gas: U256::from(50_000),
+            max: U256::from(40_000),
+        })
+    );
+
+    request.gas = U256::from(40_000);
+    request.value = U256::from(1);
+    assert!(matches!(
+        allowlist.check(&request),
+        Err(PolicyError::ValueTooHigh { rule, .. }) if rule == "counter-increment"
+    ));
+
+    // Another rule for the same function with a higher cap lets it through.
+    allowlist.replace(vec![
+        AllowRule::function("counter-increment", COUNTER, "increment()").with_max_value(U256::ZERO),
+        AllowRule::function("counter-increment-paid", COUNTER, "increment()")
+            .with_max_value(U256::from(1)),
+    ]);
+    allowlist.check(&request).unwrap();
+}
+
+#[test]
+fn clones_see_reloaded_rules() {
+    let allowlist = Allowlist::new(vec![]);
+    let relayer_view = allowlist.clone();
+    assert!(relayer_view.check(&increment()).is_err());
+
+    allowlist.replace(vec![AllowRule::function(
+        "increment",
+        COUNTER,
+        "increment()",
+    )]);
+    relayer_view.check(&increment()).unwrap();
+}
+
+#[test]
+fn allows_any_request_only_when_told_to() {
+    let allowlist = Allowlist::default();
+    assert!(!allowlist.allows_any());
+    assert!(allowlist.check(&increment()).is_err());
+
+    let relayer_view = allowlist.clone();
+    allowlist.set_allow_any(true);
+    relayer_view.check(&increment()).unwrap();
+    relayer_view.check(&request(&[])).unwrap();
+    allowlist.set_allow_any(false);
+    assert!(relayer_view.check(&increment()).is_err());
+
+    Allowlist::any().check(&increment()).unwrap();
+
+    let file: ConfigFile = toml::from_str(
+        r#"
+        [profiles.open]
+        rpc_url = "http://127.0.0.1:8545"
+        allow_any = true
+
+        [profiles.closed]
+        rpc_url = "http://127.0.0.1:8545"
+        "#,
+    )
+    .unwrap();
+    let open = file.profiles["open"].clone().resolve("open").unwrap();
+    assert!(open.allow_any);
+    let closed = file.profiles["closed"].clone().resolve("closed").unwrap();
+    assert!(!closed.allow_any);
+    assert_eq!(closed.allowlist, None);
+}
+
+#[test]
+fn parses_rules_from_config() {
+    let file: ConfigFile = toml::from_str(
+        r#"
+        [[profiles.local.allowlist]]
+        name = "counter-increment"
+        target = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+        selector = "increment()"
+        max_value = "0"
+        max_gas = 100_000
+
+        [[profiles.local.allowlist]]
+        name = "counter-by-selector"
+        target = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+        selector = "0xd09de08a"
+        "#,
+    )
+    .unwrap();
+
+    let rules = file.profiles["local"].allowlist.clone().unwrap();
+    assert_eq!(
+        rules,
+        vec![
+            AllowRule::function("counter-increment", COUNTER, "increment()")
+                .with_max_value(U256::ZERO)
+                .with_max_gas(100_000),
+            AllowRule::function("counter-by-selector", COUNTER, "increment()"),
+        ]
+    );
+
+    let error = toml::from_str::<ConfigFile>(
+        r#"
+        [[profiles.local.allowlist]]
+        name = "typo"
+        target = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+        selector = "0xd09de0"
+        "#,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("invalid selector"), "{error}");
+}
+
+#[tokio::test]
+async fn relayer_rejects_before_any_rpc() {
+    // The mock has no responses, so any RPC call would fail with a different error.
+    let (provider, _mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(Address::repeat_byte(0x11)),
+        Arc::new(provider),
+    );
+    let relayer = Relayer::new(forwarder).with_allowlist(Allowlist::new(vec![]));
+
+    let error = relayer.verify(&increment(), &[0; 65]).await.unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::PolicyRejected(PolicyError::NotAllowed { .. })
+    ));
+
+    let error = relayer
+        .relay(
+            increment(),
+            vec![0; 65].into(),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::PolicyRejected(PolicyError::NotAllowed { .. })
+    ));
+}
+
+#[tokio::test]
+async fn relayer_sends_the_value_it_allowed() {
+    // The mock has no responses, so the preflight of `execute` is the last call made.
+    let (provider, _mock) = Provider::mocked();
+    let provider = Arc::new(RecordingProvider {
+        inner: provider,
+        calls: Mutex::new(Vec::new()),
+    });
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(Address::repeat_byte(0x11)),
+        provider.clone(),
+    );
+    let relayer =
+        Relayer::new(forwarder).with_allowlist(Allowlist::new(vec![AllowRule::function(
+            "counter-increment-paid",
+            COUNTER,
+            "increment()",
+        )
+        .with_max_value(U256::from(1_000))]));
+
+    let mut request = increment();
+    request.value = U256::from(1_001);
+    let error = relayer
+        .relay(
+            request.clone(),
+            vec![0; 65].into(),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::PolicyRejected(PolicyError::ValueTooHigh { .. })
+    ));
+    assert!(provider.calls.lock().unwrap().is_empty());
+
+    request.value = U256::from(1_000);
+    let error = relayer
+        .relay(request, vec![0; 65].into(), MetaTransactionOptions::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, RelayError::PreflightFailed(_)), "{error}");
+    let calls = provider.calls.lock().unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].value(), Some(&ethers::types::U256::from(1_000)));
+}
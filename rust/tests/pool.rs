@@ -0,0 +1,366 @@
+use std::{sync::Arc, time::Duration};
+
+use alloy::primitives::{address, Address, U256};
+use counter_client::{
+    config::ConfigFile,
+    relayer::{
+        abi::{self, forwarder::ExecuteReturn},
+        convert, BalanceMonitor, ForwardRequest, GasWalletPool, MetaTransactionOptions,
+        OuterTransactionType, PoolConfig, PoolError, PoolEvent, Relayer, WalletLease,
+        WalletSelection,
+    },
+};
+use ethers::{
+    abi::AbiEncode,
+    providers::{MockProvider, Provider},
+    types::{
+        transaction::eip2718::TypedTransaction, Bytes, Transaction, TransactionReceipt,
+        TransactionRequest, H256, U64,
+    },
+};
+
+type Client = Provider<MockProvider>;
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const ALICE: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+const BOB: Address = address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC");
+const CAROL: Address = address!("90F79bf6EB2c4f870365E785982E1f101E93b906");
+
+/// A mocked client whose default sender is `address`.
+fn client(address: Address) -> (Arc<Client>, MockProvider) {
+    let (provider, mock) = Provider::mocked();
+    let provider = provider
+        .with_sender(convert::to_ethers_address(address))
+        .interval(Duration::from_millis(1));
+    (Arc::new(provider), mock)
+}
+
+/// A pool of `size` wallets, `0x0101…`, `0x0202…` and so on, with the mocks of their clients.
+fn pool(size: u8, selection: WalletSelection) -> (GasWalletPool<Client>, Vec<MockProvider>) {
+    let (forwarders, mocks) = (1..=size)
+        .map(|byte| {
+            let (client, mock) = client(Address::repeat_byte(byte));
+            (
+                abi::Forwarder::new(convert::to_ethers_address(FORWARDER), client),
+                mock,
+            )
+        })
+        .unzip();
+    (GasWalletPool::new(forwarders, selection).unwrap(), mocks)
+}
+
+fn wei(amount: u64) -> ethers::types::U256 {
+    ethers::types::U256::from(amount)
+}
+
+#[test]
+fn round_robin_keeps_a_signers_requests_on_one_wallet() {
+    let (pool, _mocks) = pool(2, WalletSelection::RoundRobin);
+    let index = |lease: &WalletLease<Client>| {
+        pool.wallets()
+            .iter()
+            .position(|wallet| wallet.address() == lease.wallet().address())
+            .unwrap()
+    };
+
+    let alice = pool.select(ALICE).unwrap();
+    let bob = pool.select(BOB).unwrap();
+    assert_eq!((index(&alice), index(&bob)), (0, 1));
+
+    // Alice's next request waits behind her first one on the same wallet.
+    let alice_again = pool.select(ALICE).unwrap();
+    assert_eq!(index(&alice_again), 0);
+    assert_eq!(pool.wallets()[0].pending(), 2);
+
+    drop(alice);
+    drop(alice_again);
+    assert_eq!(pool.wallets()[0].pending(), 0);
+    // Her requests are done, so she takes her turn like anyone else.
+    assert_eq!(index(&pool.select(ALICE).unwrap()), 0);
+    assert_eq!(index(&pool.select(CAROL).unwrap()), 1);
+}
+
+#[test]
+fn least_pending_picks_the_idlest_wallet() {
+    let (pool, _mocks) = pool(3, WalletSelection::LeastPending);
+    let address = |byte| Address::repeat_byte(byte);
+
+    let alice = pool.select(ALICE).unwrap();
+    let _alice_again = pool.select(ALICE).unwrap();
+    let bob = pool.select(BOB).unwrap();
+    assert_eq!(alice.wallet().address(), address(1));
+    assert_eq!(bob.wallet().address(), address(2));
+
+    // Wallet 3 is idle, wallet 2 has one request and wallet 1 two.
+    let carol = pool.select(CAROL).unwrap();
+    assert_eq!(carol.wallet().address(), address(3));
+    drop(bob);
+    assert_eq!(
+        pool.select(Address::repeat_byte(0xdd))
+            .unwrap()
+            .wallet()
+            .address(),
+        address(2)
+    );
+}
+
+#[tokio::test]
+async fn wallets_count_their_nonces() {
+    let (pool, mocks) = pool(1, WalletSelection::RoundRobin);
+    let wallet = &pool.wallets()[0];
+    let transfer = || -> TypedTransaction {
+        TransactionRequest::new()
+            .to(convert::to_ethers_address(BOB))
+            .gas(21_000)
+            .gas_price(1)
+            .into()
+    };
+
+    // Last in, first out: the nonce is read once, then both transactions are sent.
+    mocks[0].push(H256::repeat_byte(2)).unwrap();
+    mocks[0].push(H256::repeat_byte(1)).unwrap();
+    mocks[0].push(wei(7)).unwrap();
+
+    let mut first = transfer();
+    let mut second = transfer();
+    assert_eq!(
+        wallet.send(&mut first, None).await.unwrap(),
+        H256::repeat_byte(1)
+    );
+    assert_eq!(
+        wallet.send(&mut second, None).await.unwrap(),
+        H256::repeat_byte(2)
+    );
+    assert_eq!(first.nonce(), Some(&wei(7)));
+    assert_eq!(second.nonce(), Some(&wei(8)));
+
+    // The node has no response, so the send fails and the nonce is read again.
+    assert!(wallet.send(&mut transfer(), None).await.is_err());
+    mocks[0].push(H256::repeat_byte(3)).unwrap();
+    mocks[0].push(wei(9)).unwrap();
+    let mut third = transfer();
+    wallet.send(&mut third, None).await.unwrap();
+    assert_eq!(third.nonce(), Some(&wei(9)));
+
+    // Once the wallet is idle, the nonce is read again, e.g. after a dropped transaction.
+    drop(pool.select(ALICE).unwrap());
+    mocks[0].push(H256::repeat_byte(4)).unwrap();
+    mocks[0].push(wei(20)).unwrap();
+    let mut fourth = transfer();
+    wallet.send(&mut fourth, None).await.unwrap();
+    assert_eq!(fourth.nonce(), Some(&wei(20)));
+}
+
+#[tokio::test]
+async fn relayed_requests_hold_their_wallet_until_mined() {
+    let (pool, mocks) = pool(1, WalletSelection::RoundRobin);
+    let relayer = Relayer::new(pool.wallets()[0].forwarder().clone())
+        .with_transaction_type(OuterTransactionType::Legacy)
+        .with_gas_wallets(pool.clone());
+
+    // Last in, first out: the preflight, the wallet's nonce, the gas price and the broadcast.
+    let tx_hash = H256::repeat_byte(0xab);
+    let output = Bytes::from(ExecuteReturn(true, Bytes::new()).encode());
+    mocks[0].push(tx_hash).unwrap();
+    mocks[0].push(wei(1)).unwrap();
+    mocks[0].push(wei(0)).unwrap();
+    mocks[0].push::<Bytes, _>(output.clone()).unwrap();
+
+    let request = ForwardRequest {
+        from: ALICE,
+        to: BOB,
+        gas: U256::from(30_000),
+        ..Default::default()
+    };
+    let (sent, follower) = relayer
+        .relay_in_background(request, vec![0; 65].into(), MetaTransactionOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(sent, tx_hash);
+    // The follower has not run yet, and the caller has nothing left to drop.
+    assert_eq!(pool.wallets()[0].pending(), 1);
+
+    // The follower finds the receipt, the call trace and `getNonce(from)`.
+    mocks[0]
+        .push::<Bytes, _>(Bytes::from(wei(1).encode()))
+        .unwrap();
+    mocks[0]
+        .push(serde_json::json!({
+            "type": "CALL",
+            "from": convert::to_ethers_address(Address::repeat_byte(1)),
+            "to": FORWARDER,
+            "gas": "0x186a0",
+            "gasUsed": "0xc350",
+            "input": "0x",
+            "output": output,
+        }))
+        .unwrap();
+    mocks[0]
+        .push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(1)),
+            ..Default::default()
+        })
+        .unwrap();
+    let receipt = follower.await.unwrap().unwrap().unwrap();
+    assert_eq!(receipt.receipt.transaction_hash, tx_hash);
+    assert_eq!(pool.wallets()[0].pending(), 0);
+}
+
+#[tokio::test]
+async fn monitor_takes_low_wallets_out_of_rotation() {
+    let (pool, mocks) = pool(2, WalletSelection::RoundRobin);
+    let mut events = pool.subscribe();
+    let monitor = BalanceMonitor::new(pool.clone(), U256::from(1_000));
+
+    mocks[0].push(wei(999)).unwrap();
+    mocks[1].push(wei(5_000)).unwrap();
+    monitor.check().await;
+
+    assert_eq!(
+        events.try_recv().unwrap(),
+        PoolEvent::LowBalance {
+            wallet: Address::repeat_byte(1),
+            balance: U256::from(999),
+            min_balance: U256::from(1_000),
+        }
+    );
+    assert!(events.try_recv().is_err());
+    assert!(!pool.wallets()[0].is_active());
+    assert_eq!(pool.wallets()[1].balance(), Some(U256::from(5_000)));
+    for signer in [ALICE, BOB, CAROL] {
+        assert_eq!(
+            pool.select(signer).unwrap().wallet().address(),
+            Address::repeat_byte(2)
+        );
+    }
+
+    // A signer with a request in flight stays on their wallet when it runs low.
+    let alice = pool.select(ALICE).unwrap();
+    mocks[0].push(wei(2_000)).unwrap();
+    mocks[1].push(wei(10)).unwrap();
+    monitor.check().await;
+    assert!(!pool.wallets()[1].is_active());
+    assert_eq!(
+        pool.select(ALICE).unwrap().wallet().address(),
+        Address::repeat_byte(2)
+    );
+    assert_eq!(
+        pool.select(BOB).unwrap().wallet().address(),
+        Address::repeat_byte(1)
+    );
+    drop(alice);
+
+    mocks[0].push(wei(10)).unwrap();
+    mocks[1].push(wei(10)).unwrap();
+    monitor.check().await;
+    assert_eq!(
+        pool.select(ALICE).unwrap_err(),
+        PoolError::NoWalletAvailable { wallets: 2 }
+    );
+
+    // Funded by hand.
+    mocks[0].push(wei(2_000)).unwrap();
+    mocks[1].push(wei(10)).unwrap();
+    monitor.check().await;
+    let events: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+    assert_eq!(
+        events.last(),
+        Some(&PoolEvent::Restored {
+            wallet: Address::repeat_byte(1),
+            balance: U256::from(2_000),
+        })
+    );
+    assert!(pool.wallets()[0].is_active());
+}
+
+#[tokio::test]
+async fn monitor_tops_up_from_the_treasury() {
+    let (pool, mocks) = pool(1, WalletSelection::RoundRobin);
+    let (treasury, treasury_mock) = client(Address::repeat_byte(0xee));
+    let mut events = pool.subscribe();
+    let monitor = BalanceMonitor::new(pool.clone(), U256::from(1_000))
+        .with_treasury(treasury, U256::from(4_000));
+
+    // The wallet is low, gets a transfer and is checked again.
+    mocks[0].push(wei(4_500)).unwrap();
+    mocks[0].push(wei(500)).unwrap();
+
+    // Last in, first out: the treasury prices the transfer, sends it and follows it until it is
+    // mined.
+    let tx_hash = H256::repeat_byte(0xab);
+    treasury_mock
+        .push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(1)),
+            ..Default::default()
+        })
+        .unwrap();
+    treasury_mock
+        .push(Transaction {
+            hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            ..Default::default()
+        })
+        .unwrap();
+    treasury_mock.push(tx_hash).unwrap();
+    treasury_mock.push(wei(21_000)).unwrap();
+    treasury_mock.push(wei(21_000)).unwrap();
+
+    monitor.check().await;
+
+    let events: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+    let wallet = Address::repeat_byte(1);
+    assert_eq!(
+        events,
+        vec![
+            PoolEvent::LowBalance {
+                wallet,
+                balance: U256::from(500),
+                min_balance: U256::from(1_000),
+            },
+            PoolEvent::ToppedUp {
+                wallet,
+                amount: U256::from(4_000),
+                tx_hash,
+            },
+            PoolEvent::Restored {
+                wallet,
+                balance: U256::from(4_500),
+            },
+        ]
+    );
+    assert!(pool.wallets()[0].is_active());
+}
+
+#[test]
+fn parses_gas_pool_from_config() {
+    let file: ConfigFile = toml::from_str(
+        r#"
+        [profiles.local]
+        gas_wallets = [{ private_key_env = "GAS_KEY_1" }, { private_key_env = "GAS_KEY_2" }]
+        treasury_wallet = { private_key_env = "TREASURY_KEY" }
+
+        [profiles.local.gas_pool]
+        selection = "least-pending"
+        min_balance_wei = "50000000000000000"
+        top_up_wei = "200000000000000000"
+        "#,
+    )
+    .unwrap();
+
+    let profile = &file.profiles["local"];
+    assert_eq!(profile.gas_wallets.as_ref().map(Vec::len), Some(2));
+    assert!(profile.treasury_wallet.is_some());
+    assert_eq!(
+        profile.gas_pool,
+        Some(PoolConfig {
+            selection: WalletSelection::LeastPending,
+            min_balance_wei: Some(U256::from(50_000_000_000_000_000u64)),
+            check_interval_secs: 30,
+            top_up_wei: Some(U256::from(200_000_000_000_000_000u64)),
+        })
+    );
+}
@@ -0,0 +1,118 @@
+use counter_client::relayer::{
+    abi::forwarder::{ExecuteReturn, SignatureDoesNotMatch},
+    preflight, ForwarderRevert, MetaTransactionOptions, PreflightError, RevertDecoder,
+    RevertReason, TargetRevert,
+};
+use ethers::{
+    abi::AbiEncode,
+    contract::EthError,
+    providers::{JsonRpcError, MockResponse, Provider},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes,
+        Eip1559TransactionRequest,
+    },
+};
+
+fn execute_tx() -> TypedTransaction {
+    Eip1559TransactionRequest::new()
+        .from(Address::repeat_byte(0x42))
+        .to(Address::repeat_byte(0x43))
+        .gas(100_000)
+        .data(Bytes::from(vec![0xdf, 0x90, 0x5c, 0xaf]))
+        .into()
+}
+
+fn revert(data: Vec<u8>) -> MockResponse {
+    MockResponse::Error(JsonRpcError {
+        code: 3,
+        message: "execution reverted".to_string(),
+        data: Some(serde_json::json!(Bytes::from(data))),
+    })
+}
+
+#[tokio::test]
+async fn returns_the_target_output_at_the_pending_block() {
+    let (provider, mock) = Provider::mocked();
+    let output = Bytes::from(ExecuteReturn(true, Bytes::from(vec![1, 2, 3])).encode());
+    mock.push::<Bytes, _>(output).unwrap();
+
+    let tx = execute_tx();
+    let data = preflight::preflight(&provider, &tx, None, &RevertDecoder::new())
+        .await
+        .unwrap();
+    assert_eq!(data, Bytes::from(vec![1, 2, 3]));
+
+    mock.assert_request(
+        "eth_call",
+        (
+            serde_json::to_value(&tx).unwrap(),
+            serde_json::to_value(BlockNumber::Pending).unwrap(),
+        ),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn refuses_a_request_the_forwarder_rejects() {
+    let (provider, mock) = Provider::mocked();
+    mock.push_response(revert(SignatureDoesNotMatch.encode()));
+
+    let result = preflight::preflight(&provider, &execute_tx(), None, &RevertDecoder::new()).await;
+    assert!(matches!(
+        result,
+        Err(PreflightError::Reverted(RevertReason::Forwarder(
+            ForwarderRevert::SignatureDoesNotMatch
+        )))
+    ));
+}
+
+#[tokio::test]
+async fn refuses_a_failed_call_the_forwarder_returns() {
+    let (provider, mock) = Provider::mocked();
+    let output = Bytes::from(ExecuteReturn(false, Bytes::new()).encode());
+    mock.push::<Bytes, _>(output).unwrap();
+
+    let result = preflight::preflight(&provider, &execute_tx(), None, &RevertDecoder::new()).await;
+    assert!(matches!(
+        result,
+        Err(PreflightError::Reverted(RevertReason::Unknown(data))) if data.is_empty()
+    ));
+
+    // A revert string from the target is passed on by the forwarder.
+    let reason = [
+        String::selector().to_vec(),
+        "Only the owner".to_string().encode(),
+    ]
+    .concat();
+    mock.push_response(revert(reason));
+    let result = preflight::preflight(&provider, &execute_tx(), None, &RevertDecoder::new()).await;
+    assert!(matches!(
+        result,
+        Err(PreflightError::Reverted(RevertReason::Target(TargetRevert::Message(message))))
+            if message == "Only the owner"
+    ));
+}
+
+#[tokio::test]
+async fn reports_other_rpc_errors() {
+    let (provider, mock) = Provider::mocked();
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: -32000,
+        message: "insufficient funds for gas * price + value".to_string(),
+        data: None,
+    }));
+
+    let result = preflight::preflight(&provider, &execute_tx(), None, &RevertDecoder::new()).await;
+    assert!(matches!(result, Err(PreflightError::Rpc(_))));
+}
+
+#[test]
+fn options_opt_out_of_the_preflight() {
+    let options = MetaTransactionOptions::new();
+    assert!(!options.skip_preflight);
+    assert_eq!(options.block, None);
+
+    let options = options.block(BlockNumber::Latest).skip_preflight();
+    assert!(options.skip_preflight);
+    assert_eq!(options.block, Some(BlockNumber::Latest.into()));
+}
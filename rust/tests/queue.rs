@@ -0,0 +1,327 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use alloy::{
+    primitives::{address, Address, FixedBytes, B256, U256},
+    sol_types::SolValue,
+};
+use counter_client::{
+    relayer::{
+        abi::{self, forwarder::ExecuteReturn},
+        convert, ForwardRequest, QueueError, RelayError, RelayQueue, RelayStatus, Relayer,
+        FORWARDER_NAME, FORWARDER_VERSION,
+    },
+    server::RelayServer,
+};
+use ethers::{
+    abi::AbiEncode,
+    providers::{MockProvider, Provider},
+    types::{Bytes, Transaction, TransactionReceipt, H256, U64},
+};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const ALICE: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+fn temp_queue(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "counter-client-queue-{}-{test}.redb",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn increment(nonce: u64) -> ForwardRequest {
+    ForwardRequest {
+        from: ALICE,
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(50_000),
+        nonce: U256::from(nonce),
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    }
+}
+
+fn signature() -> Bytes {
+    Bytes::from(vec![0; 65])
+}
+
+/// The ABI-encoded return value of `eip712Domain()`.
+fn eip712_domain() -> Bytes {
+    (
+        FixedBytes::<1>::from([0x0f]),
+        FORWARDER_NAME.to_string(),
+        FORWARDER_VERSION.to_string(),
+        U256::from(31337),
+        FORWARDER,
+        FixedBytes::<32>::ZERO,
+        Vec::<U256>::new(),
+    )
+        .abi_encode_params()
+        .into()
+}
+
+fn push_word(mock: &MockProvider, word: u64) {
+    mock.push::<Bytes, _>(Bytes::from(ethers::types::U256::from(word).encode()))
+        .unwrap();
+}
+
+fn mocked_server(queue: RelayQueue) -> (RelayServer<Provider<MockProvider>>, MockProvider) {
+    let (provider, mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), Arc::new(provider));
+    (
+        RelayServer::new(Relayer::new(forwarder)).with_queue(queue),
+        mock,
+    )
+}
+
+/// Waits for the background task to move request `id` out of `Accepted` and `Pending`.
+async fn finished_status(
+    server: &RelayServer<Provider<MockProvider>>,
+    id: B256,
+) -> Option<RelayStatus> {
+    for _ in 0..100 {
+        match server.status(id).unwrap() {
+            Some(RelayStatus::Accepted | RelayStatus::Pending { .. }) => {
+                tokio::time::sleep(Duration::from_millis(10)).await
+            }
+            status => return status,
+        }
+    }
+    panic!("request {id} is still in flight");
+}
+
+#[test]
+fn queue_survives_reopening() {
+    let path = temp_queue("reopen");
+    let queue = RelayQueue::open(&path).unwrap();
+    let first = B256::repeat_byte(1);
+
+    let queued = queue
+        .accept(first, increment(0), signature())
+        .unwrap()
+        .unwrap();
+    assert_eq!(queued.status, RelayStatus::Accepted);
+    queue
+        .set_status(
+            ALICE,
+            U256::ZERO,
+            RelayStatus::Pending {
+                tx_hash: H256::repeat_byte(0xaa),
+                replacements: Vec::new(),
+            },
+        )
+        .unwrap();
+    queue
+        .accept(B256::repeat_byte(2), increment(1), signature())
+        .unwrap();
+    drop(queue);
+
+    let queue = RelayQueue::open(&path).unwrap();
+    let unfinished = queue.unfinished().unwrap();
+    let nonces: Vec<_> = unfinished.iter().map(|entry| entry.request.nonce).collect();
+    assert_eq!(nonces, vec![U256::ZERO, U256::from(1)]);
+    assert_eq!(
+        queue.get_by_id(first).unwrap().unwrap().status,
+        RelayStatus::Pending {
+            tx_hash: H256::repeat_byte(0xaa),
+            replacements: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn one_live_request_per_nonce() {
+    let queue = RelayQueue::in_memory().unwrap();
+    let first = B256::repeat_byte(1);
+    let second = B256::repeat_byte(2);
+
+    assert!(queue
+        .accept(first, increment(0), signature())
+        .unwrap()
+        .is_some());
+    // The same request again is not queued twice.
+    assert_eq!(
+        queue.accept(first, increment(0), signature()).unwrap(),
+        None
+    );
+    assert!(matches!(
+        queue.accept(second, increment(0), signature()),
+        Err(QueueError::NonceTaken { from: ALICE, .. })
+    ));
+
+    // A failed request gives up its nonce.
+    queue
+        .set_status(
+            ALICE,
+            U256::ZERO,
+            RelayStatus::Failed {
+                reason: "reverted".to_string(),
+            },
+        )
+        .unwrap();
+    assert!(queue.unfinished().unwrap().is_empty());
+    let replacement = queue
+        .accept(second, increment(0), signature())
+        .unwrap()
+        .unwrap();
+    assert_eq!(replacement.id, second);
+    assert_eq!(queue.get_by_id(first).unwrap(), None);
+}
+
+#[tokio::test]
+async fn server_rejects_another_request_for_a_queued_nonce() {
+    let queue = RelayQueue::in_memory().unwrap();
+    queue
+        .accept(B256::repeat_byte(1), increment(0), signature())
+        .unwrap();
+    let (server, mock) = mocked_server(queue);
+    // The chain id and domain for the request's id.
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+
+    let error = server
+        .send_forward_request(increment(0), signature())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::Queue(QueueError::NonceTaken { .. })
+    ));
+}
+
+#[tokio::test]
+async fn resume_fails_requests_whose_nonce_was_used() {
+    let queue = RelayQueue::in_memory().unwrap();
+    let accepted = B256::repeat_byte(1);
+    let dropped = B256::repeat_byte(2);
+    queue.accept(accepted, increment(4), signature()).unwrap();
+    queue.accept(dropped, increment(5), signature()).unwrap();
+    queue
+        .set_status(
+            ALICE,
+            U256::from(5),
+            RelayStatus::Pending {
+                tx_hash: H256::repeat_byte(0xaa),
+                replacements: Vec::new(),
+            },
+        )
+        .unwrap();
+    let (server, mock) = mocked_server(queue);
+
+    // Last in, first out. The accepted request is verified again: the chain id, the domain and
+    // `getNonce(from)`, which has moved past it. Then the pending request's `execute` is looked
+    // up and found dropped, so it is verified again too: the chain id, now that the domain is
+    // cached, and `getNonce(from)`.
+    push_word(&mock, 6);
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push::<Option<Transaction>, _>(None).unwrap();
+    push_word(&mock, 6);
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+
+    assert_eq!(server.resume().await.unwrap(), 2);
+
+    for id in [accepted, dropped] {
+        match finished_status(&server, id).await {
+            Some(RelayStatus::Failed { reason }) => {
+                assert!(reason.contains("Not resubmitted"), "{reason}")
+            }
+            status => panic!("unexpected status {status:?}"),
+        }
+    }
+    assert!(server.queue().unfinished().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn resume_follows_a_mined_replacement() {
+    let queue = RelayQueue::in_memory().unwrap();
+    let id = B256::repeat_byte(1);
+    let original = H256::repeat_byte(0xaa);
+    let replacement = H256::repeat_byte(0xbb);
+    queue.accept(id, increment(0), signature()).unwrap();
+    queue
+        .set_status(
+            ALICE,
+            U256::ZERO,
+            RelayStatus::Pending {
+                tx_hash: original,
+                replacements: vec![replacement],
+            },
+        )
+        .unwrap();
+    let (server, mock) = mocked_server(queue);
+
+    // Last in, first out: the replacement is looked up first and found mined, then its call trace
+    // and `getNonce(from)` are read. The dropped original is never resubmitted.
+    push_word(&mock, 1);
+    mock.push(serde_json::json!({
+        "type": "CALL",
+        "from": "0x4242424242424242424242424242424242424242",
+        "to": FORWARDER,
+        "gas": "0x186a0",
+        "gasUsed": "0xc350",
+        "input": "0x",
+        "output": Bytes::from(ExecuteReturn(true, Bytes::new()).encode()),
+    }))
+    .unwrap();
+    mock.push(TransactionReceipt {
+        transaction_hash: replacement,
+        block_number: Some(U64::from(10)),
+        status: Some(U64::from(1)),
+        ..Default::default()
+    })
+    .unwrap();
+    mock.push(Transaction {
+        hash: replacement,
+        block_number: Some(U64::from(10)),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(server.resume().await.unwrap(), 1);
+    match finished_status(&server, id).await {
+        Some(RelayStatus::Mined(receipt)) => {
+            assert_eq!(receipt.receipt.transaction_hash, replacement);
+            assert!(receipt.nonce_advanced);
+        }
+        status => panic!("unexpected status {status:?}"),
+    }
+}
+
+#[tokio::test]
+async fn resume_waits_for_the_gas_wallet_before_resubmitting() {
+    let queue = RelayQueue::in_memory().unwrap();
+    let id = B256::repeat_byte(1);
+    queue.accept(id, increment(4), signature()).unwrap();
+    let gas_wallet = ethers::types::Address::repeat_byte(0x42);
+    let (provider, mock) = Provider::mocked();
+    let provider = provider
+        .with_sender(gas_wallet)
+        .interval(Duration::from_millis(1));
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), Arc::new(provider));
+    let server = RelayServer::new(Relayer::new(forwarder)).with_queue(queue);
+
+    // Last in, first out. The gas wallet has a transaction in the mempool at nonce 2: maybe the
+    // request's `execute`, broadcast before its status was written. Once it is mined, the request
+    // is verified again: the chain id, the domain and `getNonce(from)`, which it has used.
+    push_word(&mock, 5);
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push(ethers::types::U256::from(3)).unwrap();
+    mock.push(ethers::types::U256::from(2)).unwrap();
+    mock.push(ethers::types::U256::from(3)).unwrap();
+
+    assert_eq!(server.resume().await.unwrap(), 1);
+    match finished_status(&server, id).await {
+        Some(RelayStatus::Failed { reason }) => {
+            assert!(reason.contains("Not resubmitted"), "{reason}")
+        }
+        status => panic!("unexpected status {status:?}"),
+    }
+    mock.assert_request("eth_getTransactionCount", (gas_wallet, "pending"))
+        .unwrap();
+    for _ in 0..2 {
+        mock.assert_request("eth_getTransactionCount", (gas_wallet, "latest"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use alloy::primitives::{address, Address, Bytes, U256};
+use counter_client::{
+    config::ConfigFile,
+    relayer::{
+        abi, convert,
+        rate_limit::{RateKey, RateTier},
+        ForwardRequest, HttpRelayTransport, MetaTransactionOptions, RateLimit, RateLimitConfig,
+        RateLimitError, RateLimiter, RelayTransport, Relayer, TransportError,
+    },
+    server::RelayServer,
+};
+use ethers::providers::{Http, Provider};
+use tokio::net::TcpListener;
+
+const ALICE: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+const BOB: Address = address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC");
+
+fn localhost() -> IpAddr {
+    "127.0.0.1".parse().unwrap()
+}
+
+fn increment(from: Address) -> ForwardRequest {
+    ForwardRequest {
+        from,
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(50_000),
+        nonce: U256::ZERO,
+        data: Bytes::from(vec![0xd0, 0x9d, 0xe0, 0x8a]),
+    }
+}
+
+#[test]
+fn buckets_allow_bursts_then_refill() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        signer: Some(RateLimit::per_minute(60).with_burst(2)),
+        ..Default::default()
+    });
+    let key = RateKey::Signer(ALICE);
+    let start = Instant::now();
+
+    limiter.check_at(key, start).unwrap();
+    limiter.check_at(key, start).unwrap();
+    assert_eq!(
+        limiter.check_at(key, start),
+        Err(RateLimitError::Exceeded {
+            key,
+            retry_after: Duration::from_secs(1),
+        })
+    );
+    // Other signers have buckets of their own.
+    limiter.check_at(RateKey::Signer(BOB), start).unwrap();
+
+    // One request per second on average.
+    let later = start + Duration::from_millis(1_500);
+    limiter.check_at(key, later).unwrap();
+    let error = limiter.check_at(key, later).unwrap_err();
+    assert_eq!(error.retry_after(), Duration::from_millis(500));
+    assert!(error.to_string().contains("retry after 1s"), "{error}");
+}
+
+#[test]
+fn many_clients_keep_their_buckets() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        ip: Some(RateLimit::per_minute(1)),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    let ip = |i: u32| RateKey::Ip(IpAddr::from(i.to_be_bytes()));
+
+    // Well past the point where full buckets are forgotten, every client is still limited.
+    for i in 0..25_000 {
+        limiter.check_at(ip(i), start).unwrap();
+    }
+    for i in [0, 12_345, 24_999] {
+        assert!(limiter.check_at(ip(i), start).is_err());
+    }
+
+    // A minute later, the bucket has refilled.
+    let later = start + Duration::from_secs(60);
+    limiter.check_at(ip(0), later).unwrap();
+}
+
+#[test]
+fn tiers_override_the_default_limits() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        signer: Some(RateLimit::per_minute(1)),
+        ip: None,
+        tiers: vec![RateTier {
+            name: "partners".to_string(),
+            signer: Some(RateLimit::per_minute(3)),
+            ip: None,
+            signers: vec![BOB],
+            ips: vec![],
+        }],
+    });
+    let now = Instant::now();
+
+    limiter.check_at(RateKey::Signer(ALICE), now).unwrap();
+    assert!(limiter.check_at(RateKey::Signer(ALICE), now).is_err());
+
+    for _ in 0..3 {
+        limiter.check_at(RateKey::Signer(BOB), now).unwrap();
+    }
+    assert!(limiter.check_at(RateKey::Signer(BOB), now).is_err());
+
+    // Without an `ip` limit, clients are not counted.
+    for _ in 0..10 {
+        limiter.check_at(RateKey::Ip(localhost()), now).unwrap();
+    }
+}
+
+#[test]
+fn parses_rate_limits_from_config() {
+    let file: ConfigFile = toml::from_str(
+        r#"
+        [profiles.local.rate_limits]
+        signer = { requests_per_minute = 30, burst = 10 }
+        ip = { requests_per_minute = 120 }
+
+        [[profiles.local.rate_limits.tiers]]
+        name = "partners"
+        signer = { requests_per_minute = 600, burst = 100 }
+        signers = ["0x70997970C51812dc3A010C7d01b50e0d17dc79C8"]
+        ips = ["10.0.0.1"]
+        "#,
+    )
+    .unwrap();
+
+    let config = file.profiles["local"].rate_limits.clone().unwrap();
+    assert_eq!(
+        config.signer,
+        Some(RateLimit::per_minute(30).with_burst(10))
+    );
+    assert_eq!(config.ip, Some(RateLimit::per_minute(120)));
+    assert_eq!(
+        config.limit(&RateKey::Signer(ALICE)),
+        Some(RateLimit::per_minute(600).with_burst(100))
+    );
+    assert_eq!(
+        config.limit(&RateKey::Ip("10.0.0.1".parse().unwrap())),
+        None
+    );
+    assert_eq!(
+        config.limit(&RateKey::Ip(localhost())),
+        Some(RateLimit::per_minute(120))
+    );
+}
+
+/// Serves a relayer whose node has no responses, so every request that gets past the rate
+/// limiter fails with an internal error.
+async fn serve(limits: RateLimitConfig) -> HttpRelayTransport {
+    let (provider, _mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(Address::repeat_byte(0x11)),
+        Arc::new(provider),
+    );
+    let server =
+        RelayServer::new(Relayer::new(forwarder)).with_rate_limiter(RateLimiter::new(limits));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server.serve(listener));
+    HttpRelayTransport::new(Provider::<Http>::try_from(url).unwrap())
+}
+
+async fn send(transport: &HttpRelayTransport, from: Address) -> TransportError {
+    match transport
+        .send(
+            increment(from),
+            vec![0; 65].into(),
+            MetaTransactionOptions::new(),
+        )
+        .await
+    {
+        Ok(_) => panic!("the relayer has no node to relay with"),
+        Err(e) => e,
+    }
+}
+
+#[tokio::test]
+async fn server_limits_signers_before_any_rpc() {
+    let transport = serve(RateLimitConfig {
+        signer: Some(RateLimit::per_minute(6).with_burst(1)),
+        ..Default::default()
+    })
+    .await;
+
+    assert!(matches!(
+        send(&transport, ALICE).await,
+        TransportError::Failed(_)
+    ));
+    let error = send(&transport, ALICE).await;
+    assert!(
+        matches!(
+            &error,
+            TransportError::RateLimited { retry_after, .. } if *retry_after == Duration::from_secs(10)
+        ),
+        "{error}"
+    );
+    assert!(matches!(
+        send(&transport, BOB).await,
+        TransportError::Failed(_)
+    ));
+}
+
+#[tokio::test]
+async fn server_limits_clients_by_ip() {
+    let transport = serve(RateLimitConfig {
+        ip: Some(RateLimit::per_minute(60).with_burst(1)),
+        ..Default::default()
+    })
+    .await;
+
+    assert!(matches!(
+        send(&transport, ALICE).await,
+        TransportError::Failed(_)
+    ));
+    let error = send(&transport, BOB).await;
+    assert!(
+        matches!(&error, TransportError::RateLimited { reason, .. } if reason.contains("127.0.0.1")),
+        "{error}"
+    );
+}
@@ -0,0 +1,205 @@
+use alloy::primitives::{address, Address};
+use counter_client::relayer::{
+    abi::forwarder::ExecuteReturn, convert, EscalatingTransaction, FeeStrategy, ForwardRequest,
+    ForwarderNonces, MetaTransactionReceipt, PendingMetaTransaction,
+};
+use ethers::{
+    abi::AbiEncode,
+    providers::{JsonRpcError, MockResponse, Provider},
+    types::{
+        transaction::eip2718::TypedTransaction, Bytes, Eip1559TransactionRequest,
+        TransactionReceipt, TransactionRequest, H256, U256,
+    },
+};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const COUNTER: Address = address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512");
+const FROM: Address = address!("70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+fn request() -> ForwardRequest {
+    ForwardRequest {
+        from: FROM,
+        to: COUNTER,
+        gas: alloy::primitives::U256::from(30_000),
+        nonce: alloy::primitives::U256::from(4),
+        ..Default::default()
+    }
+}
+
+fn execute_tx() -> TypedTransaction {
+    Eip1559TransactionRequest::new()
+        .from(ethers::types::Address::repeat_byte(0x42))
+        .to(convert::to_ethers_address(FORWARDER))
+        .gas(100_000)
+        .data(Bytes::from(vec![0xdf, 0x90, 0x5c, 0xaf]))
+        .max_fee_per_gas(20)
+        .max_priority_fee_per_gas(1)
+        .into()
+}
+
+fn mined(status: u64) -> TransactionReceipt {
+    TransactionReceipt {
+        transaction_hash: H256::repeat_byte(1),
+        block_number: Some(100.into()),
+        status: Some(status.into()),
+        ..Default::default()
+    }
+}
+
+fn nonce(value: u64) -> Bytes {
+    Bytes::from(U256::from(value).encode())
+}
+
+#[tokio::test]
+async fn reads_the_return_data_from_the_call_trace() {
+    let (provider, mock) = Provider::mocked();
+    let output = Bytes::from(ExecuteReturn(true, Bytes::from(U256::from(42).encode())).encode());
+
+    // Responses are popped last first.
+    mock.push::<Bytes, _>(nonce(5)).unwrap();
+    mock.push(serde_json::json!({
+        "type": "CALL",
+        "from": "0x4242424242424242424242424242424242424242",
+        "to": FORWARDER,
+        "gas": "0x186a0",
+        "gasUsed": "0xc350",
+        "input": "0xdf905caf",
+        "output": output,
+    }))
+    .unwrap();
+
+    let receipt =
+        MetaTransactionReceipt::recover(&provider, mined(1), &execute_tx(), FORWARDER, request())
+            .await
+            .unwrap();
+
+    assert!(receipt.success);
+    assert_eq!(receipt.decode_return::<U256>(), Some(U256::from(42)));
+    assert!(receipt.nonce_advanced);
+    assert_eq!(receipt.from(), FROM);
+    assert_eq!(receipt.nonce(), alloy::primitives::U256::from(4));
+    assert_eq!(receipt.gas(), alloy::primitives::U256::from(30_000));
+}
+
+#[tokio::test]
+async fn replays_the_call_without_a_debug_namespace() {
+    let (provider, mock) = Provider::mocked();
+    let output = Bytes::from(ExecuteReturn(true, Bytes::from(vec![7])).encode());
+
+    mock.push::<Bytes, _>(nonce(4)).unwrap();
+    mock.push::<Bytes, _>(output).unwrap();
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: -32601,
+        message: "the method debug_traceTransaction does not exist".to_string(),
+        data: None,
+    }));
+
+    let tx = execute_tx();
+    let receipt = MetaTransactionReceipt::recover(&provider, mined(1), &tx, FORWARDER, request())
+        .await
+        .unwrap();
+    assert!(receipt.success);
+    assert_eq!(receipt.return_data, Some(Bytes::from(vec![7])));
+    assert!(!receipt.nonce_advanced);
+
+    // Replayed on top of the previous block, without fees.
+    mock.assert_request(
+        "debug_traceTransaction",
+        (
+            H256::repeat_byte(1),
+            serde_json::json!({ "tracer": "callTracer" }),
+        ),
+    )
+    .unwrap();
+    let replay: TypedTransaction = TransactionRequest::new()
+        .from(*tx.from().unwrap())
+        .to(tx.to().unwrap().clone())
+        .gas(100_000)
+        .data(tx.data().unwrap().clone())
+        .into();
+    mock.assert_request(
+        "eth_call",
+        (
+            serde_json::to_value(replay).unwrap(),
+            serde_json::json!("0x63"),
+        ),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn a_reverted_execute_did_not_succeed() {
+    let (provider, mock) = Provider::mocked();
+    mock.push::<Bytes, _>(nonce(4)).unwrap();
+
+    let receipt =
+        MetaTransactionReceipt::recover(&provider, mined(0), &execute_tx(), FORWARDER, request())
+            .await
+            .unwrap();
+    assert!(!receipt.success);
+    assert_eq!(receipt.return_data, None);
+    assert!(!receipt.nonce_advanced);
+}
+
+#[tokio::test]
+async fn resyncs_the_nonce_of_a_request_the_forwarder_did_not_consume() {
+    let (provider, mock) = Provider::mocked();
+    let nonces = ForwarderNonces::new();
+
+    // The request was signed with nonce 4, so the next reservation would get 5.
+    mock.push::<Bytes, _>(nonce(4)).unwrap();
+    nonces
+        .reserve(&provider, FORWARDER, FROM)
+        .await
+        .unwrap()
+        .commit();
+
+    // `execute` reverted: its receipt, then `getNonce(from)` at its block.
+    mock.push::<Bytes, _>(nonce(4)).unwrap();
+    mock.push(mined(0)).unwrap();
+    let escalating = EscalatingTransaction::new(
+        &provider,
+        execute_tx(),
+        H256::repeat_byte(1),
+        FeeStrategy::default(),
+        None,
+        None,
+    );
+    let receipt = PendingMetaTransaction::new(escalating, FORWARDER, request())
+        .with_nonces(nonces.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!receipt.nonce_advanced);
+
+    // The forwarder is asked again.
+    mock.push::<Bytes, _>(nonce(4)).unwrap();
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), alloy::primitives::U256::from(4));
+    drop(reservation);
+
+    // Dropped: no receipt, and the gas wallet's nonce was used by another transaction.
+    mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+    mock.push(U256::from(8)).unwrap();
+    mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+    let mut dropped = execute_tx();
+    dropped.set_nonce(7);
+    let escalating = EscalatingTransaction::new(
+        &provider,
+        dropped,
+        H256::repeat_byte(1),
+        FeeStrategy::default(),
+        None,
+        None,
+    );
+    let receipt = PendingMetaTransaction::new(escalating, FORWARDER, request())
+        .with_nonces(nonces.clone())
+        .await
+        .unwrap();
+    assert_eq!(receipt, None);
+
+    // Another relayer's request took nonce 4 meanwhile.
+    mock.push::<Bytes, _>(nonce(5)).unwrap();
+    let reservation = nonces.reserve(&provider, FORWARDER, FROM).await.unwrap();
+    assert_eq!(reservation.nonce(), alloy::primitives::U256::from(5));
+}
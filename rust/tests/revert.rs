@@ -0,0 +1,134 @@
+use counter_client::relayer::{
+    abi::{self, counter_by_address::DefinitelyReverts},
+    revert::{self, NONCE_NOT_INCREASING, PANIC_SELECTOR, REVERTED_SILENTLY},
+    ForwarderRevert, RevertDecoder, RevertReason, TargetRevert,
+};
+use ethers::{
+    abi::{AbiEncode, AbiParser, Token},
+    contract::EthError,
+    providers::{JsonRpcError, Middleware, MockResponse, Provider},
+    types::{Bytes, TransactionRequest, U256},
+};
+
+fn revert_string(message: &str) -> Vec<u8> {
+    [String::selector().to_vec(), message.to_string().encode()].concat()
+}
+
+fn panic(code: u64) -> Vec<u8> {
+    [PANIC_SELECTOR.to_vec(), U256::from(code).encode()].concat()
+}
+
+#[test]
+fn tells_forwarder_failures_apart() {
+    let decoder = RevertDecoder::new();
+
+    assert_eq!(
+        decoder.decode_execute(&abi::forwarder::SignatureDoesNotMatch.encode()),
+        RevertReason::Forwarder(ForwarderRevert::SignatureDoesNotMatch)
+    );
+    assert_eq!(
+        decoder.decode_execute(&revert_string(NONCE_NOT_INCREASING)),
+        RevertReason::Forwarder(ForwarderRevert::NonceNotIncreasing)
+    );
+    let RevertReason::Forwarder(ForwarderRevert::Custom(error)) = decoder.decode_execute(
+        &abi::forwarder::ECDSAInvalidSignatureLength {
+            length: U256::from(64),
+        }
+        .encode(),
+    ) else {
+        panic!("not a forwarder error");
+    };
+    assert_eq!(
+        error.to_string(),
+        "Forwarder.ECDSAInvalidSignatureLength(40)"
+    );
+    // `assert(gasleft() > req.gas / 63)`
+    assert_eq!(
+        decoder.decode_execute(&panic(1)),
+        RevertReason::Forwarder(ForwarderRevert::Panic(U256::one()))
+    );
+}
+
+#[test]
+fn decodes_what_the_forwarder_re_raises() {
+    let decoder = RevertDecoder::new();
+
+    assert_eq!(
+        decoder.decode_execute(&revert_string("Only the owner")),
+        RevertReason::Target(TargetRevert::Message("Only the owner".to_string()))
+    );
+    assert_eq!(
+        decoder.decode_execute(&revert_string(REVERTED_SILENTLY)),
+        RevertReason::Target(TargetRevert::Silent)
+    );
+    assert_eq!(
+        decoder.decode_execute(&[]),
+        RevertReason::Unknown(Bytes::new())
+    );
+}
+
+#[test]
+fn decodes_target_custom_errors() {
+    let decoder = RevertDecoder::new();
+    let data = DefinitelyReverts.encode();
+
+    let RevertReason::Target(TargetRevert::Custom(error)) = decoder.decode_target(&data) else {
+        panic!("not a custom error");
+    };
+    assert_eq!(error.contract, "CounterByAddress");
+    assert_eq!(error.name, "DefinitelyReverts");
+    assert_eq!(error.to_string(), "CounterByAddress.DefinitelyReverts()");
+    assert_eq!(
+        DefinitelyReverts::decode_with_selector(&error.data),
+        Some(DefinitelyReverts)
+    );
+
+    assert_eq!(
+        decoder.decode_target(&panic(0x11)),
+        RevertReason::Target(TargetRevert::Panic(U256::from(0x11)))
+    );
+}
+
+#[test]
+fn decodes_registered_contracts() {
+    let abi = AbiParser::default()
+        .parse(&["error InsufficientBalance(uint256 available, uint256 required)"])
+        .unwrap();
+    let error = abi.error("InsufficientBalance").unwrap();
+    let data = error
+        .encode(&[Token::Uint(1.into()), Token::Uint(2.into())])
+        .unwrap();
+
+    assert!(matches!(
+        RevertDecoder::new().decode_target(&data),
+        RevertReason::Unknown(_)
+    ));
+
+    let decoder = RevertDecoder::new().with_contract("Token", abi);
+    let RevertReason::Target(TargetRevert::Custom(error)) = decoder.decode_target(&data) else {
+        panic!("not a custom error");
+    };
+    assert_eq!(error.to_string(), "Token.InsufficientBalance(1, 2)");
+}
+
+#[tokio::test]
+async fn extracts_revert_data_from_rpc_errors() {
+    let (provider, mock) = Provider::mocked();
+    let data = Bytes::from(DefinitelyReverts.encode());
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: 3,
+        message: "execution reverted".to_string(),
+        data: Some(serde_json::json!(data)),
+    }));
+    mock.push_response(MockResponse::Error(JsonRpcError {
+        code: -32000,
+        message: "insufficient funds for gas * price + value".to_string(),
+        data: None,
+    }));
+
+    let tx = TransactionRequest::new().into();
+    let e = provider.estimate_gas(&tx, None).await.unwrap_err();
+    assert_eq!(revert::revert_data(&e), None);
+    let e = provider.estimate_gas(&tx, None).await.unwrap_err();
+    assert_eq!(revert::revert_data(&e), Some(data));
+}
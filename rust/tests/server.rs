@@ -0,0 +1,352 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alloy::{
+    primitives::{address, Address, FixedBytes, B256, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolValue,
+};
+use async_trait::async_trait;
+use counter_client::{
+    config::{self, ProfileConfig},
+    relayer::{
+        abi::{self, forwarder::ExecuteReturn},
+        convert, forwarder_domain, ForwardRequest, QueueError, RelayError, RelayStatus, Relayer,
+        FORWARDER_NAME, FORWARDER_VERSION,
+    },
+    server::{
+        self, RelayServer, GET_STATUS, INTERNAL_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND,
+        REQUEST_REJECTED, SEND_FORWARD_REQUEST,
+    },
+};
+use ethers::{
+    abi::AbiEncode,
+    contract::EthCall,
+    middleware::SignerMiddleware,
+    providers::{Http, Middleware, MockProvider, Provider, ProviderError, RpcError},
+    types::{transaction::eip2718::TypedTransaction, BlockId, Bytes},
+};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+
+fn signer() -> PrivateKeySigner {
+    // anvil account #1
+    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+        .parse()
+        .unwrap()
+}
+
+fn sign(signer: &PrivateKeySigner, request: &ForwardRequest, forwarder: Address) -> Bytes {
+    let hash = request.eip712_signing_hash(&forwarder_domain(31337, forwarder));
+    Bytes::from(signer.sign_hash_sync(&hash).unwrap().as_bytes().to_vec())
+}
+
+fn mocked_server() -> (RelayServer<Provider<MockProvider>>, MockProvider) {
+    let (provider, mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), Arc::new(provider));
+    (RelayServer::new(Relayer::new(forwarder)), mock)
+}
+
+/// Serves `server` on an ephemeral port and returns a client for it.
+async fn serve<M: Middleware + 'static>(server: RelayServer<M>) -> Provider<Http> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server.serve(listener));
+    Provider::<Http>::try_from(url).unwrap()
+}
+
+/// The ABI-encoded return value of `eip712Domain()`.
+fn eip712_domain() -> Bytes {
+    (
+        FixedBytes::<1>::from([0x0f]),
+        FORWARDER_NAME.to_string(),
+        FORWARDER_VERSION.to_string(),
+        U256::from(31337),
+        FORWARDER,
+        FixedBytes::<32>::ZERO,
+        Vec::<U256>::new(),
+    )
+        .abi_encode_params()
+        .into()
+}
+
+/// A mocked node that records the `eth_call`s made through it.
+#[derive(Debug)]
+struct RecordingProvider {
+    inner: Provider<MockProvider>,
+    calls: Mutex<Vec<TypedTransaction>>,
+}
+
+#[async_trait]
+impl Middleware for RecordingProvider {
+    type Error = ProviderError;
+    type Provider = MockProvider;
+    type Inner = Provider<MockProvider>;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.inner
+    }
+
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, Self::Error> {
+        self.calls.lock().unwrap().push(tx.clone());
+        self.inner.call(tx, block).await
+    }
+}
+
+/// A mocked node whose gas wallet panics when it fills in a transaction, which only the
+/// submission task does.
+#[derive(Debug)]
+struct PanickingWallet(Provider<MockProvider>);
+
+#[async_trait]
+impl Middleware for PanickingWallet {
+    type Error = ProviderError;
+    type Provider = MockProvider;
+    type Inner = Provider<MockProvider>;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.0
+    }
+
+    async fn fill_transaction(
+        &self,
+        _tx: &mut TypedTransaction,
+        _block: Option<BlockId>,
+    ) -> Result<(), Self::Error> {
+        panic!("the gas wallet is broken")
+    }
+}
+
+fn error_code(error: ProviderError) -> i64 {
+    error.as_error_response().unwrap().code
+}
+
+#[tokio::test]
+async fn rejects_unknown_methods_and_bad_params() {
+    let (server, _mock) = mocked_server();
+    let client = serve(server).await;
+
+    let error = client
+        .request::<_, Value>("eth_sendTransaction", json!([]))
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(error), METHOD_NOT_FOUND);
+
+    let error = client
+        .request::<_, Value>(SEND_FORWARD_REQUEST, json!([{ "from": "0x01" }]))
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(error), INVALID_PARAMS);
+}
+
+#[tokio::test]
+async fn unknown_ids_have_no_status() {
+    let (server, _mock) = mocked_server();
+    let client = serve(server).await;
+
+    let status: Option<RelayStatus> = client
+        .request(GET_STATUS, [B256::repeat_byte(0x11)])
+        .await
+        .unwrap();
+    assert_eq!(status, None);
+}
+
+#[tokio::test]
+async fn rejects_a_request_signed_by_someone_else() {
+    let (server, mock) = mocked_server();
+
+    // Responses are popped last in, first out: the chain id and domain for the request's id, then
+    // the chain id and `getNonce(from)` for verifying it.
+    mock.push::<Bytes, _>(Bytes::from(ethers::types::U256::zero().encode()))
+        .unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    let client = serve(server).await;
+
+    let request = ForwardRequest {
+        from: address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC"),
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(100_000),
+        nonce: U256::ZERO,
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    };
+    let signature = sign(&signer(), &request, FORWARDER);
+
+    let error = client
+        .request::<_, B256>(SEND_FORWARD_REQUEST, (request, signature))
+        .await
+        .unwrap_err();
+    let response = error.as_error_response().unwrap();
+    assert_eq!(response.code, REQUEST_REJECTED);
+    assert!(
+        response.message.contains("Wrong signer"),
+        "{}",
+        response.message
+    );
+}
+
+#[tokio::test]
+async fn sends_the_requests_value_with_execute() {
+    let (provider, mock) = Provider::mocked();
+    let provider = Arc::new(RecordingProvider {
+        inner: provider,
+        calls: Mutex::new(Vec::new()),
+    });
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), provider.clone());
+    let server = RelayServer::new(Relayer::new(forwarder));
+
+    // Last in, first out: the chain id and domain for the request's id, then the chain id,
+    // `getNonce(from)` and the gas estimate for verifying it. The preflight of `execute` then
+    // finds the node out of responses.
+    mock.push(ethers::types::U256::from(30_000)).unwrap();
+    mock.push::<Bytes, _>(Bytes::from(ethers::types::U256::zero().encode()))
+        .unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+
+    let signer = signer();
+    let request = ForwardRequest {
+        from: signer.address(),
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::from(1_000_000),
+        gas: U256::from(100_000),
+        nonce: U256::ZERO,
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    };
+    let signature = sign(&signer, &request, FORWARDER);
+    assert!(server
+        .send_forward_request(request, signature)
+        .await
+        .is_err());
+
+    let calls = provider.calls.lock().unwrap();
+    let execute = calls
+        .iter()
+        .find(|tx| {
+            tx.data()
+                .is_some_and(|data| data.starts_with(&abi::forwarder::ExecuteCall::selector()))
+        })
+        .expect("execute was simulated");
+    assert_eq!(execute.value(), Some(&ethers::types::U256::from(1_000_000)));
+}
+
+#[tokio::test]
+async fn reports_a_submission_task_that_panicked() {
+    let (provider, mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(FORWARDER),
+        Arc::new(PanickingWallet(provider)),
+    );
+    let server = RelayServer::new(Relayer::new(forwarder));
+
+    // Last in, first out: the chain id and domain for the request's id, the chain id,
+    // `getNonce(from)` and the gas estimate for verifying it, and the preflight of `execute`.
+    mock.push::<Bytes, _>(Bytes::from(ExecuteReturn(true, Bytes::new()).encode()))
+        .unwrap();
+    mock.push(ethers::types::U256::from(30_000)).unwrap();
+    mock.push::<Bytes, _>(Bytes::from(ethers::types::U256::zero().encode()))
+        .unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+
+    let signer = signer();
+    let request = ForwardRequest {
+        from: signer.address(),
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(100_000),
+        nonce: U256::ZERO,
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    };
+    let signature = sign(&signer, &request, FORWARDER);
+    let error = server
+        .send_forward_request(request.clone(), signature)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        RelayError::Queue(QueueError::SubmissionLost)
+    ));
+    assert_eq!(server::error_code(&error), INTERNAL_ERROR);
+
+    // The request failed, so the client may send it again.
+    let id = request.eip712_signing_hash(&forwarder_domain(31337, FORWARDER));
+    assert!(matches!(
+        server.status(id).unwrap(),
+        Some(RelayStatus::Failed { .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs anvil with the contracts deployed by scripts/deploy.ts"]
+async fn relays_a_request_end_to_end() {
+    let profile = config::load(
+        Some(Path::new("counter-client.toml")),
+        Some("local"),
+        ProfileConfig::default(),
+    )
+    .await
+    .unwrap();
+    let gas_client = SignerMiddleware::new(profile.provider.clone(), profile.gas_wallet().unwrap());
+    let forwarder = abi::Forwarder::new(profile.forwarder().unwrap(), Arc::new(gas_client));
+    let counter = abi::CounterByAddress::new(
+        profile.counter().unwrap(),
+        Arc::new(profile.provider.clone()),
+    );
+    let client = serve(RelayServer::new(Relayer::new(forwarder.clone()))).await;
+
+    let signer = signer();
+    let from = convert::to_ethers_address(signer.address());
+    let before = counter.get_counter(from).call().await.unwrap();
+    let request = ForwardRequest {
+        from: signer.address(),
+        to: convert::to_alloy_address(counter.address()),
+        value: U256::ZERO,
+        gas: U256::from(100_000),
+        nonce: convert::to_alloy_u256(forwarder.get_nonce(from).call().await.unwrap()),
+        data: convert::to_alloy_bytes(counter.increment().calldata().unwrap()),
+    };
+    let signature = sign(
+        &signer,
+        &request,
+        convert::to_alloy_address(forwarder.address()),
+    );
+
+    let id: B256 = client
+        .request(SEND_FORWARD_REQUEST, (request.clone(), signature.clone()))
+        .await
+        .unwrap();
+    // Sending it again is a no-op.
+    let again: B256 = client
+        .request(SEND_FORWARD_REQUEST, (request, signature))
+        .await
+        .unwrap();
+    assert_eq!(again, id);
+
+    let status = loop {
+        let status: Option<RelayStatus> = client.request(GET_STATUS, [id]).await.unwrap();
+        match status.unwrap() {
+            RelayStatus::Pending { .. } => tokio::time::sleep(Duration::from_millis(200)).await,
+            status => break status,
+        }
+    };
+    let RelayStatus::Mined(receipt) = status else {
+        panic!("request failed: {status:?}");
+    };
+    assert!(receipt.success);
+    assert!(receipt.nonce_advanced);
+    assert_eq!(counter.get_counter(from).call().await.unwrap(), before + 1);
+}
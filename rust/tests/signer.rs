@@ -0,0 +1,126 @@
+use alloy::{
+    primitives::{address, Bytes, U256},
+    signers::local::PrivateKeySigner,
+};
+use counter_client::relayer::{
+    forwarder_domain, AlloyMetaSigner, EthersMetaSigner, ForwardRequest, MetaSigner,
+    MetaSignerError, NodeMetaSigner,
+};
+use ethers::{providers::Provider, signers::LocalWallet};
+
+// anvil account #1
+const PRIVATE_KEY: &str = "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+
+fn request(from: alloy::primitives::Address) -> ForwardRequest {
+    ForwardRequest {
+        from,
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(30000),
+        nonce: U256::from(7),
+        data: Bytes::from(vec![0xd0, 0x9d, 0xe0, 0x8a]),
+    }
+}
+
+#[tokio::test]
+async fn adapters_agree_on_signature() {
+    let alloy_signer: PrivateKeySigner = PRIVATE_KEY.parse().unwrap();
+    let ethers_wallet: LocalWallet = PRIVATE_KEY.parse().unwrap();
+
+    let signers: Vec<Box<dyn MetaSigner>> = vec![
+        Box::new(alloy_signer.clone()),
+        Box::new(ethers_wallet.clone()),
+        Box::new(AlloyMetaSigner(alloy_signer.clone())),
+        Box::new(EthersMetaSigner(ethers_wallet)),
+    ];
+
+    let address = alloy_signer.address();
+    let request = request(address);
+    let domain = forwarder_domain(31337, address!("5FbDB2315678afecb367f032d93F642f64180aa3"));
+    let hash = request.eip712_signing_hash(&domain);
+
+    let mut signatures = Vec::new();
+    for signer in &signers {
+        assert_eq!(signer.address(), address);
+
+        let signature = signer
+            .sign_forward_request(&request, &domain)
+            .await
+            .unwrap();
+        assert_eq!(
+            signature.recover_address_from_prehash(&hash).unwrap(),
+            address
+        );
+        signatures.push(signature);
+    }
+
+    assert!(signatures.windows(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[tokio::test]
+async fn node_signer_asks_for_typed_data_v4() {
+    let signer: PrivateKeySigner = PRIVATE_KEY.parse().unwrap();
+    let request = request(signer.address());
+    let domain = forwarder_domain(31337, address!("5FbDB2315678afecb367f032d93F642f64180aa3"));
+    let expected = signer
+        .sign_forward_request(&request, &domain)
+        .await
+        .unwrap();
+
+    // The mock answers last in first out: the signature, then one that is too short.
+    let (provider, mock) = Provider::mocked();
+    let too_short = ethers::types::Bytes::from(vec![0x01; 64]);
+    let signature = ethers::types::Bytes::from(expected.as_bytes().to_vec());
+    mock.push::<ethers::types::Bytes, _>(&too_short).unwrap();
+    mock.push::<ethers::types::Bytes, _>(&signature).unwrap();
+    let node = NodeMetaSigner::new(provider, signer.address());
+    assert_eq!(node.address(), signer.address());
+
+    let signature = node.sign_forward_request(&request, &domain).await.unwrap();
+    assert_eq!(signature, expected);
+    mock.assert_request(
+        "eth_signTypedData_v4",
+        serde_json::json!([
+            "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            {
+                "types": {
+                    "EIP712Domain": [
+                        { "name": "name", "type": "string" },
+                        { "name": "version", "type": "string" },
+                        { "name": "chainId", "type": "uint256" },
+                        { "name": "verifyingContract", "type": "address" },
+                    ],
+                    "ForwardRequest": [
+                        { "name": "from", "type": "address" },
+                        { "name": "to", "type": "address" },
+                        { "name": "value", "type": "uint256" },
+                        { "name": "gas", "type": "uint256" },
+                        { "name": "nonce", "type": "uint256" },
+                        { "name": "data", "type": "bytes" },
+                    ],
+                },
+                "primaryType": "ForwardRequest",
+                "domain": {
+                    "name": "GSNv2 Forwarder",
+                    "version": "0.0.1",
+                    "chainId": "0x7a69",
+                    "verifyingContract": "0x5fbdb2315678afecb367f032d93f642f64180aa3",
+                },
+                "message": {
+                    "from": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+                    "to": "0xe7f1725e7734ce288f8367e1bb143e90bb3f0512",
+                    "value": "0x0",
+                    "gas": "0x7530",
+                    "nonce": "0x7",
+                    "data": "0xd09de08a",
+                },
+            },
+        ]),
+    )
+    .unwrap();
+
+    assert!(matches!(
+        node.sign_forward_request(&request, &domain).await,
+        Err(MetaSignerError::InvalidSignature(_))
+    ));
+}
@@ -0,0 +1,85 @@
+use counter_client::relayer::OuterTransactionType;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, transaction::eip2930::AccessList, Address, Bytes,
+    Eip1559TransactionRequest, TransactionRequest, U256,
+};
+
+fn execute_call() -> TypedTransaction {
+    Eip1559TransactionRequest::new()
+        .from(Address::repeat_byte(0x42))
+        .to(Address::repeat_byte(0x43))
+        .gas(120_000)
+        .value(0)
+        .data(Bytes::from(vec![0xde, 0xad]))
+        .nonce(3)
+        .chain_id(31337)
+        .max_fee_per_gas(20)
+        .into()
+}
+
+fn assert_same_call(tx: &TypedTransaction) {
+    assert_eq!(tx.from(), Some(&Address::repeat_byte(0x42)));
+    assert_eq!(
+        tx.to().and_then(|to| to.as_address()),
+        Some(&Address::repeat_byte(0x43))
+    );
+    assert_eq!(tx.gas(), Some(&U256::from(120_000)));
+    assert_eq!(tx.value(), Some(&U256::zero()));
+    assert_eq!(tx.data(), Some(&Bytes::from(vec![0xde, 0xad])));
+    assert_eq!(tx.nonce(), Some(&U256::from(3)));
+    assert_eq!(tx.chain_id(), Some(31337.into()));
+}
+
+#[test]
+fn converts_to_legacy() {
+    let tx = OuterTransactionType::Legacy.convert(execute_call());
+    assert!(matches!(tx, TypedTransaction::Legacy(_)));
+    assert_same_call(&tx);
+    // Fees are priced again for the new type.
+    assert_eq!(tx.gas_price(), None);
+}
+
+#[test]
+fn converts_to_eip2930_and_back() {
+    let tx = OuterTransactionType::Eip2930.convert(execute_call());
+    assert!(matches!(tx, TypedTransaction::Eip2930(_)));
+    assert_eq!(tx.access_list(), Some(&AccessList::default()));
+    assert_same_call(&tx);
+
+    let tx = OuterTransactionType::Eip1559.convert(tx);
+    let TypedTransaction::Eip1559(eip1559) = &tx else {
+        unreachable!()
+    };
+    assert_eq!(eip1559.max_fee_per_gas, None);
+    assert_same_call(&tx);
+}
+
+#[test]
+fn parses_from_config() {
+    #[derive(serde::Deserialize)]
+    struct Profile {
+        transaction_type: OuterTransactionType,
+    }
+
+    for (name, expected) in [
+        ("legacy", OuterTransactionType::Legacy),
+        ("eip2930", OuterTransactionType::Eip2930),
+        ("eip1559", OuterTransactionType::Eip1559),
+    ] {
+        let profile: Profile = toml::from_str(&format!("transaction_type = \"{name}\"")).unwrap();
+        assert_eq!(profile.transaction_type, expected);
+    }
+    assert!(toml::from_str::<Profile>("transaction_type = \"1559\"").is_err());
+}
+
+#[test]
+fn legacy_input_keeps_its_call() {
+    let legacy: TypedTransaction = TransactionRequest::new()
+        .to(Address::repeat_byte(0x43))
+        .data(Bytes::from(vec![0xde, 0xad]))
+        .gas_price(7)
+        .into();
+    let tx = OuterTransactionType::Eip1559.convert(legacy);
+    assert_eq!(tx.data(), Some(&Bytes::from(vec![0xde, 0xad])));
+    assert_eq!(tx.gas_price(), None);
+}
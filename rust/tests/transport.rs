@@ -0,0 +1,300 @@
+use std::{sync::Arc, time::Duration};
+
+use alloy::{
+    primitives::{address, Address, FixedBytes, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolValue,
+};
+use counter_client::{
+    relayer::{
+        abi, budget::unix_time, convert, forwarder_domain, AllowRule, Allowlist, Budget,
+        BudgetError, EIP2771GasRelayerMiddleware, EIP2771GasRelayerMiddlewareError, ForwardRequest,
+        ForwardRequestVerifier, HttpRelayTransport, MetaTransactionOptions, MockRelayTransport,
+        PolicyError, RelayTransport, Relayer, SpendLedger, SpendRecord, TransportError,
+        VerifyError, FORWARDER_NAME, FORWARDER_VERSION,
+    },
+    server::RelayServer,
+};
+use ethers::{
+    abi::AbiEncode,
+    providers::{Http, MockProvider, Provider},
+    types::{Bytes, Eip1559TransactionRequest},
+};
+use tokio::net::TcpListener;
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+const COUNTER: Address = address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512");
+
+fn signer() -> PrivateKeySigner {
+    // anvil account #1
+    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+        .parse()
+        .unwrap()
+}
+
+/// The ABI-encoded return value of `eip712Domain()`.
+fn eip712_domain() -> Bytes {
+    (
+        FixedBytes::<1>::from([0x0f]),
+        FORWARDER_NAME.to_string(),
+        FORWARDER_VERSION.to_string(),
+        U256::from(31337),
+        FORWARDER,
+        FixedBytes::<32>::ZERO,
+        Vec::<U256>::new(),
+    )
+        .abi_encode_params()
+        .into()
+}
+
+fn push_word(mock: &MockProvider, word: u64) {
+    mock.push::<Bytes, _>(Bytes::from(ethers::types::U256::from(word).encode()))
+        .unwrap();
+}
+
+fn increment() -> Eip1559TransactionRequest {
+    Eip1559TransactionRequest::new()
+        .to(convert::to_ethers_address(COUNTER))
+        .data(Bytes::from(vec![0xd0, 0x9d, 0xe0, 0x8a]))
+        .chain_id(31337)
+}
+
+/// A middleware that signs with anvil account #1 and delivers to `transport`. Its node answers,
+/// last in first out, `getNonce(from)`, the inner gas estimate, the forwarder's domain and
+/// `getNonce(from)` again for the verification.
+fn middleware(
+    transport: MockRelayTransport,
+) -> (
+    EIP2771GasRelayerMiddleware<Provider<MockProvider>>,
+    MockProvider,
+) {
+    let (provider, mock) = Provider::mocked();
+    push_word(&mock, 7);
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(21_000)).unwrap();
+    push_word(&mock, 7);
+
+    let (gas_provider, _) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(
+        convert::to_ethers_address(FORWARDER),
+        Arc::new(gas_provider),
+    );
+    let middleware =
+        EIP2771GasRelayerMiddleware::new(provider, signer(), forwarder).with_transport(transport);
+    (middleware, mock)
+}
+
+#[tokio::test]
+async fn signs_requests_and_hands_them_to_the_transport() {
+    let transport = MockRelayTransport::new();
+    let (middleware, mock) = middleware(transport.clone());
+
+    let relayed = middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(relayed.tx_hash(), ethers::types::H256::from_low_u64_be(1));
+    assert_eq!(relayed.await.unwrap(), None);
+
+    // The nonce and domain are known now, so only the gas is estimated again before the
+    // verification reads the forwarder's nonce.
+    push_word(&mock, 8);
+    mock.push(ethers::types::U256::from(21_000)).unwrap();
+    middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap();
+
+    let verifier = ForwardRequestVerifier::new(forwarder_domain(31337, FORWARDER));
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    for ((request, signature), nonce) in requests.iter().zip([7, 8]) {
+        assert_eq!(request.from, signer().address());
+        assert_eq!(request.to, COUNTER);
+        assert_eq!(request.nonce, U256::from(nonce));
+        verifier
+            .verify(request, signature, U256::from(nonce))
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn rereads_a_nonce_the_forwarder_disagrees_with() {
+    let transport = MockRelayTransport::new();
+    let (middleware, mock) = middleware(transport.clone());
+    middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap();
+
+    // The request with nonce 7 was dropped, so the forwarder still expects 7 rather than 8.
+    push_word(&mock, 7);
+    mock.push(ethers::types::U256::from(21_000)).unwrap();
+    let error = middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        EIP2771GasRelayerMiddlewareError::VerificationFailed(VerifyError::NonceMismatch {
+            expected,
+            got,
+        }) if expected == U256::from(7) && got == U256::from(8)
+    ));
+
+    // The next send reads the nonce from the forwarder again.
+    push_word(&mock, 7);
+    mock.push(ethers::types::U256::from(21_000)).unwrap();
+    push_word(&mock, 7);
+    middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap();
+    let nonces: Vec<_> = transport
+        .requests()
+        .iter()
+        .map(|(request, _)| request.nonce)
+        .collect();
+    assert_eq!(nonces, vec![U256::from(7), U256::from(7)]);
+}
+
+#[tokio::test]
+async fn reports_transport_errors() {
+    let transport = MockRelayTransport::new();
+    transport.push_error(TransportError::Rejected("not allowed".to_string()));
+    let (middleware, _mock) = middleware(transport.clone());
+
+    let error = middleware
+        .send_meta_transaction(increment(), MetaTransactionOptions::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        EIP2771GasRelayerMiddlewareError::Transport(TransportError::Rejected(reason))
+            if reason == "not allowed"
+    ));
+    assert!(transport.requests().is_empty());
+}
+
+#[tokio::test]
+async fn http_transport_reports_rejections() {
+    let (provider, mock) = Provider::mocked();
+    // The chain id and domain for the request's id, then the chain id and `getNonce(from)` for
+    // verifying it.
+    push_word(&mock, 0);
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    mock.push::<Bytes, _>(eip712_domain()).unwrap();
+    mock.push(ethers::types::U256::from(31337)).unwrap();
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), Arc::new(provider));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(RelayServer::new(Relayer::new(forwarder)).serve(listener));
+    let transport = HttpRelayTransport::new(Provider::<Http>::try_from(url).unwrap());
+
+    // Signed by account #1 on behalf of account #2.
+    let request = ForwardRequest {
+        from: address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC"),
+        to: COUNTER,
+        value: U256::ZERO,
+        gas: U256::from(100_000),
+        nonce: U256::ZERO,
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    };
+    let hash = request.eip712_signing_hash(&forwarder_domain(31337, FORWARDER));
+    let signature = signer().sign_hash_sync(&hash).unwrap();
+
+    let error = transport
+        .send(
+            request,
+            Bytes::from(signature.as_bytes().to_vec()),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, TransportError::Rejected(reason) if reason.contains("Wrong signer")),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn http_transport_keeps_policy_and_budget_rejections_typed() {
+    let (provider, _mock) = Provider::mocked();
+    let forwarder = abi::Forwarder::new(convert::to_ethers_address(FORWARDER), Arc::new(provider));
+
+    // The signer has spent its whole budget; the allowlist only allows `increment()`.
+    let from = address!("3C44CdDdB6a900fa2b585dd299e03d12FA4293BC");
+    let ledger = SpendLedger::in_memory();
+    ledger
+        .record(SpendRecord {
+            from,
+            target: COUNTER,
+            tx_hash: ethers::types::H256::zero(),
+            wei: U256::from(100),
+            timestamp: unix_time(),
+        })
+        .unwrap();
+    let relayer = Relayer::new(forwarder)
+        .with_allowlist(Allowlist::new(vec![AllowRule::function(
+            "increment",
+            COUNTER,
+            "increment()",
+        )]))
+        .with_budget(Budget::new(
+            U256::from(100),
+            Duration::from_secs(60),
+            ledger,
+        ));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(RelayServer::new(relayer).serve(listener));
+    let transport = HttpRelayTransport::new(Provider::<Http>::try_from(url).unwrap());
+
+    let request = ForwardRequest {
+        from,
+        to: COUNTER,
+        value: U256::ZERO,
+        gas: U256::from(100_000),
+        nonce: U256::ZERO,
+        data: vec![0xd0, 0x9d, 0xe0, 0x8a].into(),
+    };
+    let other = address!("9fE46736679d2D9a65F0992F2272dE9f3c7fa6e0");
+    let error = transport
+        .send(
+            ForwardRequest {
+                to: other,
+                ..request.clone()
+            },
+            Bytes::from(vec![0; 65]),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            TransportError::PolicyRejected(PolicyError::NotAllowed { target, selector: Some(_) })
+                if *target == other
+        ),
+        "{error}"
+    );
+
+    let error = transport
+        .send(
+            request,
+            Bytes::from(vec![0; 65]),
+            MetaTransactionOptions::new(),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            TransportError::OverBudget(BudgetError::Exhausted { from: signer, spent, max, window_secs: 60 })
+                if *signer == from && *spent == U256::from(100) && *max == U256::from(100)
+        ),
+        "{error}"
+    );
+}
@@ -0,0 +1,51 @@
+//! Checks the artifacts vendored in `rust/abi` against a fresh Hardhat build.
+//!
+//! The comparison only runs when `../blockchain/artifacts` exists (i.e. after `npx hardhat
+//! compile`). Set `COUNTER_CLIENT_REQUIRE_ARTIFACTS=1` to fail instead of skipping when it does
+//! not, e.g. in a CI job that compiles the contracts.
+
+use std::path::Path;
+
+use alloy::json_abi::JsonAbi;
+use counter_client::relayer::abi;
+use serde_json::Value;
+
+fn check_artifact(contract: &str, vendored: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!(
+        "../blockchain/artifacts/contracts/{contract}.sol/{contract}.json"
+    ));
+    if !path.exists() {
+        if std::env::var_os("COUNTER_CLIENT_REQUIRE_ARTIFACTS").is_some() {
+            panic!("{} does not exist", path.display());
+        }
+        eprintln!("skipping {contract}: {} does not exist", path.display());
+        return;
+    }
+
+    let compiled: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let vendored: Value = serde_json::from_str(vendored).unwrap();
+
+    let compiled_abi: JsonAbi = serde_json::from_value(compiled["abi"].clone()).unwrap();
+    let vendored_abi: JsonAbi = serde_json::from_value(vendored["abi"].clone()).unwrap();
+    assert_eq!(
+        vendored_abi, compiled_abi,
+        "vendored {contract} ABI is out of date, run scripts/sync-abi.sh"
+    );
+
+    for field in ["bytecode", "deployedBytecode"] {
+        assert_eq!(
+            vendored[field], compiled[field],
+            "vendored {contract} {field} is out of date, run scripts/sync-abi.sh"
+        );
+    }
+}
+
+#[test]
+fn forwarder_artifact_is_up_to_date() {
+    check_artifact("Forwarder", abi::FORWARDER_ARTIFACT);
+}
+
+#[test]
+fn counter_by_address_artifact_is_up_to_date() {
+    check_artifact("CounterByAddress", abi::COUNTER_BY_ADDRESS_ARTIFACT);
+}
@@ -0,0 +1,185 @@
+use alloy::{
+    primitives::{address, keccak256, Address, Bytes, U256},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::SolStruct,
+};
+use counter_client::relayer::{
+    alloy_structs, forward_request_digest, forward_request_struct_hash, forward_request_typehash,
+    forwarder_domain, ForwardRequest, ForwardRequestVerifier, VerifyError,
+};
+
+const FORWARDER: Address = address!("5FbDB2315678afecb367f032d93F642f64180aa3");
+
+fn signer() -> PrivateKeySigner {
+    // anvil account #1
+    "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+        .parse()
+        .unwrap()
+}
+
+fn request(from: Address) -> ForwardRequest {
+    ForwardRequest {
+        from,
+        to: address!("e7f1725E7734CE288F8367e1Bb143E90bb3F0512"),
+        value: U256::ZERO,
+        gas: U256::from(30000),
+        nonce: U256::from(3),
+        data: Bytes::from(vec![0xd0, 0x9d, 0xe0, 0x8a]),
+    }
+}
+
+fn sign(signer: &PrivateKeySigner, request: &ForwardRequest, chain_id: u64) -> Vec<u8> {
+    let hash = request.eip712_signing_hash(&forwarder_domain(chain_id, FORWARDER));
+    signer.sign_hash_sync(&hash).unwrap().as_bytes().to_vec()
+}
+
+#[test]
+fn hashes_match_the_contract() {
+    let request = request(signer().address());
+    let domain = forwarder_domain(11155111, FORWARDER);
+    let alloy_request = alloy_structs::ForwardRequest::from(request.clone());
+
+    assert_eq!(
+        forward_request_typehash(),
+        keccak256(alloy_structs::ForwardRequest::eip712_encode_type().as_bytes())
+    );
+    assert_eq!(
+        forward_request_struct_hash(&request),
+        alloy_request.eip712_hash_struct()
+    );
+    assert_eq!(
+        forward_request_digest(&request, &domain),
+        request.eip712_signing_hash(&domain)
+    );
+}
+
+#[test]
+fn accepts_a_valid_request() {
+    let signer = signer();
+    let request = request(signer.address());
+    let signature = sign(&signer, &request, 11155111);
+
+    ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER))
+        .verify(&request, &signature, U256::from(3))
+        .unwrap();
+}
+
+#[test]
+fn diagnoses_a_wrong_signer() {
+    let signer = signer();
+    let other = PrivateKeySigner::random();
+    let request = request(signer.address());
+    let signature = sign(&other, &request, 11155111);
+
+    assert_eq!(
+        ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER)).verify(
+            &request,
+            &signature,
+            U256::from(3)
+        ),
+        Err(VerifyError::WrongSigner {
+            expected: signer.address(),
+            recovered: other.address(),
+        })
+    );
+}
+
+#[test]
+fn diagnoses_a_hard_coded_chain_id() {
+    let signer = signer();
+    let request = request(signer.address());
+    let signature = sign(&signer, &request, 31337);
+
+    assert_eq!(
+        ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER)).verify(
+            &request,
+            &signature,
+            U256::from(3)
+        ),
+        Err(VerifyError::WrongDomain {
+            from: signer.address(),
+            chain_id: Some(11155111),
+            forwarder: Some(FORWARDER),
+            signed_chain_id: Some(31337),
+            signed_forwarder: Some(FORWARDER),
+        })
+    );
+}
+
+#[test]
+fn diagnoses_a_custom_candidate_domain() {
+    let signer = signer();
+    let request = request(signer.address());
+    let other_forwarder = Address::repeat_byte(0x42);
+    let hash = request.eip712_signing_hash(&forwarder_domain(11155111, other_forwarder));
+    let signature = signer.sign_hash_sync(&hash).unwrap().as_bytes();
+
+    let verifier = ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER));
+    assert!(matches!(
+        verifier.verify_signature(&request, &signature),
+        Err(VerifyError::WrongSigner { .. })
+    ));
+    assert!(matches!(
+        verifier
+            .with_candidate(forwarder_domain(11155111, other_forwarder))
+            .verify_signature(&request, &signature),
+        Err(VerifyError::WrongDomain {
+            signed_forwarder: Some(forwarder),
+            ..
+        }) if forwarder == other_forwarder
+    ));
+}
+
+#[test]
+fn diagnoses_a_stale_nonce() {
+    let signer = signer();
+    let request = request(signer.address());
+    let signature = sign(&signer, &request, 11155111);
+
+    assert_eq!(
+        ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER)).verify(
+            &request,
+            &signature,
+            U256::from(4)
+        ),
+        Err(VerifyError::NonceMismatch {
+            expected: U256::from(4),
+            got: U256::from(3),
+        })
+    );
+}
+
+#[test]
+fn rejects_what_ecdsa_recover_rejects() {
+    let signer = signer();
+    let request = request(signer.address());
+    let verifier = ForwardRequestVerifier::new(forwarder_domain(11155111, FORWARDER));
+    let signature = sign(&signer, &request, 11155111);
+
+    // Truncated
+    assert!(matches!(
+        verifier.verify_signature(&request, &signature[..64]),
+        Err(VerifyError::MalformedSignature(_))
+    ));
+
+    // v of 0/1 instead of 27/28
+    let mut raw_v = signature.clone();
+    raw_v[64] -= 27;
+    assert!(matches!(
+        verifier.verify_signature(&request, &raw_v),
+        Err(VerifyError::MalformedSignature(_))
+    ));
+
+    // The malleable (r, n - s, v ^ 1) twin of a valid signature
+    let n = U256::from_be_bytes(alloy::primitives::hex!(
+        "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"
+    ));
+    let s = U256::from_be_slice(&signature[32..64]);
+    let mut malleable = signature.clone();
+    malleable[32..64].copy_from_slice(&(n - s).to_be_bytes::<32>());
+    malleable[64] = 27 + 28 - malleable[64];
+    assert!(matches!(
+        verifier.verify_signature(&request, &malleable),
+        Err(VerifyError::MalformedSignature(_))
+    ));
+}